@@ -2,8 +2,11 @@
 // Still simplified but supports multiple heads with minimal allocations.
 use std::f32;
 
+use crate::kv_cache::LayerKv;
+
 /// Computes scaled dot-product attention for a single head.
 /// `q`, `k`, `v` are matrices of shape (seq_len x dim).
+#[allow(dead_code)]
 fn scaled_dot_product_attention(q: &[Vec<f32>], k: &[Vec<f32>], v: &[Vec<f32>]) -> Vec<Vec<f32>> {
     let seq_len = q.len();
     let dim = q[0].len() as f32;
@@ -41,25 +44,36 @@ fn scaled_dot_product_attention(q: &[Vec<f32>], k: &[Vec<f32>], v: &[Vec<f32>])
 }
 
 /// Computes multi-head scaled dot-product attention.
-/// `q`, `k`, `v` are matrices of shape (seq_len x embed_dim).
-/// `num_heads` must divide `embed_dim`.
+/// `q` has shape (seq_len x embed_dim) while `k` and `v` have shape
+/// (kv_len x embed_dim). Query `i` sits at absolute position `offset + i` and
+/// attends to keys `0..=offset + i`, so `kv_len` must be at least
+/// `offset + seq_len`. `num_heads` must divide `embed_dim`.
+/// When `weights` is provided it receives the softmax probabilities with shape
+/// (num_heads x seq_len x kv_len); masked positions are zero.
 fn multi_head_attention(
     q: &[Vec<f32>],
     k: &[Vec<f32>],
     v: &[Vec<f32>],
     num_heads: usize,
+    offset: usize,
+    mut weights: Option<&mut Vec<Vec<Vec<f32>>>>,
 ) -> Vec<Vec<f32>> {
     let seq_len = q.len();
+    let kv_len = k.len();
     let dim = q[0].len();
     let head_dim = dim / num_heads;
     let mut output = vec![vec![0.0f32; dim]; seq_len];
-    let mut scores = vec![0.0f32; seq_len];
+    let mut scores = vec![0.0f32; kv_len];
     let scale = (head_dim as f32).sqrt();
+    if let Some(w) = weights.as_mut() {
+        **w = vec![vec![vec![0.0f32; kv_len]; seq_len]; num_heads];
+    }
 
     for i in 0..seq_len {
+        let pos = offset + i;
         for h in 0..num_heads {
             scores.iter_mut().for_each(|s| *s = 0.0);
-            for j in 0..=i {
+            for j in 0..=pos {
                 let mut dot = 0.0f32;
                 for d in 0..head_dim {
                     dot += q[i][h * head_dim + d] * k[j][h * head_dim + d];
@@ -71,12 +85,15 @@ fn multi_head_attention(
                 .iter()
                 .map(|s| if *s != 0.0 { (*s - max_score).exp() } else { 0.0 })
                 .sum();
-            for j in 0..=i {
+            for j in 0..=pos {
                 let weight = if exp_sum > 0.0 {
                     (scores[j] - max_score).exp() / exp_sum
                 } else {
                     0.0
                 };
+                if let Some(w) = weights.as_mut() {
+                    w[h][i][j] = weight;
+                }
                 for d in 0..head_dim {
                     output[i][h * head_dim + d] += weight * v[j][h * head_dim + d];
                 }
@@ -97,7 +114,7 @@ pub struct MultiHeadAttention {
 
 impl MultiHeadAttention {
     pub fn new(embed_dim: usize, num_heads: usize) -> Self {
-        assert!(embed_dim % num_heads == 0);
+        assert!(embed_dim.is_multiple_of(num_heads));
         // initialize with identity weights for simplicity
         let identity = (0..embed_dim)
             .map(|i| {
@@ -120,7 +137,68 @@ impl MultiHeadAttention {
         let q = self.w_q.forward(input);
         let k = self.w_k.forward(input);
         let v = self.w_v.forward(input);
-        let context = multi_head_attention(&q, &k, &v, self.num_heads);
+        let context = multi_head_attention(&q, &k, &v, self.num_heads, 0, None);
+        self.w_o.forward(&context)
+    }
+
+    /// Like [`forward`](Self::forward) but also returns the attention
+    /// probabilities with shape (num_heads x seq_len x seq_len).
+    pub fn forward_with_weights(&self, input: &[Vec<f32>]) -> (Vec<Vec<f32>>, Vec<Vec<Vec<f32>>>) {
+        let q = self.w_q.forward(input);
+        let k = self.w_k.forward(input);
+        let v = self.w_v.forward(input);
+        let mut weights = Vec::new();
+        let context = multi_head_attention(&q, &k, &v, self.num_heads, 0, Some(&mut weights));
+        (self.w_o.forward(&context), weights)
+    }
+
+    /// Processes `input` as the continuation of the sequence held in `cache`.
+    ///
+    /// The keys and values of the new positions are appended to `cache` and
+    /// the queries attend over everything cached so far.
+    pub fn forward_cached(&self, input: &[Vec<f32>], cache: &mut LayerKv) -> Vec<Vec<f32>> {
+        let offset = cache.keys.len();
+        let q = self.w_q.forward(input);
+        cache.keys.extend(self.w_k.forward(input));
+        cache.values.extend(self.w_v.forward(input));
+        let context = multi_head_attention(&q, &cache.keys, &cache.values, self.num_heads, offset, None);
+        self.w_o.forward(&context)
+    }
+
+    /// Cached attention over several independent sequences at once.
+    ///
+    /// `input` stacks the new rows of every sequence; `lens[s]` rows belong to
+    /// sequence `s`, whose keys and values live in `caches[s]`. The
+    /// projections run as single matrix multiplications over the whole batch
+    /// while attention itself stays per sequence.
+    pub fn forward_batch_cached(
+        &self,
+        input: &[Vec<f32>],
+        lens: &[usize],
+        caches: &mut [&mut LayerKv],
+    ) -> Vec<Vec<f32>> {
+        let q = self.w_q.forward(input);
+        let mut k = self.w_k.forward(input).into_iter();
+        let mut v = self.w_v.forward(input).into_iter();
+        let mut context = Vec::with_capacity(input.len());
+        let mut start = 0;
+        for (&len, cache) in lens.iter().zip(caches.iter_mut()) {
+            if len == 0 {
+                continue;
+            }
+            let offset = cache.keys.len();
+            cache.keys.extend(k.by_ref().take(len));
+            cache.values.extend(v.by_ref().take(len));
+            context.extend(multi_head_attention(
+                &q[start..start + len],
+                &cache.keys,
+                &cache.values,
+                self.num_heads,
+                offset,
+                None,
+            ));
+            start += len;
+        }
         self.w_o.forward(&context)
     }
 }
@@ -138,5 +216,37 @@ mod tests {
         // with identity weights and bias zero, output should equal input
         assert_eq!(output, input);
     }
+
+    #[test]
+    fn cached_matches_full() {
+        let layer = MultiHeadAttention::new(4, 2);
+        let input = vec![
+            vec![1.0f32, 0.5, -1.0, 2.0],
+            vec![0.3, -0.2, 0.8, 0.1],
+            vec![-0.7, 0.4, 0.2, -0.3],
+        ];
+        let full = layer.forward(&input);
+        let mut cache = LayerKv::default();
+        let mut incremental = layer.forward_cached(&input[..2], &mut cache);
+        incremental.extend(layer.forward_cached(&input[2..], &mut cache));
+        assert_eq!(cache.keys.len(), 3);
+        for (a, b) in full.iter().flatten().zip(incremental.iter().flatten()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn attention_weights_are_causal() {
+        let layer = MultiHeadAttention::new(4, 2);
+        let input = vec![vec![1.0f32, 0.5, -1.0, 2.0], vec![0.3, -0.2, 0.8, 0.1]];
+        let (output, weights) = layer.forward_with_weights(&input);
+        assert_eq!(output, layer.forward(&input));
+        assert_eq!(weights.len(), 2);
+        for head in &weights {
+            assert_eq!(head.len(), 2);
+            assert_eq!(head[0][1], 0.0);
+            assert!((head[0][0] - 1.0).abs() < 1e-6);
+        }
+    }
 }
 
@@ -0,0 +1,129 @@
+use dragon_core::tokenizer::{bytes_to_symbols, is_single_file, BpeTokenizer, DEFAULT_WORD_CACHE};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+// The string based encoder `BpeTokenizer` used before symbol ids, kept here
+// as the baseline: every merge step rebuilds the candidate pairs as strings.
+struct StringEncoder<'a> {
+    tokenizer: &'a BpeTokenizer,
+    vocab: HashMap<String, usize>,
+    merges: HashMap<(String, String), usize>,
+}
+
+impl<'a> StringEncoder<'a> {
+    fn new(tokenizer: &'a BpeTokenizer) -> Self {
+        let vocab = (0..tokenizer.vocab_size())
+            .filter_map(|id| tokenizer.id_to_token(id).map(|t| (t.to_string(), id)))
+            .collect();
+        let merges = tokenizer
+            .merges()
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (a, b))| ((a.to_string(), b.to_string()), rank))
+            .collect();
+        Self { tokenizer, vocab, merges }
+    }
+
+    fn merge_word(&self, word: &str) -> Vec<String> {
+        let mut pieces: Vec<String> = word.chars().map(|c| c.to_string()).collect();
+        loop {
+            let mut best: Option<(usize, usize)> = None; // (rank, index)
+            for i in 0..pieces.len().saturating_sub(1) {
+                let pair = (pieces[i].clone(), pieces[i + 1].clone());
+                if let Some(&rank) = self.merges.get(&pair) {
+                    if best.map(|b| rank < b.0).unwrap_or(true) {
+                        best = Some((rank, i));
+                    }
+                }
+            }
+            match best {
+                Some((_, idx)) => {
+                    pieces[idx] = format!("{}{}", pieces[idx], pieces[idx + 1]);
+                    pieces.remove(idx + 1);
+                }
+                None => return pieces,
+            }
+        }
+    }
+
+    fn encode(&self, text: &str) -> Vec<usize> {
+        let pipeline = self.tokenizer.pipeline();
+        let normalized = pipeline.normalize(text);
+        let mut ids = Vec::new();
+        for piece in pipeline.pre_tokenize(&normalized) {
+            let word = if self.tokenizer.is_byte_level() {
+                bytes_to_symbols(piece.as_bytes())
+            } else {
+                piece.to_string()
+            };
+            for p in self.merge_word(&word) {
+                ids.push(self.vocab.get(&p).copied().unwrap_or(self.tokenizer.unk_id()));
+            }
+        }
+        ids
+    }
+}
+
+fn report(label: &str, mb: f64, secs: f64, tokens: usize) {
+    print!(
+        "{:<14} {:.2} MB in {:.3}s, {:.2} MB/s, {:.0} tokens/s",
+        label,
+        mb,
+        secs,
+        mb / secs,
+        tokens as f64 / secs
+    );
+}
+
+fn usage() {
+    eprintln!("Usage: bench_tokenizer <tokenizer.json | vocab.txt merges.txt> <corpus.txt> [--byte-level]");
+}
+
+fn main() {
+    let mut positional = Vec::new();
+    let mut byte_level = false;
+    for arg in env::args().skip(1) {
+        if arg == "--byte-level" {
+            byte_level = true;
+        } else {
+            positional.push(arg);
+        }
+    }
+    // `--byte-level` only applies to the legacy pair
+    let single = positional.first().is_some_and(|p| is_single_file(p));
+    let corpus_index = if single { 1 } else { 2 };
+    if positional.len() <= corpus_index {
+        usage();
+        std::process::exit(1);
+    }
+    let load = || {
+        let tokenizer = if single {
+            BpeTokenizer::load(&positional[0])
+        } else {
+            BpeTokenizer::load_legacy(&positional[0], &positional[1], 0, byte_level)
+        };
+        tokenizer.expect("failed to load tokenizer")
+    };
+    let corpus = fs::read_to_string(&positional[corpus_index]).expect("failed to read corpus");
+    let mb = corpus.len() as f64 / (1024.0 * 1024.0);
+
+    let tokenizer = load();
+    let baseline = StringEncoder::new(&tokenizer);
+    let start = Instant::now();
+    let tokens: usize = corpus.lines().map(|line| baseline.encode(line).len()).sum();
+    report("string:", mb, start.elapsed().as_secs_f64(), tokens);
+    println!();
+
+    for capacity in [0, DEFAULT_WORD_CACHE] {
+        let mut tokenizer = load();
+        tokenizer.set_cache_capacity(capacity);
+        let start = Instant::now();
+        let tokens: usize = corpus.lines().map(|line| tokenizer.encode(line).len()).sum();
+        let secs = start.elapsed().as_secs_f64();
+        let stats = tokenizer.cache_stats();
+        report(&format!("cache {:>6}:", capacity), mb, secs, tokens);
+        println!(", {} hits, {} misses", stats.hits, stats.misses);
+    }
+}
@@ -0,0 +1,70 @@
+use dragon_core::model::Model;
+use dragon_core::hallucination::DEFAULT_PMI_THRESHOLD;
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
+use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
+use serde_json::json;
+use std::env;
+
+fn usage() {
+    eprintln!("Usage: context_pmi <tokenizer.json | vocab.txt merges.txt> <context> <query> <answer> [threshold]");
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
+    let mut next_arg = || match args.next() {
+        Some(a) => a,
+        None => {
+            usage();
+            std::process::exit(1);
+        }
+    };
+    let context = next_arg();
+    let query = next_arg();
+    let answer = next_arg();
+    let threshold: f32 = args
+        .next()
+        .map(|t| t.parse().expect("invalid threshold"))
+        .unwrap_or(DEFAULT_PMI_THRESHOLD);
+
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
+    let context_tokens = tokenizer.encode(&context);
+    let query_tokens = tokenizer.encode(&query);
+    let answer_tokens = tokenizer.encode(&answer);
+    if query_tokens.is_empty() {
+        eprintln!("Query must contain at least one token");
+        std::process::exit(1);
+    }
+
+    let vocab_size = tokenizer.vocab_size();
+    let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
+    let report = model
+        .context_attribution(&context_tokens, &query_tokens, &answer_tokens, threshold)
+        .expect("tokens outside the model vocabulary");
+
+    let tokens: Vec<_> = report
+        .tokens
+        .iter()
+        .map(|t| {
+            json!({
+                "token": t.token,
+                "text": tokenizer.decode(&[t.token]),
+                "logprob_with": t.logprob_with,
+                "logprob_without": t.logprob_without,
+                "pmi": t.pmi,
+                "flagged": t.flagged,
+            })
+        })
+        .collect();
+    let out = json!({
+        "answer": answer,
+        "threshold": threshold,
+        "mean_pmi": report.mean_pmi,
+        "flagged_fraction": report.flagged_fraction,
+        "tokens": tokens,
+    });
+    println!("{}", out);
+}
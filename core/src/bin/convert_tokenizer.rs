@@ -0,0 +1,48 @@
+use dragon_core::tokenizer::BpeTokenizer;
+use dragon_core::tokenizer_file::{is_tokenizer_file, TokenizerFile};
+use dragon_core::unigram::UnigramTokenizer;
+use std::env;
+
+fn usage() {
+    eprintln!("Usage: convert_tokenizer <vocab.txt merges.txt | tokenizer.json | tokenizer.model> <output.json> [unk_id] [--byte-level]");
+}
+
+fn main() {
+    let mut positional = Vec::new();
+    let mut byte_level = false;
+    for arg in env::args().skip(1) {
+        if arg == "--byte-level" {
+            byte_level = true;
+        } else {
+            positional.push(arg);
+        }
+    }
+    if positional.len() < 2 {
+        usage();
+        std::process::exit(1);
+    }
+
+    let input = &positional[0];
+    let (file, output) = if input.ends_with(".model") {
+        let tok = UnigramTokenizer::load_model(input).expect("failed to read model");
+        (TokenizerFile::from_unigram(&tok), &positional[1])
+    } else if input.ends_with(".json") {
+        let json = std::fs::read_to_string(input).expect("failed to read tokenizer");
+        let file = if is_tokenizer_file(&json) {
+            TokenizerFile::from_json(&json)
+        } else {
+            BpeTokenizer::from_hf_json(&json).map(|tok| TokenizerFile::from_bpe(&tok))
+        };
+        (file.expect("failed to parse tokenizer"), &positional[1])
+    } else {
+        if positional.len() < 3 {
+            usage();
+            std::process::exit(1);
+        }
+        let unk_id = positional.get(3).map_or(0, |id| id.parse().expect("invalid unk_id"));
+        let tok = BpeTokenizer::load_legacy(input, &positional[1], unk_id, byte_level).expect("failed to read vocab and merges");
+        (TokenizerFile::from_bpe(&tok), &positional[2])
+    };
+    file.write(output).expect("failed to write tokenizer");
+    eprintln!("wrote {} (format version {})", output, file.version);
+}
@@ -0,0 +1,48 @@
+use dragon_core::hooks::ActivationRecorder;
+use dragon_core::model::Model;
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
+use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
+use serde_json::json;
+use std::env;
+
+fn usage() {
+    eprintln!("Usage: dump_attention <tokenizer.json | vocab.txt merges.txt> <text>");
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
+    let text = match args.next() {
+        Some(t) => t,
+        None => {
+            usage();
+            std::process::exit(1);
+        }
+    };
+
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
+    let tokens = tokenizer.encode(&text);
+    if tokens.is_empty() {
+        eprintln!("Need at least one token to trace");
+        std::process::exit(1);
+    }
+
+    let vocab_size = tokenizer.vocab_size();
+    let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
+    let mut recorder = ActivationRecorder::new();
+    model.forward_with_observer(&tokens, &mut recorder);
+
+    let token_info: Vec<_> = tokens
+        .iter()
+        .map(|&id| json!({"id": id, "text": tokenizer.decode(&[id])}))
+        .collect();
+    let out = json!({
+        "tokens": token_info,
+        "num_heads": NUM_HEADS,
+        "layers": recorder.layers,
+    });
+    println!("{}", out);
+}
@@ -0,0 +1,72 @@
+use dragon_core::model::Model;
+use dragon_core::pooling::{Pooling, PoolingConfig};
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
+use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
+use std::env;
+use std::fs;
+use std::io::{BufWriter, Write};
+
+// Embeds every line of a text file and writes the result as a binary matrix.
+// The output starts with the row and column counts as little-endian u64 values
+// followed by the row-major f32 data, also little-endian. Empty lines produce
+// zero rows so row indices always match line numbers.
+fn usage() {
+    eprintln!("Usage: embed_lines <tokenizer.json | vocab.txt merges.txt> <input.txt> <output.bin> [mean|last|attention] [layer] [--no-normalize]");
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
+    let input_path = match args.next() {
+        Some(p) => p,
+        None => {
+            usage();
+            std::process::exit(1);
+        }
+    };
+    let output_path = match args.next() {
+        Some(p) => p,
+        None => {
+            usage();
+            std::process::exit(1);
+        }
+    };
+    let mut config = PoolingConfig::default();
+    for arg in args {
+        if arg == "--no-normalize" {
+            config.normalize = false;
+        } else if let Some(pooling) = Pooling::parse(&arg) {
+            config.pooling = pooling;
+        } else if let Ok(layer) = arg.parse::<usize>() {
+            config.layer = Some(layer);
+        } else {
+            usage();
+            std::process::exit(1);
+        }
+    }
+
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
+    let vocab_size = tokenizer.vocab_size();
+    let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
+
+    let text = fs::read_to_string(&input_path).expect("failed to read input file");
+    let rows: Vec<Vec<f32>> = text
+        .lines()
+        .map(|line| model.embed(&tokenizer.encode(line), &config))
+        .collect();
+
+    let file = fs::File::create(&output_path).expect("failed to create output file");
+    let mut out = BufWriter::new(file);
+    out.write_all(&(rows.len() as u64).to_le_bytes()).expect("failed to write header");
+    out.write_all(&(EMBED_DIM as u64).to_le_bytes()).expect("failed to write header");
+    for row in &rows {
+        for v in row {
+            out.write_all(&v.to_le_bytes()).expect("failed to write embedding");
+        }
+    }
+    out.flush().expect("failed to write embeddings");
+    eprintln!("wrote {} x {} matrix to {}", rows.len(), EMBED_DIM, output_path);
+}
@@ -1,58 +1,82 @@
 use dragon_core::model::Model;
-use dragon_core::tokenizer::BpeTokenizer;
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
+use dragon_core::generation::GenerationConfig;
 use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
 use std::env;
-use std::fs;
+use serde_json::json;
+
+fn usage() {
+    eprintln!("Usage: generate_text <tokenizer.json | vocab.txt merges.txt> <prompt> <steps> [top_n]");
+}
 
 fn main() {
     let mut args = env::args().skip(1);
-    let vocab_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: generate_text <vocab.txt> <merges.txt> <prompt> <steps>");
-            std::process::exit(1);
-        }
-    };
-    let merges_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: generate_text <vocab.txt> <merges.txt> <prompt> <steps>");
-            std::process::exit(1);
-        }
-    };
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
     let prompt = match args.next() {
         Some(t) => t,
         None => {
-            eprintln!("Usage: generate_text <vocab.txt> <merges.txt> <prompt> <steps>");
+            usage();
             std::process::exit(1);
         }
     };
     let steps: usize = match args.next() {
         Some(s) => s.parse().expect("invalid steps"),
         None => {
-            eprintln!("Usage: generate_text <vocab.txt> <merges.txt> <prompt> <steps>");
+            usage();
             std::process::exit(1);
         }
     };
+    let top_n: Option<usize> = args.next().map(|s| s.parse().expect("invalid top_n"));
 
-    let vocab_contents = fs::read_to_string(vocab_path).expect("failed to read vocab file");
-    let vocab: Vec<String> = vocab_contents.lines().map(|s| s.to_string()).collect();
-    let merges_contents = fs::read_to_string(merges_path).expect("failed to read merges file");
-    let merges: Vec<(String, String)> = merges_contents
-        .lines()
-        .filter_map(|l| {
-            let mut parts = l.split_whitespace();
-            let a = parts.next()?.to_string();
-            let b = parts.next()?.to_string();
-            Some((a, b))
-        })
-        .collect();
-
-    let tokenizer = BpeTokenizer::new(vocab.clone(), merges, 0);
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
     let mut tokens = tokenizer.encode(&prompt);
 
-    let vocab_size = vocab.len();
+    let vocab_size = tokenizer.vocab_size();
     let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
+
+    // with top_n, emit JSON carrying per-token log-probabilities and alternatives
+    if let Some(top_n) = top_n {
+        let config = GenerationConfig {
+            max_tokens: steps,
+            temperature: 0.0,
+            top_logprobs: top_n,
+            ..Default::default()
+        };
+        let output = model.generate_with(&tokens, &config, &mut rand::thread_rng());
+        let generated: Vec<_> = output
+            .generated
+            .iter()
+            .map(|t| {
+                let top: Vec<_> = t
+                    .top
+                    .iter()
+                    .map(|alt| json!({
+                        "token": alt.token,
+                        "text": tokenizer.decode(&[alt.token]),
+                        "logprob": alt.logprob,
+                    }))
+                    .collect();
+                json!({
+                    "token": t.token,
+                    "text": tokenizer.decode(&[t.token]),
+                    "logprob": t.logprob,
+                    "top": top,
+                })
+            })
+            .collect();
+        let out = json!({
+            "text": tokenizer.decode(&output.tokens),
+            "tokens": output.tokens,
+            "total_logprob": output.total_logprob(),
+            "generated": generated,
+        });
+        println!("{}", out);
+        return;
+    }
+
     tokens = model.generate(&tokens, steps);
 
     let out_text = tokenizer.decode(&tokens);
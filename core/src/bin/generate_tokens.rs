@@ -1,5 +1,8 @@
 use dragon_core::model::Model;
+use dragon_core::generation::GenerationConfig;
 use dragon_core::hyperparams::{DEFAULT_VOCAB_SIZE, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::io::{self, Write};
 
 fn main() {
@@ -18,22 +21,17 @@ fn main() {
         std::process::exit(1);
     }
 
-    let mut current = tokens;
     let vocab_size = DEFAULT_VOCAB_SIZE;
     let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
 
-    for _ in 0..steps {
-        let logits = model.forward(&current);
-        if let Some(last) = logits.last() {
-            let next = last
-                .iter()
-                .enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                .map(|(i, _)| i)
-                .unwrap_or(0);
-            current.push(next);
-            println!("{}", next);
-            io::stdout().flush().unwrap();
-        }
+    let config = GenerationConfig {
+        max_tokens: steps,
+        ..Default::default()
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    // print each token as soon as it is generated
+    for event in model.stream(&tokens, &config, &mut rng) {
+        println!("{}", event.generated.token);
+        io::stdout().flush().unwrap();
     }
 }
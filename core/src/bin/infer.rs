@@ -1,21 +1,49 @@
+use dragon_core::generation::token_logprobs;
 use dragon_core::model::Model;
+use dragon_core::sampling;
 use dragon_core::hyperparams::{DEFAULT_VOCAB_SIZE, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
+use serde_json::json;
+
+// number of alternatives reported per position unless `--top` is given
+const DEFAULT_TOP_N: usize = 5;
+
+fn usage() {
+    eprintln!("Usage: infer [--top N] <token ids>");
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    if args.is_empty() {
-        eprintln!("Usage: infer <token ids>");
+    let mut args = std::env::args().skip(1).peekable();
+    let mut top_n = DEFAULT_TOP_N;
+    if args.peek().is_some_and(|a| a == "--top") {
+        args.next();
+        top_n = match args.next() {
+            Some(n) => n.parse().expect("invalid top N"),
+            None => {
+                usage();
+                std::process::exit(1);
+            }
+        };
+    }
+    let tokens: Vec<usize> = args.map(|a| a.parse::<usize>().expect("invalid token")).collect();
+    if tokens.is_empty() {
+        usage();
         std::process::exit(1);
     }
 
-    let tokens: Vec<usize> = args.iter().map(|a| a.parse::<usize>().expect("invalid token" )).collect();
-
     // Example model dimensions; in real usage load actual weights.
     let vocab_size = DEFAULT_VOCAB_SIZE;
     let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
     let logits = model.forward(&tokens);
 
-    for (idx, logit) in logits.iter().enumerate() {
-        println!("step {} -> {:?}", idx, logit);
-    }
+    // the greedy prediction after every prefix with its log-probability and
+    // the most likely alternatives
+    let predictions: Vec<_> = logits
+        .iter()
+        .map(|row| token_logprobs(row, sampling::argmax(row), top_n))
+        .collect();
+    let out = json!({
+        "tokens": tokens,
+        "predictions": predictions,
+    });
+    println!("{}", out);
 }
@@ -1,51 +1,31 @@
 use dragon_core::model::Model;
-use dragon_core::tokenizer::BpeTokenizer;
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
 use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
 use std::env;
-use std::fs;
+
+fn usage() {
+    eprintln!("Usage: infer_text <tokenizer.json | vocab.txt merges.txt> <text>");
+}
 
 fn main() {
     let mut args = env::args().skip(1);
-    let vocab_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: infer_text <vocab.txt> <merges.txt> <text>");
-            std::process::exit(1);
-        }
-    };
-    let merges_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: infer_text <vocab.txt> <merges.txt> <text>");
-            std::process::exit(1);
-        }
-    };
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
     let text = match args.next() {
         Some(t) => t,
         None => {
-            eprintln!("Usage: infer_text <vocab.txt> <merges.txt> <text>");
+            usage();
             std::process::exit(1);
         }
     };
 
-    let vocab_contents = fs::read_to_string(vocab_path).expect("failed to read vocab file");
-    let vocab: Vec<String> = vocab_contents.lines().map(|s| s.to_string()).collect();
-    let merges_contents = fs::read_to_string(merges_path).expect("failed to read merges file");
-    let merges: Vec<(String, String)> = merges_contents
-        .lines()
-        .filter_map(|l| {
-            let mut parts = l.split_whitespace();
-            let a = parts.next()?.to_string();
-            let b = parts.next()?.to_string();
-            Some((a, b))
-        })
-        .collect();
-
-    let tokenizer = BpeTokenizer::new(vocab.clone(), merges, 0);
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
 
     let tokens = tokenizer.encode(&text);
 
-    let vocab_size = vocab.len();
+    let vocab_size = tokenizer.vocab_size();
     let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
     let logits = model.forward(&tokens);
 
@@ -59,7 +39,7 @@ fn main() {
             logit
                 .iter()
                 .enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+                .max_by(|a, b| a.1.total_cmp(b.1))
                 .map(|(i, _)| i)
                 .unwrap_or(0)
         })
@@ -0,0 +1,59 @@
+use dragon_core::model::Model;
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
+use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS};
+use serde_json::json;
+use std::env;
+
+fn usage() {
+    eprintln!("Usage: score_text <tokenizer.json | vocab.txt merges.txt> <context> <candidate> [candidate ...]");
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
+    let context = match args.next() {
+        Some(t) => t,
+        None => {
+            usage();
+            std::process::exit(1);
+        }
+    };
+    let candidates: Vec<String> = args.collect();
+    if candidates.is_empty() {
+        usage();
+        std::process::exit(1);
+    }
+
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
+    let context_tokens = tokenizer.encode(&context);
+    if context_tokens.is_empty() {
+        eprintln!("Context must contain at least one token");
+        std::process::exit(1);
+    }
+    let candidate_tokens: Vec<Vec<usize>> = candidates.iter().map(|c| tokenizer.encode(c)).collect();
+
+    let vocab_size = tokenizer.vocab_size();
+    let model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
+    let scores = model
+        .score_batch(&context_tokens, &candidate_tokens)
+        .expect("tokens outside the model vocabulary");
+
+    let results: Vec<_> = candidates
+        .iter()
+        .zip(&candidate_tokens)
+        .zip(&scores)
+        .map(|((text, tokens), score)| {
+            json!({
+                "candidate": text,
+                "tokens": tokens,
+                "total": score.total,
+                "mean": score.mean(),
+                "token_logprobs": score.token_logprobs,
+            })
+        })
+        .collect();
+    println!("{}", json!(results));
+}
@@ -1,31 +1,24 @@
 use dragon_core::model::Model;
-use dragon_core::tokenizer::BpeTokenizer;
+use dragon_core::tokenizer::{load_tokenizer, tokenizer_args};
 use dragon_core::loss::cross_entropy;
 use dragon_core::hyperparams::{EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS, LEARNING_RATE};
 use half::f16;
 use std::env;
-use std::fs;
+
+fn usage() {
+    eprintln!("Usage: train <tokenizer.json | vocab.txt merges.txt> <text> [epochs] [accum_steps] [--fp16]");
+}
 
 fn main() {
     let mut args = env::args().skip(1);
-    let vocab_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: train <vocab.txt> <merges.txt> <text> [epochs] [accum_steps] [--fp16]");
-            std::process::exit(1);
-        }
-    };
-    let merges_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: train <vocab.txt> <merges.txt> <text> [epochs] [accum_steps] [--fp16]");
-            std::process::exit(1);
-        }
-    };
+    let (vocab_path, merges_path) = tokenizer_args(&mut args).unwrap_or_else(|| {
+        usage();
+        std::process::exit(1);
+    });
     let text = match args.next() {
         Some(t) => t,
         None => {
-            eprintln!("Usage: train <vocab.txt> <merges.txt> <text> [epochs] [accum_steps] [--fp16]");
+            usage();
             std::process::exit(1);
         }
     };
@@ -39,22 +32,9 @@ fn main() {
         .unwrap_or_else(|| "1".into())
         .parse()
         .expect("invalid accumulation steps");
-    let use_fp16 = args.next().map_or(false, |a| a == "--fp16");
-
-    let vocab_contents = fs::read_to_string(&vocab_path).expect("failed to read vocab file");
-    let vocab: Vec<String> = vocab_contents.lines().map(|s| s.to_string()).collect();
-    let merges_contents = fs::read_to_string(&merges_path).expect("failed to read merges file");
-    let merges: Vec<(String, String)> = merges_contents
-        .lines()
-        .filter_map(|l| {
-            let mut parts = l.split_whitespace();
-            let a = parts.next()?.to_string();
-            let b = parts.next()?.to_string();
-            Some((a, b))
-        })
-        .collect();
+    let use_fp16 = args.next().is_some_and(|a| a == "--fp16");
 
-    let tokenizer = BpeTokenizer::new(vocab.clone(), merges, 0);
+    let tokenizer = load_tokenizer(&vocab_path, &merges_path, 0).expect("failed to load tokenizer");
     let tokens = tokenizer.encode(&text);
     if tokens.len() < 2 {
         eprintln!("Need at least two tokens to train");
@@ -64,7 +44,7 @@ fn main() {
     let inputs = &tokens[..tokens.len() - 1];
     let targets = &tokens[1..];
 
-    let vocab_size = vocab.len();
+    let vocab_size = tokenizer.vocab_size();
     let mut model = Model::new(vocab_size, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
     let lr = LEARNING_RATE;
 
@@ -1,41 +1,118 @@
-use std::collections::HashMap;
+use dragon_core::bpe_trainer::{BpeTrainer, BpeTrainerConfig};
+use dragon_core::text_pipeline::{Normalizer, PreTokenizer, TextPipeline};
+use dragon_core::tokenizer_file::TOKENIZER_FILE;
+use dragon_core::tokenizer::DEFAULT_SPECIAL_TOKENS;
+use dragon_core::unigram_trainer::{UnigramTrainer, UnigramTrainerConfig};
 use std::env;
-use std::fs;
-use std::io::Write;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+fn usage() {
+    eprintln!("Usage: train_vocab <input.txt> <output_dir> [vocab_size] [min_frequency] [--byte-level] [--unigram] [--no-special] [--legacy]");
+    eprintln!("       [--nfc] [--nfkc] [--lowercase] [--strip-accents] [--split-digits] [--isolate-punctuation]");
+}
 
 fn main() {
-    let mut args = env::args().skip(1);
-    let input_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: train_vocab <input.txt> <output.txt> [limit]");
-            std::process::exit(1);
+    let mut positional = Vec::new();
+    let mut byte_level = false;
+    let mut special = true;
+    let mut unigram = false;
+    let mut legacy = false;
+    let mut normalizers = Vec::new();
+    let mut extra_pre_tokenizers = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--byte-level" => byte_level = true,
+            "--no-special" => special = false,
+            "--unigram" => unigram = true,
+            "--legacy" => legacy = true,
+            "--nfc" => normalizers.push(Normalizer::Nfc),
+            "--nfkc" => normalizers.push(Normalizer::Nfkc),
+            "--lowercase" => normalizers.push(Normalizer::Lowercase),
+            "--strip-accents" => normalizers.push(Normalizer::StripAccents),
+            "--split-digits" => extra_pre_tokenizers.push(PreTokenizer::Digits { individual: true }),
+            "--isolate-punctuation" => extra_pre_tokenizers.push(PreTokenizer::Punctuation),
+            _ => positional.push(arg),
         }
+    }
+    if positional.len() < 2 {
+        usage();
+        std::process::exit(1);
+    }
+    let vocab_size = positional.get(2).map(|size| size.parse().expect("invalid vocab_size"));
+    let min_frequency = positional.get(3).map(|freq| freq.parse().expect("invalid min_frequency"));
+    let special_tokens: Vec<String> = if special {
+        DEFAULT_SPECIAL_TOKENS.iter().map(|s| s.to_string()).collect()
+    } else {
+        Vec::new()
     };
-    let output_path = match args.next() {
-        Some(p) => p,
-        None => {
-            eprintln!("Usage: train_vocab <input.txt> <output.txt> [limit]");
-            std::process::exit(1);
+
+    // stream the corpus line by line so large files never sit in memory
+    let file = File::open(&positional[0]).expect("failed to open input file");
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    let mut feed = |f: &mut dyn FnMut(&str)| loop {
+        line.clear();
+        if reader.read_line(&mut line).expect("failed to read input file") == 0 {
+            break;
         }
+        f(&line);
     };
-    let limit: Option<usize> = args.next().and_then(|s| s.parse().ok());
 
-    let contents = fs::read_to_string(&input_path).expect("failed to read input file");
-    let mut counts: HashMap<String, usize> = HashMap::new();
-    for word in contents.split_whitespace() {
-        *counts.entry(word.to_string()).or_insert(0) += 1;
-    }
+    if unigram {
+        if !extra_pre_tokenizers.is_empty() {
+            eprintln!("pre-tokenizer flags are ignored for unigram models");
+        }
+        let mut config = UnigramTrainerConfig {
+            special_tokens,
+            normalizers,
+            ..Default::default()
+        };
+        config.vocab_size = vocab_size.unwrap_or(config.vocab_size);
+        config.min_frequency = min_frequency.unwrap_or(config.min_frequency);
+        let mut trainer = UnigramTrainer::new(config);
+        feed(&mut |text| trainer.feed(text));
+        eprintln!("counted {} distinct words", trainer.distinct_words());
 
-    let mut items: Vec<(String, usize)> = counts.into_iter().collect();
-    items.sort_by(|a, b| b.1.cmp(&a.1));
-    if let Some(lim) = limit {
-        items.truncate(lim);
+        let tokenizer = trainer.train();
+        std::fs::create_dir_all(&positional[1]).expect("failed to create output directory");
+        let dir = std::path::Path::new(&positional[1]);
+        let (path, saved) = if legacy {
+            let path = dir.join("unigram.model");
+            let saved = tokenizer.save_model(&path);
+            (path, saved)
+        } else {
+            let path = dir.join(TOKENIZER_FILE);
+            let saved = tokenizer.save(&path);
+            (path, saved)
+        };
+        saved.expect("failed to write tokenizer model");
+        eprintln!("wrote {} pieces to {}", tokenizer.vocab_size(), path.display());
+        return;
     }
 
-    let mut file = fs::File::create(&output_path).expect("failed to create output file");
-    for (token, _) in items {
-        writeln!(file, "{}", token).expect("failed to write token");
-    }
-}
+    let mut pipeline = TextPipeline::default_for(byte_level);
+    pipeline.normalizers = normalizers;
+    pipeline.pre_tokenizers.extend(extra_pre_tokenizers);
+    let mut config = BpeTrainerConfig {
+        byte_level,
+        special_tokens,
+        pipeline: Some(pipeline),
+        ..Default::default()
+    };
+    config.vocab_size = vocab_size.unwrap_or(config.vocab_size);
+    config.min_frequency = min_frequency.unwrap_or(config.min_frequency);
+    let mut trainer = BpeTrainer::new(config);
+    feed(&mut |text| trainer.feed(text));
+    eprintln!("counted {} distinct words", trainer.distinct_words());
 
+    let trained = trainer.train();
+    let (written, saved) = if legacy {
+        (positional[1].clone(), trained.save_legacy(&positional[1]))
+    } else {
+        let path = std::path::Path::new(&positional[1]).join(TOKENIZER_FILE);
+        (path.display().to_string(), trained.save(&positional[1]))
+    };
+    saved.expect("failed to write tokenizer files");
+    eprintln!("wrote {} tokens and {} merges to {}", trained.vocab.len(), trained.merges.len(), written);
+}
@@ -45,7 +45,7 @@ fn main() {
     }
 
     let mut items: Vec<(String, usize)> = counts.into_iter().collect();
-    items.sort_by(|a, b| b.1.cmp(&a.1));
+    items.sort_by_key(|b| std::cmp::Reverse(b.1));
 
     for (token, _) in items {
         if limit.map(|l| vocab.len() < l).unwrap_or(true) {
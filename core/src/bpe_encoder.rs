@@ -0,0 +1,305 @@
+// Fast BPE word encoding.
+// Symbols are interned to integers and merges are looked up by symbol pair,
+// so no strings are built while merging. A word is held as a linked list of
+// symbols with a min-heap of candidate merges keyed by (rank, position);
+// entries made stale by earlier merges are skipped when popped. Encoded words
+// are kept in an LRU cache since natural text repeats words heavily.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+/// Symbol id of characters that appear in neither the vocabulary nor a merge.
+pub(crate) const UNKNOWN: u32 = u32::MAX;
+
+/// Merge rules over interned symbols.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MergeTable {
+    symbols: HashMap<String, u32>,
+    names: Vec<String>,
+    // vocabulary id of each symbol, if it is a token
+    token_ids: Vec<Option<usize>>,
+    chars: HashMap<char, u32>,
+    // (left, right) -> (rank, merged symbol)
+    ranks: HashMap<(u32, u32), (usize, u32)>,
+}
+
+impl MergeTable {
+    pub(crate) fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&sym) = self.symbols.get(name) {
+            return sym;
+        }
+        let sym = self.names.len() as u32;
+        self.symbols.insert(name.to_string(), sym);
+        self.names.push(name.to_string());
+        self.token_ids.push(None);
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            self.chars.insert(c, sym);
+        }
+        sym
+    }
+
+    /// Records that `name` is vocabulary token `id`.
+    pub(crate) fn set_token(&mut self, name: &str, id: usize) {
+        let sym = self.intern(name);
+        self.token_ids[sym as usize] = Some(id);
+    }
+
+    /// Adds merge `a + b` with `rank` unless the pair already has one.
+    pub(crate) fn add_merge(&mut self, a: &str, b: &str, rank: usize) {
+        let left = self.intern(a);
+        let right = self.intern(b);
+        let merged = self.intern(&format!("{}{}", a, b));
+        self.ranks.entry((left, right)).or_insert((rank, merged));
+    }
+
+    pub(crate) fn char_symbol(&self, c: char) -> u32 {
+        self.chars.get(&c).copied().unwrap_or(UNKNOWN)
+    }
+
+    pub(crate) fn token_id(&self, sym: u32) -> Option<usize> {
+        self.token_ids.get(sym as usize).copied().flatten()
+    }
+
+    pub(crate) fn name(&self, sym: u32) -> &str {
+        &self.names[sym as usize]
+    }
+
+    /// Applies the merges to the characters of `word` in rank order, leftmost
+    /// first among equal ranks. Returns each final symbol with the char range
+    /// of `word` it covers.
+    pub(crate) fn merge(&self, word: &str) -> Vec<(u32, usize, usize)> {
+        let mut syms: Vec<u32> = word.chars().map(|c| self.char_symbol(c)).collect();
+        let n = syms.len();
+        // char range end of every live symbol; 0 marks a merged-away one
+        let mut end: Vec<usize> = (1..=n).collect();
+        let mut prev: Vec<usize> = (0..n).map(|i| i.wrapping_sub(1)).collect();
+        let mut next: Vec<usize> = (1..=n).collect();
+        let rank_of = |l: u32, r: u32| self.ranks.get(&(l, r)).copied();
+
+        let mut heap = BinaryHeap::new();
+        for i in 0..n.saturating_sub(1) {
+            if let Some((rank, _)) = rank_of(syms[i], syms[i + 1]) {
+                heap.push(Reverse((rank, i)));
+            }
+        }
+        while let Some(Reverse((rank, i))) = heap.pop() {
+            let j = next[i];
+            if end[i] == 0 || j >= n {
+                continue;
+            }
+            let merged = match rank_of(syms[i], syms[j]) {
+                Some((r, merged)) if r == rank => merged,
+                _ => continue,
+            };
+            syms[i] = merged;
+            end[i] = end[j];
+            end[j] = 0;
+            next[i] = next[j];
+            if next[i] < n {
+                prev[next[i]] = i;
+            }
+            if prev[i] < n {
+                if let Some((r, _)) = rank_of(syms[prev[i]], syms[i]) {
+                    heap.push(Reverse((r, prev[i])));
+                }
+            }
+            if next[i] < n {
+                if let Some((r, _)) = rank_of(syms[i], syms[next[i]]) {
+                    heap.push(Reverse((r, i)));
+                }
+            }
+        }
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < n {
+            out.push((syms[i], i, end[i]));
+            i = next[i];
+        }
+        out
+    }
+}
+
+/// Cache hit statistics of a [`WordCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WordCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// LRU cache from words to their token ids.
+#[derive(Debug, Clone, Default)]
+pub struct WordCache {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (Vec<usize>, u64)>,
+    // tick of last use -> word, oldest first
+    order: BTreeMap<u64, String>,
+    stats: WordCacheStats,
+}
+
+impl WordCache {
+    /// Creates a cache holding at most `capacity` words. Zero disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> WordCacheStats {
+        self.stats
+    }
+
+    /// Returns the ids cached for `word` and marks it as recently used.
+    pub fn get(&mut self, word: &str) -> Option<Vec<usize>> {
+        self.tick += 1;
+        match self.entries.get_mut(word) {
+            Some((ids, used)) => {
+                let word = self.order.remove(used).unwrap_or_default();
+                *used = self.tick;
+                self.order.insert(self.tick, word);
+                self.stats.hits += 1;
+                Some(ids.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Stores `ids` for `word`, evicting the least recently used word when
+    /// full.
+    pub fn insert(&mut self, word: &str, ids: Vec<usize>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.remove(word) {
+            self.order.remove(&used);
+        }
+        while self.entries.len() >= self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.entries.insert(word.to_string(), (ids, self.tick));
+        self.order.insert(self.tick, word.to_string());
+    }
+
+    /// Drops every entry, keeping the statistics.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_by_rank_then_position() {
+        let mut table = MergeTable::default();
+        for c in ["a", "b", "c"] {
+            table.intern(c);
+        }
+        table.add_merge("b", "c", 0);
+        table.add_merge("a", "b", 1);
+        table.add_merge("a", "a", 2);
+        let names = |word: &str| -> Vec<String> {
+            table.merge(word).iter().map(|&(s, _, _)| table.name(s).to_string()).collect()
+        };
+        assert_eq!(names("abc"), vec!["a", "bc"]);
+        assert_eq!(names("abab"), vec!["ab", "ab"]);
+        assert_eq!(names("aaaaa"), vec!["aa", "aa", "a"]);
+        assert_eq!(table.merge("xab"), vec![(UNKNOWN, 0, 1), (table.symbols["ab"], 1, 3)]);
+        assert!(table.merge("").is_empty());
+    }
+
+    // the straightforward string based merge loop the table replaces
+    fn reference(merges: &[(String, String)], word: &str) -> Vec<String> {
+        let mut pieces: Vec<String> = word.chars().map(|c| c.to_string()).collect();
+        loop {
+            let best = (0..pieces.len().saturating_sub(1))
+                .filter_map(|i| {
+                    merges
+                        .iter()
+                        .position(|(a, b)| *a == pieces[i] && *b == pieces[i + 1])
+                        .map(|rank| (rank, i))
+                })
+                .min();
+            match best {
+                Some((_, i)) => {
+                    let right = pieces.remove(i + 1);
+                    pieces[i].push_str(&right);
+                }
+                None => return pieces,
+            }
+        }
+    }
+
+    #[test]
+    fn matches_reference_on_random_words() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        let mut rng = StdRng::seed_from_u64(44);
+        let mut tokens: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+        let mut merges = Vec::new();
+        let mut table = MergeTable::default();
+        for t in &tokens {
+            table.intern(t);
+        }
+        for rank in 0..30 {
+            let a = tokens[rng.gen_range(0..tokens.len())].clone();
+            let b = tokens[rng.gen_range(0..tokens.len())].clone();
+            if merges.contains(&(a.clone(), b.clone())) {
+                continue;
+            }
+            table.add_merge(&a, &b, rank);
+            tokens.push(format!("{}{}", a, b));
+            merges.push((a, b));
+        }
+        for _ in 0..500 {
+            let len = rng.gen_range(0..12);
+            let word: String = (0..len).map(|_| ['a', 'b', 'c', 'd', 'e'][rng.gen_range(0..5)]).collect();
+            let fast: Vec<String> = table
+                .merge(&word)
+                .into_iter()
+                .map(|(s, start, end)| match s {
+                    UNKNOWN => word[start..end].to_string(),
+                    _ => table.name(s).to_string(),
+                })
+                .collect();
+            assert_eq!(fast, reference(&merges, &word), "word {}", word);
+        }
+    }
+
+    #[test]
+    fn cache_evicts_least_recent() {
+        let mut cache = WordCache::new(2);
+        cache.insert("a", vec![1]);
+        cache.insert("b", vec![2]);
+        assert_eq!(cache.get("a"), Some(vec![1]));
+        cache.insert("c", vec![3]);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(vec![1]));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats(), WordCacheStats { hits: 2, misses: 1 });
+    }
+}
@@ -0,0 +1,411 @@
+// BPE merge trainer.
+// Words are counted once while the corpus streams in, so memory grows with
+// the number of distinct words rather than the corpus size. Training keeps
+// per-pair counts and the words each pair occurs in; after a merge only the
+// affected words are rescanned and the changed pair counts are pushed onto a
+// max-heap. Stale heap entries are skipped when popped.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::text_pipeline::{TextPipeline, PIPELINE_FILE};
+use crate::tokenizer::{bytes_to_symbols, byte_level_alphabet, BpeTokenizer, SPECIAL_TOKENS_FILE};
+use crate::tokenizer_file::TOKENIZER_FILE;
+
+/// Options for [`BpeTrainer`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BpeTrainerConfig {
+    /// Vocabulary size to stop at, special tokens and alphabet included.
+    pub vocab_size: usize,
+    /// Pairs seen fewer times than this are never merged.
+    pub min_frequency: u64,
+    /// Tokens reserved at the start of the vocabulary and registered as
+    /// special tokens.
+    pub special_tokens: Vec<String>,
+    /// Train over the 256 byte symbols with GPT-2 pre-tokenization instead of
+    /// characters of whitespace-separated words.
+    pub byte_level: bool,
+    /// Normalization and pre-tokenization of the corpus, saved with the
+    /// result. `None` uses the default of the mode, see
+    /// [`TextPipeline::default_for`].
+    pub pipeline: Option<TextPipeline>,
+}
+
+impl Default for BpeTrainerConfig {
+    fn default() -> Self {
+        Self {
+            vocab_size: 16_000,
+            min_frequency: 2,
+            special_tokens: Vec::new(),
+            byte_level: false,
+            pipeline: None,
+        }
+    }
+}
+
+/// Result of [`BpeTrainer::train`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrainedBpe {
+    /// Tokens in id order.
+    pub vocab: Vec<String>,
+    /// Merges in priority order.
+    pub merges: Vec<(String, String)>,
+    /// Special tokens, a prefix of `vocab` after `<unk>`.
+    pub special_tokens: Vec<String>,
+    pub byte_level: bool,
+    /// Pipeline the corpus was split with.
+    pub pipeline: TextPipeline,
+}
+
+impl TrainedBpe {
+    /// Builds a tokenizer from the trained vocabulary.
+    pub fn tokenizer(&self) -> BpeTokenizer {
+        let mut tok = if self.byte_level {
+            BpeTokenizer::new_byte_level(self.vocab.clone(), self.merges.clone())
+        } else {
+            BpeTokenizer::new(self.vocab.clone(), self.merges.clone(), 0)
+        };
+        for token in &self.special_tokens {
+            tok.add_special_token(token);
+        }
+        tok.set_pipeline(self.pipeline.clone());
+        tok
+    }
+
+    /// Writes the tokenizer to `dir` as a single [`TOKENIZER_FILE`].
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        self.tokenizer().save(dir.as_ref().join(TOKENIZER_FILE))
+    }
+
+    /// Writes the legacy layout: `vocab.txt`, `merges.txt`,
+    /// [`PIPELINE_FILE`] and, when there are special tokens,
+    /// `special_tokens.txt` into `dir`. Merges of tokens containing spaces
+    /// cannot be read back; prefer [`TrainedBpe::save`].
+    pub fn save_legacy<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let mut vocab = io::BufWriter::new(std::fs::File::create(dir.join("vocab.txt"))?);
+        for token in &self.vocab {
+            writeln!(vocab, "{}", token)?;
+        }
+        vocab.flush()?;
+        let mut merges = io::BufWriter::new(std::fs::File::create(dir.join("merges.txt"))?);
+        for (a, b) in &self.merges {
+            writeln!(merges, "{} {}", a, b)?;
+        }
+        merges.flush()?;
+        if !self.special_tokens.is_empty() {
+            self.tokenizer().save_special_tokens(dir.join(SPECIAL_TOKENS_FILE))?;
+        }
+        let pipeline = serde_json::to_string_pretty(&self.pipeline).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(dir.join(PIPELINE_FILE), pipeline)?;
+        Ok(())
+    }
+}
+
+/// Learns up to `num_merges` merges over `words`, each given as its current
+/// symbols and a count. Stops early once no pair reaches `min_frequency`.
+pub fn learn_merges(words: Vec<(Vec<String>, u64)>, num_merges: usize, min_frequency: u64) -> Vec<(String, String)> {
+    let mut symbols: Vec<String> = Vec::new();
+    let mut symbol_ids: HashMap<String, u32> = HashMap::new();
+    let mut intern = |s: &str, symbols: &mut Vec<String>| -> u32 {
+        *symbol_ids.entry(s.to_string()).or_insert_with(|| {
+            symbols.push(s.to_string());
+            (symbols.len() - 1) as u32
+        })
+    };
+    let mut seqs: Vec<Vec<u32>> = Vec::with_capacity(words.len());
+    let mut counts: Vec<u64> = Vec::with_capacity(words.len());
+    for (pieces, count) in words {
+        seqs.push(pieces.iter().map(|p| intern(p, &mut symbols)).collect());
+        counts.push(count);
+    }
+
+    let mut pair_counts: HashMap<(u32, u32), u64> = HashMap::new();
+    let mut pair_words: HashMap<(u32, u32), HashSet<usize>> = HashMap::new();
+    for (w, seq) in seqs.iter().enumerate() {
+        for pair in seq.windows(2) {
+            let pair = (pair[0], pair[1]);
+            *pair_counts.entry(pair).or_insert(0) += counts[w];
+            pair_words.entry(pair).or_default().insert(w);
+        }
+    }
+    // ties go to the pair whose symbols were created first
+    let mut heap: BinaryHeap<(u64, Reverse<(u32, u32)>)> =
+        pair_counts.iter().map(|(&pair, &count)| (count, Reverse(pair))).collect();
+
+    let mut merges = Vec::new();
+    while merges.len() < num_merges {
+        let (count, Reverse(pair)) = match heap.pop() {
+            Some(top) => top,
+            None => break,
+        };
+        let current = pair_counts.get(&pair).copied().unwrap_or(0);
+        if current != count {
+            if current > 0 {
+                heap.push((current, Reverse(pair)));
+            }
+            continue;
+        }
+        if count < min_frequency.max(1) {
+            break;
+        }
+        let merged = format!("{}{}", symbols[pair.0 as usize], symbols[pair.1 as usize]);
+        merges.push((symbols[pair.0 as usize].clone(), symbols[pair.1 as usize].clone()));
+        let new_id = intern(&merged, &mut symbols);
+
+        let affected: Vec<usize> = pair_words.remove(&pair).map(|s| s.into_iter().collect()).unwrap_or_default();
+        let mut changed: HashSet<(u32, u32)> = HashSet::new();
+        for w in affected {
+            let seq = &mut seqs[w];
+            if !seq.windows(2).any(|p| (p[0], p[1]) == pair) {
+                continue;
+            }
+            let count = counts[w];
+            for p in seq.windows(2) {
+                let p = (p[0], p[1]);
+                if let Some(c) = pair_counts.get_mut(&p) {
+                    *c -= count;
+                }
+                changed.insert(p);
+            }
+            let mut merged_seq = Vec::with_capacity(seq.len());
+            let mut i = 0;
+            while i < seq.len() {
+                if i + 1 < seq.len() && (seq[i], seq[i + 1]) == pair {
+                    merged_seq.push(new_id);
+                    i += 2;
+                } else {
+                    merged_seq.push(seq[i]);
+                    i += 1;
+                }
+            }
+            *seq = merged_seq;
+            for p in seq.windows(2) {
+                let p = (p[0], p[1]);
+                *pair_counts.entry(p).or_insert(0) += count;
+                pair_words.entry(p).or_default().insert(w);
+                changed.insert(p);
+            }
+        }
+        pair_counts.remove(&pair);
+        for p in changed {
+            match pair_counts.get(&p) {
+                Some(&c) if c > 0 => heap.push((c, Reverse(p))),
+                Some(_) => {
+                    pair_counts.remove(&p);
+                }
+                None => {}
+            }
+        }
+    }
+    merges
+}
+
+/// Streaming BPE trainer producing a vocabulary and merge list.
+pub struct BpeTrainer {
+    config: BpeTrainerConfig,
+    pipeline: TextPipeline,
+    word_counts: HashMap<String, u64>,
+}
+
+impl BpeTrainer {
+    pub fn new(config: BpeTrainerConfig) -> Self {
+        let pipeline = config
+            .pipeline
+            .clone()
+            .unwrap_or_else(|| TextPipeline::default_for(config.byte_level));
+        Self {
+            config,
+            pipeline,
+            word_counts: HashMap::new(),
+        }
+    }
+
+    /// Number of distinct words seen so far.
+    pub fn distinct_words(&self) -> usize {
+        self.word_counts.len()
+    }
+
+    /// Counts the words of `text` after the text pipeline. Can be called
+    /// repeatedly, e.g. once per line of a large corpus. Occurrences of
+    /// special tokens are skipped.
+    pub fn feed(&mut self, text: &str) {
+        let mut segments = vec![text];
+        for special in &self.config.special_tokens {
+            segments = segments.into_iter().flat_map(|s| s.split(special.as_str())).collect();
+        }
+        for segment in segments {
+            let normalized = self.pipeline.normalize(segment);
+            let pieces = self.pipeline.pre_tokenize(&normalized);
+            if self.config.byte_level {
+                for piece in pieces {
+                    *self.word_counts.entry(bytes_to_symbols(piece.as_bytes())).or_insert(0) += 1;
+                }
+            } else {
+                for word in pieces {
+                    match self.word_counts.get_mut(word) {
+                        Some(c) => *c += 1,
+                        None => {
+                            self.word_counts.insert(word.to_string(), 1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Learns merges until the vocabulary reaches `vocab_size` or no pair is
+    /// frequent enough. The vocabulary can end up slightly smaller when two
+    /// merges produce the same string.
+    ///
+    /// The vocabulary starts with `<unk>` and the special tokens, followed by
+    /// the base alphabet and one token per merge. Byte-level tokenizers never
+    /// emit `<unk>`, but reserving it keeps id 0 from naming a special token.
+    pub fn train(&self) -> TrainedBpe {
+        let mut vocab: Vec<String> = vec!["<unk>".to_string()];
+        for token in &self.config.special_tokens {
+            if !vocab.contains(token) {
+                vocab.push(token.clone());
+            }
+        }
+        let alphabet: Vec<String> = if self.config.byte_level {
+            byte_level_alphabet()
+        } else {
+            let chars: std::collections::BTreeSet<char> = self.word_counts.keys().flat_map(|w| w.chars()).collect();
+            chars.into_iter().map(|c| c.to_string()).collect()
+        };
+        let mut known: HashSet<String> = vocab.iter().cloned().collect();
+        for symbol in alphabet {
+            if known.insert(symbol.clone()) {
+                vocab.push(symbol);
+            }
+        }
+
+        // sorted so training does not depend on hash order
+        let mut words: Vec<(&String, &u64)> = self.word_counts.iter().collect();
+        words.sort();
+        let words = words
+            .into_iter()
+            .map(|(w, &c)| (w.chars().map(|c| c.to_string()).collect(), c))
+            .collect();
+        let budget = self.config.vocab_size.saturating_sub(vocab.len());
+        let merges = learn_merges(words, budget, self.config.min_frequency);
+        for (a, b) in &merges {
+            // two merges can spell the same token; it keeps its first id
+            let merged = format!("{}{}", a, b);
+            if known.insert(merged.clone()) {
+                vocab.push(merged);
+            }
+        }
+        TrainedBpe {
+            vocab,
+            merges,
+            special_tokens: self.config.special_tokens.clone(),
+            byte_level: self.config.byte_level,
+            pipeline: self.pipeline.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_follow_pair_frequency() {
+        let words = vec![
+            (vec!["l".into(), "o".into(), "w".into()], 5),
+            (vec!["l".into(), "o".into(), "w".into(), "e".into(), "r".into()], 2),
+            (vec!["n".into(), "e".into(), "w".into(), "e".into(), "s".into(), "t".into()], 6),
+            (vec!["w".into(), "i".into(), "d".into(), "e".into(), "s".into(), "t".into()], 3),
+        ];
+        let merges = learn_merges(words, 4, 1);
+        let expected: Vec<(String, String)> = [("e", "s"), ("es", "t"), ("l", "o"), ("lo", "w")]
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect();
+        assert_eq!(merges, expected);
+    }
+
+    #[test]
+    fn min_frequency_stops_training() {
+        let mut trainer = BpeTrainer::new(BpeTrainerConfig {
+            vocab_size: 100,
+            min_frequency: 3,
+            ..Default::default()
+        });
+        trainer.feed("ab ab ab cd cd");
+        let trained = trainer.train();
+        assert_eq!(trained.merges, vec![("a".to_string(), "b".to_string())]);
+        assert_eq!(trained.vocab, vec!["<unk>", "a", "b", "c", "d", "ab"]);
+    }
+
+    #[test]
+    fn trained_tokenizer_roundtrips() {
+        let mut trainer = BpeTrainer::new(BpeTrainerConfig {
+            vocab_size: 280,
+            min_frequency: 1,
+            special_tokens: vec!["<eos>".into(), "<chunk>".into()],
+            byte_level: true,
+            pipeline: None,
+        });
+        let corpus = "the cat sat on the mat<eos> the hat<chunk> that cat";
+        trainer.feed(corpus);
+        let trained = trainer.train();
+        assert!(!trained.merges.is_empty() && trained.vocab.len() <= 280);
+        assert_eq!(&trained.vocab[..3], ["<unk>", "<eos>", "<chunk>"]);
+        let tok = trained.tokenizer();
+        assert_eq!(tok.special_token_id("<chunk>"), Some(2));
+        assert_eq!(tok.unk_id(), 0);
+        assert!(!tok.is_special(tok.unk_id()));
+        let ids = tok.encode(corpus);
+        assert!(ids.len() < corpus.len() / 2);
+        assert_eq!(tok.decode(&ids), corpus);
+
+        // the legacy files do not record the mode; it is told from the vocabulary
+        let dir = std::env::temp_dir().join(format!("dragon_bpe_legacy_byte_level_{}", std::process::id()));
+        trained.save_legacy(&dir).unwrap();
+        let (vocab, merges) = (dir.join("vocab.txt"), dir.join("merges.txt"));
+        let legacy = crate::tokenizer::load_tokenizer(vocab.to_str().unwrap(), merges.to_str().unwrap(), 0).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        let text = "the cät\tsat<chunk>";
+        assert_eq!(legacy.encode(text), tok.encode(text));
+        assert_eq!(legacy.decode(&legacy.encode(text)), text);
+    }
+
+    #[test]
+    fn pipeline_is_applied_and_saved() {
+        use crate::text_pipeline::{Normalizer, PreTokenizer};
+        let mut trainer = BpeTrainer::new(BpeTrainerConfig {
+            vocab_size: 100,
+            min_frequency: 1,
+            pipeline: Some(TextPipeline {
+                normalizers: vec![Normalizer::Lowercase],
+                pre_tokenizers: vec![PreTokenizer::Whitespace, PreTokenizer::Digits { individual: true }],
+            }),
+            ..Default::default()
+        });
+        trainer.feed("Hello HELLO hello 2024 2024");
+        let trained = trainer.train();
+        assert!(!trained.vocab.iter().any(|t| t.contains('H') || t.contains("20")));
+        let tok = trained.tokenizer();
+        let id = |t: &str| tok.token_to_id(t).unwrap();
+        assert_eq!(tok.encode("HeLLo 2024"), vec![id("hello"), id("2"), id("0"), id("2"), id("4")]);
+
+        let dir = std::env::temp_dir().join(format!("dragon_bpe_pipeline_test_{}", std::process::id()));
+        trained.save(&dir).unwrap();
+        trained.save_legacy(&dir).unwrap();
+        let load = |vocab: &str, merges: &str| {
+            let (vocab, merges) = (dir.join(vocab), dir.join(merges));
+            crate::tokenizer::load_tokenizer(vocab.to_str().unwrap(), merges.to_str().unwrap(), 0).unwrap()
+        };
+        let single = load(TOKENIZER_FILE, "");
+        let legacy = load("vocab.txt", "merges.txt");
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(single.encode("HeLLo 2024"), tok.encode("HeLLo 2024"));
+        assert_eq!(legacy.encode("HeLLo 2024"), tok.encode("HeLLo 2024"));
+    }
+}
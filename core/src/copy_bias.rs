@@ -0,0 +1,145 @@
+// Copy-biased decoding.
+// dRAGon should take its content words from the retrieved chunks, so this
+// logits processor favours (or only allows) tokens that appear in the
+// context, plus a whitelist of function words that are always fine. When the
+// tail of the sequence matches a span of the context, the token that follows
+// that span gets an extra boost so multi-token entities are copied whole.
+use std::collections::HashSet;
+
+/// How tokens absent from the context are treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyMode {
+    /// Add the given bias to the logits of allowed tokens.
+    Boost(f32),
+    /// Mask every token that is neither in the context nor whitelisted.
+    /// Logits are left unmasked when none of the allowed tokens is in the
+    /// vocabulary, since masking everything would leave nothing to sample.
+    Restrict,
+}
+
+/// Logits processor biasing generation toward tokens of the retrieved
+/// context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyBias {
+    context: Vec<usize>,
+    allowed: HashSet<usize>,
+    /// Treatment of tokens outside the context.
+    pub mode: CopyMode,
+    /// Bias added to the token continuing a span copied from the context.
+    pub ngram_boost: f32,
+    /// Longest sequence suffix matched against the context.
+    pub max_ngram: usize,
+}
+
+impl CopyBias {
+    /// Builds a processor for `context`. Tokens in `whitelist` (function
+    /// words, punctuation, EOS, ...) are always allowed.
+    pub fn new(context: &[usize], whitelist: &[usize], mode: CopyMode) -> Self {
+        let allowed = context.iter().chain(whitelist).copied().collect();
+        Self {
+            context: context.to_vec(),
+            allowed,
+            mode,
+            ngram_boost: 2.0,
+            max_ngram: 4,
+        }
+    }
+
+    /// Returns `true` if `token` may be generated without penalty.
+    pub fn is_allowed(&self, token: usize) -> bool {
+        self.allowed.contains(&token)
+    }
+
+    /// Tokens following the longest suffix of `sequence` (up to
+    /// `max_ngram` tokens) that also occurs in the context.
+    pub fn continuations(&self, sequence: &[usize]) -> Vec<usize> {
+        let longest = self.max_ngram.min(sequence.len()).min(self.context.len());
+        for n in (1..=longest).rev() {
+            let suffix = &sequence[sequence.len() - n..];
+            let mut next: Vec<usize> = self
+                .context
+                .windows(n + 1)
+                .filter(|w| &w[..n] == suffix)
+                .map(|w| w[n])
+                .collect();
+            if !next.is_empty() {
+                next.sort_unstable();
+                next.dedup();
+                return next;
+            }
+        }
+        Vec::new()
+    }
+
+    /// Adjusts the next-token `logits` given the `sequence` generated so far
+    /// (prompt included).
+    pub fn apply(&self, sequence: &[usize], logits: &mut [f32]) {
+        let restrict = self.mode == CopyMode::Restrict && self.allowed.iter().any(|&t| t < logits.len());
+        for (token, logit) in logits.iter_mut().enumerate() {
+            let allowed = self.allowed.contains(&token);
+            match self.mode {
+                CopyMode::Boost(bias) if allowed => *logit += bias,
+                CopyMode::Restrict if restrict && !allowed => *logit = f32::NEG_INFINITY,
+                _ => {}
+            }
+        }
+        if self.ngram_boost != 0.0 {
+            for token in self.continuations(sequence) {
+                if let Some(logit) = logits.get_mut(token) {
+                    *logit += self.ngram_boost;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restrict_masks_unseen_tokens() {
+        let bias = CopyBias::new(&[2, 3], &[0], CopyMode::Restrict);
+        let mut logits = vec![1.0; 5];
+        bias.apply(&[], &mut logits);
+        assert_eq!(logits[0], 1.0);
+        assert_eq!(logits[1], f32::NEG_INFINITY);
+        assert_eq!(logits[2], 1.0);
+        assert_eq!(logits[4], f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn restrict_without_allowed_tokens_keeps_logits() {
+        // every allowed id is out of vocabulary, so masking would leave -inf
+        // everywhere and softmax would produce NaN
+        let bias = CopyBias::new(&[7, 8], &[], CopyMode::Restrict);
+        let mut logits = vec![0.5, 1.0, -1.0];
+        bias.apply(&[], &mut logits);
+        assert_eq!(logits, vec![0.5, 1.0, -1.0]);
+        let empty = CopyBias::new(&[], &[], CopyMode::Restrict);
+        empty.apply(&[], &mut logits);
+        assert!(logits.iter().all(|l| l.is_finite()));
+    }
+
+    #[test]
+    fn boost_raises_context_tokens() {
+        let mut bias = CopyBias::new(&[2], &[], CopyMode::Boost(0.5));
+        bias.ngram_boost = 0.0;
+        let mut logits = vec![0.0; 3];
+        bias.apply(&[1], &mut logits);
+        assert_eq!(logits, vec![0.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn longest_match_picks_continuation() {
+        let bias = CopyBias::new(&[5, 6, 7, 9, 6, 8], &[], CopyMode::Boost(0.0));
+        // "6" alone is followed by 7 and 8, "5 6" only by 7
+        assert_eq!(bias.continuations(&[1, 6]), vec![7, 8]);
+        assert_eq!(bias.continuations(&[5, 6]), vec![7]);
+        assert!(bias.continuations(&[4]).is_empty());
+        let mut logits = vec![0.0; 10];
+        bias.apply(&[5, 6], &mut logits);
+        assert_eq!(logits[7], 2.0);
+        assert_eq!(logits[8], 0.0);
+    }
+}
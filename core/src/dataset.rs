@@ -3,26 +3,30 @@ use std::io::{self, BufRead, BufReader};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
-use crate::tokenizer::WhitespaceTokenizer;
+use crate::tokenizer::Tokenizer;
 
 /// Streaming text dataset backed by a file.
 ///
 /// The loader reads the corpus line by line and converts each line
-/// into token id sequences using the provided [`WhitespaceTokenizer`].
+/// into token id sequences using the provided [`Tokenizer`].
 /// This avoids loading the entire dataset into memory and is suitable
 /// for large text corpora.
 pub struct TextDataset {
     reader: BufReader<File>,
-    tokenizer: WhitespaceTokenizer,
+    tokenizer: Box<dyn Tokenizer>,
 }
 
 impl TextDataset {
     /// Opens the dataset at `path` with the given tokenizer.
-    pub fn open<P: AsRef<std::path::Path>>(path: P, tokenizer: WhitespaceTokenizer) -> io::Result<Self> {
+    pub fn open<P, T>(path: P, tokenizer: T) -> io::Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+        T: Tokenizer + 'static,
+    {
         let file = File::open(path)?;
         Ok(Self {
             reader: BufReader::new(file),
-            tokenizer,
+            tokenizer: Box::new(tokenizer),
         })
     }
 
@@ -47,6 +51,9 @@ impl TextDataset {
     }
 }
 
+/// A batch of `(inputs, targets)` token id sequences.
+pub type Batch = (Vec<Vec<usize>>, Vec<Vec<usize>>);
+
 /// In-memory dataloader that yields shuffled batches of samples.
 pub struct DataLoader {
     samples: Vec<(Vec<usize>, Vec<usize>)>,
@@ -68,7 +75,7 @@ impl DataLoader {
     }
 
     /// Returns the next batch as `(inputs, targets)` or `None` at end of epoch.
-    pub fn next_batch(&mut self) -> Option<(Vec<Vec<usize>>, Vec<Vec<usize>>)> {
+    pub fn next_batch(&mut self) -> Option<Batch> {
         if self.index >= self.samples.len() {
             return None;
         }
@@ -91,6 +98,7 @@ impl DataLoader {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tokenizer::{BpeTokenizer, WhitespaceTokenizer};
     use std::io::Write;
 
     #[test]
@@ -134,4 +142,21 @@ mod tests {
         assert!(dl.next_batch().is_none());
         let _ = std::fs::remove_file(&path);
     }
+
+    #[test]
+    fn bpe_tokenizer_dataset() {
+        let path = std::env::temp_dir().join("dataset_bpe_test.txt");
+        {
+            let mut f = File::create(&path).unwrap();
+            writeln!(f, "ab<eos>").unwrap();
+        }
+        let vocab = vec!["<unk>".into(), "a".into(), "b".into(), "ab".into()];
+        let mut tok = BpeTokenizer::new(vocab, vec![("a".into(), "b".into())], 0);
+        let eos = tok.add_special_token("<eos>");
+        let mut ds = TextDataset::open(&path, tok).unwrap();
+        let (input, target) = ds.next_sample().unwrap().unwrap();
+        assert_eq!(input, vec![3]);
+        assert_eq!(target, vec![eos]);
+        let _ = std::fs::remove_file(&path);
+    }
 }
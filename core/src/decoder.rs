@@ -1,6 +1,8 @@
 use crate::attention::MultiHeadAttention;
 use crate::feedforward::FeedForward;
 use crate::layernorm::LayerNorm;
+use crate::hooks::ForwardObserver;
+use crate::kv_cache::LayerKv;
 
 /// Simplified decoder block combining self-attention and feedforward layers.
 ///
@@ -31,6 +33,49 @@ impl DecoderBlock {
         let norm2 = self.ln2.forward(&attn_out);
         self.feedforward.forward(&norm2)
     }
+
+    /// Runs the block on new positions, reading and extending `cache`.
+    pub fn forward_cached(&self, input: &[Vec<f32>], cache: &mut LayerKv) -> Vec<Vec<f32>> {
+        let norm1 = self.ln1.forward(input);
+        let attn_out = self.self_attn.forward_cached(&norm1, cache);
+        let norm2 = self.ln2.forward(&attn_out);
+        self.feedforward.forward(&norm2)
+    }
+
+    /// Runs the block on the stacked new rows of several sequences; see
+    /// [`MultiHeadAttention::forward_batch_cached`].
+    ///
+    /// [`MultiHeadAttention::forward_batch_cached`]: crate::attention::MultiHeadAttention::forward_batch_cached
+    pub fn forward_batch_cached(
+        &self,
+        input: &[Vec<f32>],
+        lens: &[usize],
+        caches: &mut [&mut LayerKv],
+    ) -> Vec<Vec<f32>> {
+        let norm1 = self.ln1.forward(input);
+        let attn_out = self.self_attn.forward_batch_cached(&norm1, lens, caches);
+        let norm2 = self.ln2.forward(&attn_out);
+        self.feedforward.forward(&norm2)
+    }
+
+    /// Runs the block while reporting its activations to `observer` as
+    /// layer `layer`.
+    pub fn forward_observed(
+        &self,
+        input: &[Vec<f32>],
+        layer: usize,
+        observer: &mut dyn ForwardObserver,
+    ) -> Vec<Vec<f32>> {
+        observer.on_block_input(layer, input);
+        let norm1 = self.ln1.forward(input);
+        let (attn_out, weights) = self.self_attn.forward_with_weights(&norm1);
+        observer.on_attention(layer, &weights);
+        observer.on_attention_output(layer, &attn_out);
+        let norm2 = self.ln2.forward(&attn_out);
+        let output = self.feedforward.forward(&norm2);
+        observer.on_block_output(layer, &output);
+        output
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,131 @@
+use crate::model::Model;
+use crate::sampling;
+
+/// Decoding strategy used by [`Model::generate_with`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum DecodingMode {
+    /// Sample from the model distribution at the configured temperature;
+    /// greedy when the temperature is zero.
+    #[default]
+    Sample,
+    /// Contrastive search: among the `top_k` most likely tokens pick the one
+    /// maximizing `(1 - alpha) * p(v) - alpha * max_j cos(h_v, h_j)`, where
+    /// `h_v` is the hidden state the candidate would produce and `h_j` are
+    /// the hidden states of the sequence so far. Penalizes degenerate
+    /// repetition.
+    Contrastive { top_k: usize, alpha: f32 },
+    /// Context-aware decoding: contrasts the logits with and without the
+    /// retrieved chunks at prompt positions `context_start..context_end`,
+    /// using `(1 + alpha) * with - alpha * without`, then samples at the
+    /// configured temperature. Amplifies the influence of the context.
+    ContextAware {
+        context_start: usize,
+        context_end: usize,
+        alpha: f32,
+    },
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let na = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+impl Model {
+    /// Picks the next token by contrastive search and returns it together
+    /// with the model's logits for the current position.
+    pub fn contrastive_step(&self, tokens: &[usize], top_k: usize, alpha: f32) -> (usize, Vec<f32>) {
+        let states = self.hidden_states(tokens, None);
+        let last = &states[states.len() - 1..];
+        let logits = self.output_layer.forward(last).remove(0);
+        let probs = sampling::probabilities(&logits, 1.0);
+        let mut best = (sampling::argmax(&probs), f32::NEG_INFINITY);
+        let mut extended = tokens.to_vec();
+        for candidate in sampling::top_n(&probs, top_k.max(1)) {
+            extended.push(candidate);
+            let candidate_states = self.hidden_states(&extended, None);
+            extended.pop();
+            let h = &candidate_states[candidate_states.len() - 1];
+            let degeneration = states
+                .iter()
+                .map(|s| cosine(h, s))
+                .fold(f32::NEG_INFINITY, f32::max);
+            let score = (1.0 - alpha) * probs[candidate] - alpha * degeneration;
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+        (best.0, logits)
+    }
+
+    /// Returns the context-aware logits `(1 + alpha) * with - alpha * without`
+    /// for the next position, where `without` drops `tokens[start..end]`,
+    /// alongside the plain logits computed with the context.
+    pub fn context_aware_logits(&self, tokens: &[usize], start: usize, end: usize, alpha: f32) -> (Vec<f32>, Vec<f32>) {
+        let end = end.min(tokens.len());
+        let start = start.min(end);
+        let with = self.forward(tokens).pop().unwrap_or_default();
+        let mut stripped = tokens[..start].to_vec();
+        stripped.extend_from_slice(&tokens[end..]);
+        if stripped.is_empty() {
+            return (with.clone(), with);
+        }
+        let without = self.forward(&stripped).pop().unwrap_or_default();
+        let adjusted = with
+            .iter()
+            .zip(&without)
+            .map(|(w, wo)| (1.0 + alpha) * w - alpha * wo)
+            .collect();
+        (adjusted, with)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::GenerationConfig;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn contrastive_without_penalty_is_greedy() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let config = GenerationConfig {
+            max_tokens: 4,
+            decoding: DecodingMode::Contrastive { top_k: 3, alpha: 0.0 },
+            ..Default::default()
+        };
+        let out = model.generate_with(&[1, 2], &config, &mut rng);
+        assert_eq!(out.tokens, model.generate(&[1, 2], 4));
+    }
+
+    #[test]
+    fn contrastive_stays_within_top_k() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let tokens = [3usize, 4];
+        let logits = model.forward(&tokens).pop().unwrap();
+        let allowed = sampling::top_n(&logits, 2);
+        let (next, plain) = model.contrastive_step(&tokens, 2, 0.9);
+        assert!(allowed.contains(&next));
+        assert_eq!(plain, logits);
+    }
+
+    #[test]
+    fn context_aware_amplifies_difference() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let tokens = [1usize, 2, 3];
+        let (zero, with) = model.context_aware_logits(&tokens, 0, 2, 0.0);
+        assert_eq!(zero, with);
+        let without = model.forward(&[3]).pop().unwrap();
+        let (adjusted, _) = model.context_aware_logits(&tokens, 0, 2, 1.0);
+        for i in 0..6 {
+            assert!((adjusted[i] - (2.0 * with[i] - without[i])).abs() < 1e-5);
+        }
+    }
+}
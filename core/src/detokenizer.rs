@@ -0,0 +1,131 @@
+// Incremental detokenization for streaming output.
+// Tokens arrive one at a time and a multi-byte character may be split across
+// several byte tokens, so decoding each token on its own can emit broken
+// characters. `IncrementalDecoder` buffers bytes and only emits text once it
+// is complete UTF-8, so the concatenated deltas equal `BpeTokenizer::decode`
+// of the whole sequence.
+use crate::tokenizer::BpeTokenizer;
+
+/// Stateful decoder turning a stream of token ids into stable text deltas.
+pub struct IncrementalDecoder<'a> {
+    tokenizer: &'a BpeTokenizer,
+    pending: Vec<u8>,
+    started: bool,
+}
+
+impl<'a> IncrementalDecoder<'a> {
+    /// Creates a decoder for a sequence starting at the beginning of the
+    /// output.
+    pub fn new(tokenizer: &'a BpeTokenizer) -> Self {
+        Self {
+            tokenizer,
+            pending: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Returns `true` while bytes of an incomplete character are buffered.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Adds `token` and returns the text that became final. The result is
+    /// empty while a character is still incomplete.
+    pub fn push(&mut self, token: usize) -> String {
+        self.pending.extend(self.tokenizer.token_bytes(token));
+        let mut out = String::new();
+        loop {
+            match std::str::from_utf8(&self.pending) {
+                Ok(text) => {
+                    out.push_str(text);
+                    self.pending.clear();
+                    break;
+                }
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    // valid prefix is checked above, so this cannot fail
+                    out.push_str(std::str::from_utf8(&self.pending[..valid]).unwrap_or_default());
+                    match e.error_len() {
+                        // invalid sequence: emit a replacement and carry on
+                        Some(len) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            self.pending.drain(..valid + len);
+                        }
+                        // incomplete character at the end: wait for more bytes
+                        None => {
+                            self.pending.drain(..valid);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.emit(out)
+    }
+
+    /// Flushes any buffered bytes, replacing an incomplete character with
+    /// `U+FFFD`.
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        self.emit(text)
+    }
+
+    // drops the space implied by a word-boundary marker at the very start;
+    // byte-level tokens carry real spaces, so they are kept there
+    fn emit(&mut self, text: String) -> String {
+        if self.started || text.is_empty() || self.tokenizer.is_byte_level() {
+            return text;
+        }
+        self.started = true;
+        match text.strip_prefix(' ') {
+            Some(rest) => rest.to_string(),
+            None => text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenizer() -> BpeTokenizer {
+        let vocab = ["<unk>", "Ġhello", "Ġw", "orld", "<0xC3>", "<0xA9>", "▁caf", "<0xE2>", "<0x82>", "<0xAC>"];
+        BpeTokenizer::new(vocab.iter().map(|s| s.to_string()).collect(), Vec::new(), 0)
+    }
+
+    #[test]
+    fn spaces_follow_word_markers() {
+        let tok = tokenizer();
+        let mut dec = IncrementalDecoder::new(&tok);
+        let deltas: Vec<String> = [1, 2, 3].iter().map(|&t| dec.push(t)).collect();
+        assert_eq!(deltas, ["hello", " w", "orld"]);
+        assert_eq!(tok.decode(&[1, 2, 3]), "hello world");
+    }
+
+    #[test]
+    fn buffers_split_characters() {
+        let tok = tokenizer();
+        let mut dec = IncrementalDecoder::new(&tok);
+        assert_eq!(dec.push(6), "caf");
+        assert_eq!(dec.push(4), "");
+        assert!(dec.has_pending());
+        assert_eq!(dec.push(5), "é");
+        assert_eq!(dec.push(7), "");
+        assert_eq!(dec.push(8), "");
+        assert_eq!(dec.push(9), "€");
+        assert_eq!(tok.decode(&[6, 4, 5, 7, 8, 9]), "café€");
+    }
+
+    #[test]
+    fn deltas_match_full_decode() {
+        let tok = tokenizer();
+        let seqs: [&[usize]; 3] = [&[4, 1, 5, 5, 7], &[7, 8], &[5, 2, 4, 5, 3, 0]];
+        for seq in seqs {
+            let mut dec = IncrementalDecoder::new(&tok);
+            let mut text: String = seq.iter().map(|&t| dec.push(t)).collect();
+            text.push_str(&dec.finish());
+            assert_eq!(text, tok.decode(seq), "sequence {:?}", seq);
+        }
+    }
+}
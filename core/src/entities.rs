@@ -0,0 +1,437 @@
+// Entity placeholder substitution.
+// Following the entity-randomization idea from the training strategy, names,
+// numbers, dates and quoted strings in the retrieved chunks are swapped for
+// placeholder tokens before generation so the model only has to copy a
+// placeholder instead of spelling a rare name. The originals are restored in
+// the decoded output.
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::model::Model;
+use crate::tokenizer::BpeTokenizer;
+
+/// Kind of entity-like span found by [`detect_entities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    /// A run of capitalized words, e.g. `Ada Lovelace`.
+    Capitalized,
+    /// A number such as `42`, `-3.5` or `1,000`.
+    Number,
+    /// A date such as `2024-01-31`, `31/01/2024` or `January 31, 2024`.
+    Date,
+    /// A double-quoted string, quotes included.
+    Quoted,
+}
+
+/// An entity-like span of the input text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntitySpan {
+    /// Byte range in the input text.
+    pub range: Range<usize>,
+    pub kind: EntityKind,
+}
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October",
+    "November", "December",
+];
+
+// capitalized words that usually just start a sentence
+const SENTENCE_STARTERS: [&str; 24] = [
+    "A", "An", "The", "This", "That", "These", "Those", "It", "Its", "I", "He", "She", "We", "They",
+    "You", "In", "On", "At", "As", "If", "When", "But", "And", "There",
+];
+
+struct Word {
+    range: Range<usize>,
+    // punctuation was trimmed from the end, so no entity continues past it
+    breaks: bool,
+    sentence_start: bool,
+}
+
+fn is_month(word: &str) -> bool {
+    MONTHS
+        .iter()
+        .any(|m| word == *m || (word.len() == 3 && m.starts_with(word)))
+}
+
+fn is_number(word: &str) -> bool {
+    let body = word.strip_prefix(['-', '+', '$']).unwrap_or(word);
+    let body = body.strip_suffix('%').unwrap_or(body);
+    body.starts_with(|c: char| c.is_ascii_digit())
+        && body.ends_with(|c: char| c.is_ascii_digit())
+        && body.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '.')
+}
+
+fn is_numeric_date(word: &str) -> bool {
+    for sep in ['-', '/'] {
+        let parts: Vec<&str> = word.split(sep).collect();
+        if parts.len() == 3
+            && parts
+                .iter()
+                .all(|p| !p.is_empty() && p.len() <= 4 && p.chars().all(|c| c.is_ascii_digit()))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+fn is_day_or_year(word: &str) -> bool {
+    matches!(word.len(), 1 | 2 | 4) && word.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_capitalized(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_uppercase())
+}
+
+fn split_words(text: &str, skip: &[Range<usize>]) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut sentence_start = true;
+    let mut pos = 0;
+    for piece in text.split_whitespace() {
+        let start = pos + text[pos..].find(piece).unwrap_or(0);
+        pos = start + piece.len();
+        if skip.iter().any(|r| r.start < pos && start < r.end) {
+            sentence_start = false;
+            continue;
+        }
+        let lead = piece.len() - piece.trim_start_matches(['(', '[', '{']).len();
+        let trimmed = piece.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '}']);
+        let ends_sentence = piece.ends_with(['.', '!', '?']);
+        if trimmed.len() > lead {
+            words.push(Word {
+                range: start + lead..start + trimmed.len(),
+                breaks: trimmed.len() < piece.len(),
+                sentence_start,
+            });
+        }
+        sentence_start = ends_sentence;
+    }
+    words
+}
+
+/// Finds entity-like spans in `text`: double-quoted strings, dates, numbers
+/// and runs of capitalized words. Spans are sorted and never overlap. A
+/// capitalized function word at the start of a sentence or of a run is not
+/// part of an entity, and neither is the pronoun "I".
+pub fn detect_entities(text: &str) -> Vec<EntitySpan> {
+    let mut spans = Vec::new();
+    let mut open: Option<usize> = None;
+    for (i, c) in text.char_indices() {
+        match (c, open) {
+            ('"' | '\u{201c}', None) => open = Some(i),
+            ('"' | '\u{201d}', Some(start)) => {
+                spans.push(EntitySpan {
+                    range: start..i + c.len_utf8(),
+                    kind: EntityKind::Quoted,
+                });
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    let quoted: Vec<Range<usize>> = spans.iter().map(|s| s.range.clone()).collect();
+    let words = split_words(text, &quoted);
+    let word = |i: usize| &text[words[i].range.clone()];
+
+    let mut i = 0;
+    while i < words.len() {
+        let w = word(i);
+        // number of words the entity starting here covers
+        let mut date_len = 0;
+        if is_numeric_date(w) {
+            date_len = 1;
+        } else if is_month(w) && i + 1 < words.len() && !words[i].breaks && is_day_or_year(word(i + 1)) {
+            // "March 5, 2020" or "March 2020"
+            date_len = 2;
+            if word(i + 1).len() <= 2 && i + 2 < words.len() && word(i + 2).len() == 4 && is_day_or_year(word(i + 2)) {
+                date_len = 3;
+            }
+        } else if w.len() <= 2
+            && is_day_or_year(w)
+            && !words[i].breaks
+            && i + 1 < words.len()
+            && is_month(word(i + 1))
+        {
+            // "5 March" or "5 March 2020"
+            date_len = 2;
+            if !words[i + 1].breaks && i + 2 < words.len() && word(i + 2).len() == 4 && is_day_or_year(word(i + 2)) {
+                date_len = 3;
+            }
+        }
+        let (len, kind) = if date_len > 0 {
+            (date_len, EntityKind::Date)
+        } else if is_number(w) {
+            (1, EntityKind::Number)
+        } else if is_capitalized(w) {
+            let mut len = 1;
+            while !words[i + len - 1].breaks && i + len < words.len() && is_capitalized(word(i + len)) && word(i + len) != "I" {
+                len += 1;
+            }
+            // a starter opening a run ("In Paris") belongs to the sentence,
+            // not to the name
+            if SENTENCE_STARTERS.contains(&w) && (len > 1 || words[i].sentence_start) {
+                i += 1;
+                len -= 1;
+            }
+            if len == 0 {
+                continue;
+            }
+            if len == 1 && word(i) == "I" {
+                i += 1;
+                continue;
+            }
+            (len, EntityKind::Capitalized)
+        } else {
+            i += 1;
+            continue;
+        };
+        spans.push(EntitySpan {
+            range: words[i].range.start..words[i + len - 1].range.end,
+            kind,
+        });
+        i += len;
+    }
+    spans.sort_by_key(|s| s.range.start);
+    spans
+}
+
+/// Replaces entities with numbered placeholders and restores them afterwards.
+///
+/// Equal strings share a placeholder, so the same name in two chunks and in
+/// the query maps to one token. Placeholder `i` is written as `<ent_i>` in
+/// text and registered as a special token of the tokenizer, which assigns
+/// its id. Entities beyond the number of placeholders are left as is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntityMasker {
+    ids: Vec<usize>,
+    entities: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl EntityMasker {
+    /// Creates a masker with `capacity` placeholders, registering
+    /// `<ent_0>` to `<ent_{capacity - 1}>` as special tokens of `tokenizer`.
+    pub fn new(tokenizer: &mut BpeTokenizer, capacity: usize) -> Self {
+        Self {
+            ids: (0..capacity)
+                .map(|i| tokenizer.add_special_token(&Self::placeholder(i)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Original strings in placeholder order.
+    pub fn entities(&self) -> &[String] {
+        &self.entities
+    }
+
+    /// Text form of placeholder `index`.
+    pub fn placeholder(index: usize) -> String {
+        format!("<ent_{}>", index)
+    }
+
+    /// Token id of placeholder `index`.
+    pub fn placeholder_id(&self, index: usize) -> usize {
+        self.ids[index]
+    }
+
+    fn intern(&mut self, entity: &str) -> Option<usize> {
+        if let Some(&idx) = self.index.get(entity) {
+            return Some(idx);
+        }
+        if self.entities.len() >= self.ids.len() {
+            return None;
+        }
+        let idx = self.entities.len();
+        self.entities.push(entity.to_string());
+        self.index.insert(entity.to_string(), idx);
+        Some(idx)
+    }
+
+    // splits `text` into plain text and placeholder indices
+    fn segments(&mut self, text: &str) -> Vec<Result<usize, String>> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        for span in detect_entities(text) {
+            if let Some(idx) = self.intern(&text[span.range.clone()]) {
+                out.push(Err(text[pos..span.range.start].to_string()));
+                out.push(Ok(idx));
+                pos = span.range.end;
+            }
+        }
+        out.push(Err(text[pos..].to_string()));
+        out
+    }
+
+    /// Replaces every entity in `text` with its `<ent_i>` placeholder.
+    pub fn mask_text(&mut self, text: &str) -> String {
+        self.segments(text)
+            .into_iter()
+            .map(|s| s.map(Self::placeholder).unwrap_or_else(|t| t))
+            .collect()
+    }
+
+    /// Replaces `<ent_i>` placeholders in `text` with the original strings.
+    pub fn restore_text(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("<ent_") {
+            out.push_str(&rest[..start]);
+            let tail = &rest[start + 5..];
+            let digits = tail.len() - tail.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let entity = tail[..digits]
+                .parse::<usize>()
+                .ok()
+                .filter(|_| tail[digits..].starts_with('>'))
+                .and_then(|idx| self.entities.get(idx));
+            match entity {
+                Some(entity) => {
+                    out.push_str(entity);
+                    rest = &tail[digits + 1..];
+                }
+                None => {
+                    out.push_str("<ent_");
+                    rest = tail;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Encodes `text` with `tokenizer`, emitting placeholder ids for entities.
+    pub fn encode(&mut self, tokenizer: &BpeTokenizer, text: &str) -> Vec<usize> {
+        let mut ids = Vec::new();
+        for segment in self.segments(text) {
+            match segment {
+                Ok(idx) => ids.push(self.placeholder_id(idx)),
+                Err(plain) => ids.extend(tokenizer.encode(&plain)),
+            }
+        }
+        ids
+    }
+
+    /// Decodes `ids` with `tokenizer`, substituting placeholder ids with the
+    /// original strings.
+    pub fn decode(&self, tokenizer: &BpeTokenizer, ids: &[usize]) -> String {
+        let mut out = String::new();
+        let mut run = Vec::new();
+        for &id in ids {
+            match self
+                .ids
+                .iter()
+                .position(|&p| p == id)
+                .and_then(|idx| self.entities.get(idx))
+            {
+                Some(entity) => {
+                    out.push_str(&tokenizer.decode(&run));
+                    run.clear();
+                    out.push_str(entity);
+                }
+                None => run.push(id),
+            }
+        }
+        out.push_str(&tokenizer.decode(&run));
+        out
+    }
+}
+
+impl Model {
+    /// Greedily continues `prompt` for `steps` tokens with its entities
+    /// replaced by placeholders and returns the decoded continuation with the
+    /// original strings restored.
+    pub fn generate_masked(
+        &self,
+        tokenizer: &BpeTokenizer,
+        masker: &mut EntityMasker,
+        prompt: &str,
+        steps: usize,
+    ) -> String {
+        let input = masker.encode(tokenizer, prompt);
+        let output = self.generate(&input, steps);
+        masker.decode(tokenizer, &output[input.len()..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenizer() -> BpeTokenizer {
+        let vocab: Vec<String> = ["<unk>", "a", "b"].iter().map(|s| s.to_string()).collect();
+        BpeTokenizer::new(vocab, Vec::new(), 0)
+    }
+
+    fn kinds(text: &str) -> Vec<(&str, EntityKind)> {
+        detect_entities(text)
+            .into_iter()
+            .map(|s| (&text[s.range], s.kind))
+            .collect()
+    }
+
+    #[test]
+    fn detects_entity_kinds() {
+        let text = "The paper by Ada Lovelace, published March 5, 2020, cost $1,200 and was titled \"On Engines\" (see 2021-04-01).";
+        assert_eq!(
+            kinds(text),
+            vec![
+                ("Ada Lovelace", EntityKind::Capitalized),
+                ("March 5, 2020", EntityKind::Date),
+                ("$1,200", EntityKind::Number),
+                ("\"On Engines\"", EntityKind::Quoted),
+                ("2021-04-01", EntityKind::Date),
+            ]
+        );
+        assert_eq!(kinds("Paris is big. It rains."), vec![("Paris", EntityKind::Capitalized)]);
+    }
+
+    #[test]
+    fn pronoun_i_is_never_an_entity() {
+        assert_eq!(kinds("We met Ada and I left."), vec![("Ada", EntityKind::Capitalized)]);
+        assert_eq!(kinds("We rest on Monday I think."), vec![("Monday", EntityKind::Capitalized)]);
+        assert!(kinds("I think so.").is_empty());
+    }
+
+    #[test]
+    fn leading_starter_is_stripped() {
+        assert_eq!(kinds("In Paris it rained."), vec![("Paris", EntityKind::Capitalized)]);
+        assert_eq!(
+            kinds("We flew there. The Eiffel Tower was closed."),
+            vec![("Eiffel Tower", EntityKind::Capitalized)]
+        );
+        assert!(kinds("When I left, it rained.").is_empty());
+    }
+
+    #[test]
+    fn mask_and_restore_text() {
+        let mut masker = EntityMasker::new(&mut tokenizer(), 8);
+        let masked = masker.mask_text("Alan Turing met Alan Turing in 1936.");
+        assert_eq!(masked, "<ent_0> met <ent_0> in <ent_1>.");
+        assert_eq!(masker.entities(), ["Alan Turing", "1936"]);
+        assert_eq!(masker.restore_text("<ent_1>: <ent_0> <ent_7>"), "1936: Alan Turing <ent_7>");
+    }
+
+    #[test]
+    fn capacity_limits_placeholders() {
+        let mut masker = EntityMasker::new(&mut tokenizer(), 1);
+        assert_eq!(masker.mask_text("from Oslo to Bergen"), "from <ent_0> to Bergen");
+    }
+
+    #[test]
+    fn token_roundtrip() {
+        let mut tok = tokenizer();
+        let mut masker = EntityMasker::new(&mut tok, 4);
+        assert_eq!(tok.special_token_id("<ent_0>"), Some(3));
+        assert_eq!(tok.special_token_id("<ent_3>"), Some(6));
+        let ids = masker.encode(&tok, "a Zed b 42");
+        assert_eq!(ids, vec![1, 3, 2, 4]);
+        assert_eq!(masker.decode(&tok, &ids), "aZedb42");
+
+        let model = Model::new(7, 6, 4, 1, 1);
+        let out = model.generate_masked(&tok, &mut masker, "a Zed", 2);
+        let expected = model.generate(&[1, 3], 2);
+        assert_eq!(out, masker.decode(&tok, &expected[2..]));
+    }
+}
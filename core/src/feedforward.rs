@@ -55,7 +55,7 @@ mod tests {
         let layer = FeedForward::new(2, 2);
         let input = vec![vec![0.5f32, -0.5]];
         let output = layer.forward(&input);
-        let expected = vec![vec![gelu(0.5), gelu(-0.5)]];
+        let expected = [[gelu(0.5), gelu(-0.5)]];
         assert!((output[0][0] - expected[0][0]).abs() < 1e-5);
         assert!((output[0][1] - expected[0][1]).abs() < 1e-5);
     }
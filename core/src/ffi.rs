@@ -1,7 +1,9 @@
-use std::os::raw::{c_char, c_ulong};
+use std::os::raw::{c_char, c_float, c_long, c_ulong};
 use std::ffi::CStr;
-use crate::tokenizer::BpeTokenizer;
+use crate::tokenizer::{load_tokenizer, Tokenizer};
 use crate::model::Model;
+use crate::generation::GenerationConfig;
+use crate::pooling::{Pooling, PoolingConfig};
 
 /// Opaque handle wrapping a `Model` for FFI usage.
 #[repr(C)]
@@ -15,16 +17,19 @@ pub extern "C" fn dragon_model_create(
     embed_dim: c_ulong,
     hidden_dim: c_ulong,
     num_layers: c_ulong,
+    num_heads: c_ulong,
 ) -> *mut ModelHandle {
     let model = Model::new(
         vocab_size as usize,
         embed_dim as usize,
         hidden_dim as usize,
         num_layers as usize,
+        num_heads as usize,
     );
     Box::into_raw(Box::new(ModelHandle { model }))
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_model_free(handle: *mut ModelHandle) {
     if !handle.is_null() {
@@ -34,6 +39,7 @@ pub extern "C" fn dragon_model_free(handle: *mut ModelHandle) {
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_model_generate(
     handle: *mut ModelHandle,
@@ -56,6 +62,7 @@ pub extern "C" fn dragon_model_generate(
     result.len() as c_ulong
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_model_save(handle: *mut ModelHandle, path: *const c_char) -> bool {
     if handle.is_null() || path.is_null() {
@@ -70,6 +77,7 @@ pub extern "C" fn dragon_model_save(handle: *mut ModelHandle, path: *const c_cha
     model.save_safetensors(path_str).is_ok()
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_model_load(path: *const c_char) -> *mut ModelHandle {
     if path.is_null() {
@@ -85,6 +93,7 @@ pub extern "C" fn dragon_model_load(path: *const c_char) -> *mut ModelHandle {
         Err(_) => std::ptr::null_mut(),
     }
 }
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_model_generate_inplace(
     handle: *mut ModelHandle,
@@ -106,7 +115,7 @@ pub extern "C" fn dragon_model_generate_inplace(
             let next = last
                 .iter()
                 .enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+                .max_by(|a, b| a.1.total_cmp(b.1))
                 .map(|(i, _)| i)
                 .unwrap_or(0);
             buf[current] = next;
@@ -116,13 +125,178 @@ pub extern "C" fn dragon_model_generate_inplace(
     current as c_ulong
 }
 
+/// Generates `steps` tokens and reports per-token log-probabilities.
+///
+/// `out_tokens` and `out_logprobs` must hold `steps` entries while
+/// `out_top_ids` and `out_top_logprobs` must hold `steps * top_n` entries.
+/// Alternatives beyond the vocabulary size are padded with id `0` and a
+/// log-probability of negative infinity. Returns the number of generated tokens.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_model_generate_logprobs(
+    handle: *mut ModelHandle,
+    tokens_ptr: *const c_ulong,
+    len: c_ulong,
+    steps: c_ulong,
+    temperature: c_float,
+    top_n: c_ulong,
+    out_tokens: *mut c_ulong,
+    out_logprobs: *mut c_float,
+    out_top_ids: *mut c_ulong,
+    out_top_logprobs: *mut c_float,
+) -> c_ulong {
+    if handle.is_null() || tokens_ptr.is_null() || out_tokens.is_null() || out_logprobs.is_null() {
+        return 0;
+    }
+    let top_n = top_n as usize;
+    if top_n > 0 && (out_top_ids.is_null() || out_top_logprobs.is_null()) {
+        return 0;
+    }
+    let model = unsafe { &(*handle).model };
+    let raw = unsafe { std::slice::from_raw_parts(tokens_ptr, len as usize) };
+    let tokens: Vec<usize> = raw.iter().map(|&v| v as usize).collect();
+    let config = GenerationConfig {
+        max_tokens: steps as usize,
+        temperature,
+        top_logprobs: top_n,
+        ..Default::default()
+    };
+    let output = model.generate_with(&tokens, &config, &mut rand::thread_rng());
+    let count = output.generated.len();
+    unsafe {
+        let ids = std::slice::from_raw_parts_mut(out_tokens, count);
+        let logprobs = std::slice::from_raw_parts_mut(out_logprobs, count);
+        for (i, tok) in output.generated.iter().enumerate() {
+            ids[i] = tok.token as c_ulong;
+            logprobs[i] = tok.logprob;
+        }
+        if top_n > 0 {
+            let top_ids = std::slice::from_raw_parts_mut(out_top_ids, count * top_n);
+            let top_logprobs = std::slice::from_raw_parts_mut(out_top_logprobs, count * top_n);
+            for (i, tok) in output.generated.iter().enumerate() {
+                for j in 0..top_n {
+                    let slot = i * top_n + j;
+                    match tok.top.get(j) {
+                        Some(alt) => {
+                            top_ids[slot] = alt.token as c_ulong;
+                            top_logprobs[slot] = alt.logprob;
+                        }
+                        None => {
+                            top_ids[slot] = 0;
+                            top_logprobs[slot] = f32::NEG_INFINITY;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    count as c_ulong
+}
 
-/// Opaque handle wrapping a `BpeTokenizer` for FFI usage.
+/// Scores `continuation` given `context` and returns its total log-likelihood.
+///
+/// When `out_logprobs` is not null it receives one log-probability per
+/// continuation token. Returns NaN on invalid arguments.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_model_score(
+    handle: *const ModelHandle,
+    context_ptr: *const c_ulong,
+    context_len: c_ulong,
+    continuation_ptr: *const c_ulong,
+    continuation_len: c_ulong,
+    out_logprobs: *mut c_float,
+) -> c_float {
+    if handle.is_null() || context_ptr.is_null() || context_len == 0 {
+        return f32::NAN;
+    }
+    if continuation_ptr.is_null() && continuation_len > 0 {
+        return f32::NAN;
+    }
+    let model = unsafe { &(*handle).model };
+    let context: Vec<usize> = unsafe { std::slice::from_raw_parts(context_ptr, context_len as usize) }
+        .iter()
+        .map(|&v| v as usize)
+        .collect();
+    let continuation: Vec<usize> = if continuation_len == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(continuation_ptr, continuation_len as usize) }
+            .iter()
+            .map(|&v| v as usize)
+            .collect()
+    };
+    let score = match model.score(&context, &continuation) {
+        Some(score) => score,
+        None => return f32::NAN,
+    };
+    if !out_logprobs.is_null() {
+        let out = unsafe { std::slice::from_raw_parts_mut(out_logprobs, score.token_logprobs.len()) };
+        out.copy_from_slice(&score.token_logprobs);
+    }
+    score.total
+}
+
+/// Writes a pooled embedding of the tokens into `out_ptr`.
+///
+/// `pooling` selects mean (`0`), last-token (`1`) or attention-weighted (`2`)
+/// pooling. A negative `layer` pools the final hidden states. Returns the
+/// number of values written, or `0` if `out_cap` is smaller than the
+/// embedding dimension.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_model_embed(
+    handle: *const ModelHandle,
+    tokens_ptr: *const c_ulong,
+    len: c_ulong,
+    pooling: c_ulong,
+    layer: c_long,
+    normalize: bool,
+    out_ptr: *mut c_float,
+    out_cap: c_ulong,
+) -> c_ulong {
+    if handle.is_null() || out_ptr.is_null() || (tokens_ptr.is_null() && len > 0) {
+        return 0;
+    }
+    let pooling = match pooling {
+        0 => Pooling::Mean,
+        1 => Pooling::LastToken,
+        2 => Pooling::AttentionWeighted,
+        _ => return 0,
+    };
+    let model = unsafe { &(*handle).model };
+    let tokens: Vec<usize> = if len == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(tokens_ptr, len as usize) }
+            .iter()
+            .map(|&v| v as usize)
+            .collect()
+    };
+    let config = PoolingConfig {
+        pooling,
+        layer: if layer < 0 { None } else { Some(layer as usize) },
+        normalize,
+    };
+    let embedding = model.embed(&tokens, &config);
+    if embedding.len() > out_cap as usize {
+        return 0;
+    }
+    let out = unsafe { std::slice::from_raw_parts_mut(out_ptr, embedding.len()) };
+    out.copy_from_slice(&embedding);
+    embedding.len() as c_ulong
+}
+
+/// Opaque handle wrapping a [`Tokenizer`] for FFI usage.
 #[repr(C)]
 pub struct TokenizerHandle {
-    tok: BpeTokenizer,
+    tok: Box<dyn Tokenizer + Send + Sync>,
 }
 
+/// Loads a tokenizer, see [`load_tokenizer`]. `vocab_path` may also name a
+/// tokenizer file, a SentencePiece `.model` or a HuggingFace
+/// `tokenizer.json`, in which case `merges_path` and `unk_id` are ignored.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_tokenizer_create(
     vocab_path: *const c_char,
@@ -142,28 +316,13 @@ pub extern "C" fn dragon_tokenizer_create(
         Ok(s) => s,
         Err(_) => return std::ptr::null_mut(),
     };
-    let vocab_contents = match std::fs::read_to_string(vocab_str) {
-        Ok(c) => c,
-        Err(_) => return std::ptr::null_mut(),
-    };
-    let vocab: Vec<String> = vocab_contents.lines().map(|l| l.to_string()).collect();
-    let merges_contents = match std::fs::read_to_string(merges_str) {
-        Ok(c) => c,
-        Err(_) => return std::ptr::null_mut(),
-    };
-    let merges: Vec<(String, String)> = merges_contents
-        .lines()
-        .filter_map(|l| {
-            let mut parts = l.split_whitespace();
-            let a = parts.next()?.to_string();
-            let b = parts.next()?.to_string();
-            Some((a, b))
-        })
-        .collect();
-    let tok = BpeTokenizer::new(vocab, merges, unk_id as usize);
-    Box::into_raw(Box::new(TokenizerHandle { tok }))
+    match load_tokenizer(vocab_str, merges_str, unk_id as usize) {
+        Ok(tok) => Box::into_raw(Box::new(TokenizerHandle { tok })),
+        Err(_) => std::ptr::null_mut(),
+    }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn dragon_tokenizer_free(handle: *mut TokenizerHandle) {
     if !handle.is_null() {
@@ -179,6 +338,20 @@ pub extern "C" fn dragon_tokenizer_encode(
     text: *const c_char,
     out_ptr: *mut c_ulong,
     out_cap: c_ulong,
+) -> c_ulong {
+    dragon_tokenizer_encode_with_special(handle, text, true, out_ptr, out_cap)
+}
+
+/// Encodes `text`, matching special tokens only when `allow_special` is set.
+/// Pass `false` for untrusted user text.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_tokenizer_encode_with_special(
+    handle: *const TokenizerHandle,
+    text: *const c_char,
+    allow_special: bool,
+    out_ptr: *mut c_ulong,
+    out_cap: c_ulong,
 ) -> c_ulong {
     if handle.is_null() || text.is_null() {
         return 0;
@@ -189,7 +362,7 @@ pub extern "C" fn dragon_tokenizer_encode(
         Ok(s) => s,
         Err(_) => return 0,
     };
-    let tokens = tok.encode(text_str);
+    let tokens = tok.encode_with_special(text_str, allow_special);
     let count = std::cmp::min(tokens.len(), out_cap as usize);
     unsafe {
         let out_slice = std::slice::from_raw_parts_mut(out_ptr, count);
@@ -199,3 +372,93 @@ pub extern "C" fn dragon_tokenizer_encode(
     }
     count as c_ulong
 }
+
+/// Encodes `text` like `dragon_tokenizer_encode_with_special` and also
+/// writes each token's span of `text` to `out_spans` as four values:
+/// byte start, byte end, char start and char end. `out_spans` must hold
+/// `4 * out_cap` values. Returns the number of tokens written.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_tokenizer_encode_with_offsets(
+    handle: *const TokenizerHandle,
+    text: *const c_char,
+    allow_special: bool,
+    out_ptr: *mut c_ulong,
+    out_spans: *mut c_ulong,
+    out_cap: c_ulong,
+) -> c_ulong {
+    if handle.is_null() || text.is_null() || out_ptr.is_null() || out_spans.is_null() {
+        return 0;
+    }
+    let tok = unsafe { &(*handle).tok };
+    let text_str = match unsafe { CStr::from_ptr(text) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return 0,
+    };
+    let spans = tok.encode_with_offsets(text_str, allow_special);
+    let count = std::cmp::min(spans.len(), out_cap as usize);
+    let ids = unsafe { std::slice::from_raw_parts_mut(out_ptr, count) };
+    let offsets = unsafe { std::slice::from_raw_parts_mut(out_spans, count * 4) };
+    for (i, span) in spans.iter().take(count).enumerate() {
+        ids[i] = span.id as c_ulong;
+        offsets[i * 4] = span.byte_start as c_ulong;
+        offsets[i * 4 + 1] = span.byte_end as c_ulong;
+        offsets[i * 4 + 2] = span.char_start as c_ulong;
+        offsets[i * 4 + 3] = span.char_end as c_ulong;
+    }
+    count as c_ulong
+}
+
+/// Returns the vocabulary size of the tokenizer, or 0 for a null handle.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_tokenizer_vocab_size(handle: *const TokenizerHandle) -> c_ulong {
+    if handle.is_null() {
+        return 0;
+    }
+    let tok = unsafe { &(*handle).tok };
+    tok.vocab_size() as c_ulong
+}
+
+/// Returns the id of `token`, or -1 if it is not in the vocabulary.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_tokenizer_token_to_id(handle: *const TokenizerHandle, token: *const c_char) -> c_long {
+    if handle.is_null() || token.is_null() {
+        return -1;
+    }
+    let tok = unsafe { &(*handle).tok };
+    let token = match unsafe { CStr::from_ptr(token) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    tok.token_to_id(token).map_or(-1, |id| id as c_long)
+}
+
+/// Decodes `len` token ids into `out_ptr` as a NUL terminated UTF-8 string.
+/// Returns the number of bytes written without the terminator, or 0 if the
+/// text and terminator do not fit in `out_cap` bytes.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn dragon_tokenizer_decode(
+    handle: *const TokenizerHandle,
+    tokens_ptr: *const c_ulong,
+    len: c_ulong,
+    out_ptr: *mut c_char,
+    out_cap: c_ulong,
+) -> c_ulong {
+    if handle.is_null() || tokens_ptr.is_null() || out_ptr.is_null() {
+        return 0;
+    }
+    let tok = unsafe { &(*handle).tok };
+    let ids = unsafe { std::slice::from_raw_parts(tokens_ptr, len as usize) };
+    let ids: Vec<usize> = ids.iter().map(|&t| t as usize).collect();
+    let text = tok.decode(&ids);
+    if text.len() + 1 > out_cap as usize {
+        return 0;
+    }
+    let out = unsafe { std::slice::from_raw_parts_mut(out_ptr as *mut u8, text.len() + 1) };
+    out[..text.len()].copy_from_slice(text.as_bytes());
+    out[text.len()] = 0;
+    text.len() as c_ulong
+}
@@ -0,0 +1,250 @@
+use crate::copy_bias::CopyBias;
+use crate::decoding::DecodingMode;
+use crate::model::Model;
+use crate::samplers::{Sampler, SamplerKind, SamplerState};
+use crate::sampling;
+use rand::Rng;
+use serde::Serialize;
+
+/// Options controlling [`Model::generate_with`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationConfig {
+    /// Number of tokens to generate after the prompt.
+    pub max_tokens: usize,
+    /// Sampling temperature. Zero selects greedy decoding.
+    pub temperature: f32,
+    /// Number of alternative tokens reported with each generated token.
+    pub top_logprobs: usize,
+    /// Strategy used to choose each token.
+    pub decoding: DecodingMode,
+    /// Sampler drawing from the distribution chosen by `decoding`.
+    /// Contrastive search ignores it.
+    pub sampler: SamplerKind,
+    /// Optional bias toward tokens of the retrieved context, applied before
+    /// sampling. Contrastive search ignores it.
+    pub copy_bias: Option<CopyBias>,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 16,
+            temperature: 0.0,
+            top_logprobs: 0,
+            decoding: DecodingMode::Sample,
+            sampler: SamplerKind::Temperature,
+            copy_bias: None,
+        }
+    }
+}
+
+/// A token id paired with its log-probability under the model.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TokenLogprob {
+    pub token: usize,
+    pub logprob: f32,
+}
+
+/// A generated token with its log-probability and the most likely alternatives
+/// at the same position.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GeneratedToken {
+    pub token: usize,
+    pub logprob: f32,
+    pub top: Vec<TokenLogprob>,
+}
+
+/// Result of [`Model::generate_with`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GenerationOutput {
+    /// Prompt followed by the generated tokens.
+    pub tokens: Vec<usize>,
+    /// Per-token details for the generated part only.
+    pub generated: Vec<GeneratedToken>,
+    /// Sampler state after the last token.
+    pub sampler: SamplerState,
+}
+
+impl GenerationOutput {
+    /// Sum of the log-probabilities of all generated tokens.
+    pub fn total_logprob(&self) -> f32 {
+        self.generated.iter().map(|t| t.logprob).sum()
+    }
+}
+
+/// Builds the per-token record for `token` chosen from `logits`.
+///
+/// Log-probabilities are taken from the untempered distribution so they stay
+/// comparable across sampling settings.
+pub fn token_logprobs(logits: &[f32], token: usize, top_n: usize) -> GeneratedToken {
+    let logprobs = sampling::log_softmax(logits);
+    let top = sampling::top_n(&logprobs, top_n)
+        .into_iter()
+        .map(|id| TokenLogprob {
+            token: id,
+            logprob: logprobs[id],
+        })
+        .collect();
+    GeneratedToken {
+        token,
+        logprob: logprobs[token],
+        top,
+    }
+}
+
+fn biased(config: &GenerationConfig, tokens: &[usize], mut logits: Vec<f32>) -> Vec<f32> {
+    if let Some(bias) = &config.copy_bias {
+        bias.apply(tokens, &mut logits);
+    }
+    logits
+}
+
+impl Model {
+    /// Chooses the token following `tokens` according to `config`, drawing
+    /// with `sampler`, and returns its record. Returns `None` when `tokens`
+    /// is empty or already fills the model's context window.
+    pub fn next_token<R: Rng + ?Sized>(
+        &self,
+        tokens: &[usize],
+        config: &GenerationConfig,
+        sampler: &mut Sampler,
+        rng: &mut R,
+    ) -> Option<GeneratedToken> {
+        if tokens.is_empty() || tokens.len() >= self.max_context {
+            return None;
+        }
+        // reported log-probabilities always come from the plain model logits
+        let (next, logits) = match config.decoding {
+            DecodingMode::Sample => {
+                let logits = self.forward(tokens).pop().unwrap_or_default();
+                let biased = biased(config, tokens, logits.clone());
+                (sampler.sample(&biased, config.temperature, rng), logits)
+            }
+            DecodingMode::Contrastive { top_k, alpha } => self.contrastive_step(tokens, top_k, alpha),
+            DecodingMode::ContextAware {
+                context_start,
+                context_end,
+                alpha,
+            } => {
+                let (adjusted, logits) = self.context_aware_logits(tokens, context_start, context_end, alpha);
+                let biased = biased(config, tokens, adjusted);
+                (sampler.sample(&biased, config.temperature, rng), logits)
+            }
+        };
+        Some(token_logprobs(&logits, next, config.top_logprobs))
+    }
+
+    /// Generates tokens according to `config`, recording the log-probability
+    /// of each chosen token and the `top_logprobs` best alternatives.
+    ///
+    /// Generation stops early once the sequence fills the model's context
+    /// window.
+    pub fn generate_with<R: Rng + ?Sized>(
+        &self,
+        input: &[usize],
+        config: &GenerationConfig,
+        rng: &mut R,
+    ) -> GenerationOutput {
+        let mut tokens = input.to_vec();
+        let mut generated = Vec::with_capacity(config.max_tokens);
+        let mut sampler = Sampler::new(config.sampler);
+        for _ in 0..config.max_tokens {
+            match self.next_token(&tokens, config, &mut sampler, rng) {
+                Some(token) => {
+                    tokens.push(token.token);
+                    generated.push(token);
+                }
+                None => break,
+            }
+        }
+        GenerationOutput {
+            tokens,
+            generated,
+            sampler: sampler.state(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn greedy_matches_generate() {
+        let model = Model::new(4, 4, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let config = GenerationConfig {
+            max_tokens: 3,
+            temperature: 0.0,
+            top_logprobs: 2,
+            ..Default::default()
+        };
+        let out = model.generate_with(&[1, 2], &config, &mut rng);
+        assert_eq!(out.tokens, model.generate(&[1, 2], 3));
+        assert_eq!(out.generated.len(), 3);
+        for tok in &out.generated {
+            assert_eq!(tok.top.len(), 2);
+            // greedy picks the most likely token, so it heads the alternatives
+            assert_eq!(tok.top[0].token, tok.token);
+            assert!(tok.logprob <= 0.0);
+            assert!(tok.top[0].logprob >= tok.top[1].logprob);
+        }
+    }
+
+    #[test]
+    fn stops_at_context_limit() {
+        let mut model = Model::new(4, 4, 4, 1, 1);
+        model.max_context = 4;
+        let mut rng = StdRng::seed_from_u64(0);
+        let config = GenerationConfig {
+            max_tokens: 10,
+            ..Default::default()
+        };
+        let out = model.generate_with(&[1, 2], &config, &mut rng);
+        assert_eq!(out.tokens.len(), 4);
+    }
+
+    #[test]
+    fn copy_bias_restricts_output() {
+        use crate::copy_bias::CopyMode;
+        let model = Model::new(6, 6, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(3);
+        let config = GenerationConfig {
+            max_tokens: 8,
+            temperature: 1.0,
+            copy_bias: Some(CopyBias::new(&[4, 5], &[0], CopyMode::Restrict)),
+            ..Default::default()
+        };
+        let out = model.generate_with(&[1, 2], &config, &mut rng);
+        assert!(out.tokens[2..].iter().all(|t| [0, 4, 5].contains(t)));
+    }
+
+    #[test]
+    fn mirostat_state_is_reported() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(1);
+        let config = GenerationConfig {
+            max_tokens: 5,
+            sampler: SamplerKind::MirostatV2 { tau: 1.0, eta: 0.1 },
+            ..Default::default()
+        };
+        let out = model.generate_with(&[1, 2], &config, &mut rng);
+        assert_eq!(out.sampler.steps, 5);
+        assert_ne!(out.sampler.mu, 2.0);
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let out = GenerationOutput {
+            tokens: vec![0, 1],
+            generated: vec![token_logprobs(&[0.0, 0.0], 1, 1)],
+            sampler: SamplerState::default(),
+        };
+        let json = serde_json::to_value(&out).unwrap();
+        assert_eq!(json["generated"][0]["token"], 1);
+        assert!((json["generated"][0]["logprob"].as_f64().unwrap() + 2f64.ln()).abs() < 1e-5);
+        assert!((out.total_logprob() + 2f32.ln()).abs() < 1e-5);
+    }
+}
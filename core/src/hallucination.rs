@@ -0,0 +1,114 @@
+use crate::model::Model;
+use serde::Serialize;
+
+/// Default PMI margin below which an answer token is flagged. A token the
+/// context leaves unchanged has a PMI of about zero and must still be
+/// flagged, so the margin is positive.
+pub const DEFAULT_PMI_THRESHOLD: f32 = 0.5;
+
+/// Context dependence of a single answer token.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenAttribution {
+    pub token: usize,
+    /// Log-probability given the retrieved context and the query.
+    pub logprob_with: f32,
+    /// Log-probability given the query alone.
+    pub logprob_without: f32,
+    /// Pointwise mutual information `logprob_with - logprob_without`.
+    pub pmi: f32,
+    /// Set when `pmi` falls below the threshold, i.e. the token's
+    /// probability does not depend on the context.
+    pub flagged: bool,
+}
+
+/// Per-token pointwise mutual information between an answer and its context.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HallucinationReport {
+    pub tokens: Vec<TokenAttribution>,
+    /// Average PMI over the answer tokens.
+    pub mean_pmi: f32,
+    /// Fraction of answer tokens that were flagged.
+    pub flagged_fraction: f32,
+}
+
+impl Model {
+    /// Scores `answer` once after `context` followed by `query` and once after
+    /// `query` alone, reporting the per-token PMI. Tokens whose PMI is below
+    /// `threshold` are flagged as likely hallucinated since the context did not
+    /// make them meaningfully more probable; [`DEFAULT_PMI_THRESHOLD`] is a
+    /// reasonable choice.
+    ///
+    /// Returns `None` when `query` is empty, since both runs need a prefix to
+    /// predict the first answer token from, or when a token is outside the
+    /// vocabulary.
+    pub fn context_attribution(
+        &self,
+        context: &[usize],
+        query: &[usize],
+        answer: &[usize],
+        threshold: f32,
+    ) -> Option<HallucinationReport> {
+        let mut prompt = context.to_vec();
+        prompt.extend_from_slice(query);
+        let with = self.score(&prompt, answer)?;
+        let without = self.score(query, answer)?;
+        let tokens: Vec<TokenAttribution> = answer
+            .iter()
+            .zip(with.token_logprobs.iter().zip(&without.token_logprobs))
+            .map(|(&token, (&lw, &lwo))| {
+                let pmi = lw - lwo;
+                TokenAttribution {
+                    token,
+                    logprob_with: lw,
+                    logprob_without: lwo,
+                    pmi,
+                    flagged: pmi < threshold,
+                }
+            })
+            .collect();
+        let n = tokens.len().max(1) as f32;
+        Some(HallucinationReport {
+            mean_pmi: tokens.iter().map(|t| t.pmi).sum::<f32>() / n,
+            flagged_fraction: tokens.iter().filter(|t| t.flagged).count() as f32 / n,
+            tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pmi_is_difference_of_scores() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let report = model.context_attribution(&[1, 2], &[3], &[4, 5], 0.0).unwrap();
+        let with = model.score(&[1, 2, 3], &[4, 5]).unwrap();
+        let without = model.score(&[3], &[4, 5]).unwrap();
+        assert_eq!(report.tokens.len(), 2);
+        for (i, t) in report.tokens.iter().enumerate() {
+            assert!((t.pmi - (with.token_logprobs[i] - without.token_logprobs[i])).abs() < 1e-6);
+            assert_eq!(t.flagged, t.pmi < 0.0);
+        }
+    }
+
+    #[test]
+    fn empty_context_flags_everything() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        // without context both runs agree, so no token gains probability
+        let report = model.context_attribution(&[], &[3], &[4, 5], 0.1).unwrap();
+        assert!(report.tokens.iter().all(|t| t.pmi.abs() < 1e-6));
+        assert_eq!(report.flagged_fraction, 1.0);
+    }
+
+    #[test]
+    fn default_threshold_flags_unaffected_tokens() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        // identical prompts give a PMI of exactly zero, which a zero
+        // threshold would let through
+        let report = model.context_attribution(&[], &[3], &[4, 5], DEFAULT_PMI_THRESHOLD).unwrap();
+        assert!(report.tokens.iter().all(|t| t.pmi.abs() < 1e-6 && t.flagged));
+        let strict = model.context_attribution(&[], &[3], &[4, 5], 0.0).unwrap();
+        assert_eq!(strict.flagged_fraction, 0.0);
+    }
+}
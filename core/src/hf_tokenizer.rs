@@ -0,0 +1,352 @@
+// HuggingFace `tokenizer.json` import and export for `BpeTokenizer`.
+// Only the parts our tokenizer can reproduce are accepted: a BPE model
+// without subword prefixes or suffixes, normalizers and pre-tokenizers that
+// map onto a `TextPipeline`, and a ByteLevel step selecting byte-level mode.
+// Added tokens become special tokens so they are matched atomically.
+use serde_json::{json, Map, Value};
+use std::io;
+use std::path::Path;
+
+use crate::text_pipeline::{Normalizer, PreTokenizer, TextPipeline};
+use crate::tokenizer::BpeTokenizer;
+
+// the GPT-2 regex as written by HuggingFace `Split` pre-tokenizers
+const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+// true if `component` is, or is a sequence containing, a ByteLevel step
+fn has_byte_level(component: &Value, list_key: &str) -> bool {
+    match component.get("type").and_then(Value::as_str) {
+        Some("ByteLevel") => true,
+        Some("Sequence") => component
+            .get(list_key)
+            .and_then(Value::as_array)
+            .is_some_and(|steps| steps.iter().any(|s| has_byte_level(s, list_key))),
+        _ => false,
+    }
+}
+
+fn component_type(component: &Value) -> &str {
+    component.get("type").and_then(Value::as_str).unwrap_or("")
+}
+
+// steps of a normalizer or pre-tokenizer, with sequences flattened
+fn flatten<'v>(component: &'v Value, list_key: &str, out: &mut Vec<&'v Value>) {
+    match component_type(component) {
+        "Sequence" => {
+            for step in component.get(list_key).and_then(Value::as_array).into_iter().flatten() {
+                flatten(step, list_key, out);
+            }
+        }
+        _ if component.is_null() => {}
+        _ => out.push(component),
+    }
+}
+
+// HuggingFace strips accents by decomposing first, so `NFD` is only
+// accepted right before `StripAccents`; our accent stripping recomposes, so
+// an `NFC` right after it is dropped
+fn parse_normalizers(normalizer: &Value) -> io::Result<Vec<Normalizer>> {
+    let mut steps = Vec::new();
+    flatten(normalizer, "normalizers", &mut steps);
+    let mut normalizers = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        normalizers.push(match component_type(step) {
+            "NFC" if normalizers.last() == Some(&Normalizer::StripAccents) => continue,
+            "NFC" => Normalizer::Nfc,
+            "NFKC" => Normalizer::Nfkc,
+            "Lowercase" => Normalizer::Lowercase,
+            "StripAccents" => Normalizer::StripAccents,
+            "NFD" if steps.get(i + 1).map(|s| component_type(s)) == Some("StripAccents") => continue,
+            other => return Err(invalid(format!("unsupported normalizer {}", other))),
+        });
+    }
+    Ok(normalizers)
+}
+
+// pre-tokenizer steps and whether a ByteLevel step asks for byte-level mode.
+// `Whitespace` (`\w+|[^\w\s]+`) and a ByteLevel step adding a prefix space
+// have no exact equivalent and are rejected.
+fn parse_pre_tokenizers(pre_tokenizer: &Value) -> io::Result<(Vec<PreTokenizer>, bool)> {
+    let mut steps = Vec::new();
+    flatten(pre_tokenizer, "pretokenizers", &mut steps);
+    let mut pre_tokenizers = Vec::new();
+    let mut byte_level = false;
+    for step in steps {
+        let behavior = step.get("behavior").and_then(Value::as_str).unwrap_or("Isolated");
+        match component_type(step) {
+            "ByteLevel" => {
+                if step.get("add_prefix_space").and_then(Value::as_bool).unwrap_or(false) {
+                    return Err(invalid("unsupported ByteLevel pre-tokenizer with add_prefix_space"));
+                }
+                byte_level = true;
+                if step.get("use_regex").and_then(Value::as_bool).unwrap_or(true) {
+                    pre_tokenizers.push(PreTokenizer::Gpt2);
+                }
+            }
+            "WhitespaceSplit" => pre_tokenizers.push(PreTokenizer::Whitespace),
+            "Digits" => pre_tokenizers.push(PreTokenizer::Digits {
+                individual: step.get("individual_digits").and_then(Value::as_bool).unwrap_or(false),
+            }),
+            "Punctuation" if behavior == "Isolated" => pre_tokenizers.push(PreTokenizer::Punctuation),
+            "Split"
+                if behavior == "Isolated"
+                    && step.pointer("/pattern/Regex").and_then(Value::as_str) == Some(GPT2_PATTERN)
+                    && !step.get("invert").and_then(Value::as_bool).unwrap_or(false) =>
+            {
+                pre_tokenizers.push(PreTokenizer::Gpt2)
+            }
+            other => return Err(invalid(format!("unsupported pre-tokenizer {}", other))),
+        }
+    }
+    Ok((pre_tokenizers, byte_level))
+}
+
+// a single step as is, several as a sequence
+fn sequence(mut steps: Vec<Value>, list_key: &str) -> Value {
+    match steps.len() {
+        0 => Value::Null,
+        1 => steps.remove(0),
+        _ => json!({ "type": "Sequence", list_key: steps }),
+    }
+}
+
+fn byte_level_step(use_regex: bool) -> Value {
+    json!({
+        "type": "ByteLevel",
+        "add_prefix_space": false,
+        "trim_offsets": true,
+        "use_regex": use_regex,
+    })
+}
+
+fn parse_merge(merge: &Value) -> io::Result<(String, String)> {
+    match merge {
+        Value::String(s) => s
+            .split_once(' ')
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .ok_or_else(|| invalid(format!("malformed merge {:?}", s))),
+        Value::Array(pair) => match pair.as_slice() {
+            [Value::String(a), Value::String(b)] => Ok((a.clone(), b.clone())),
+            _ => Err(invalid(format!("malformed merge {}", merge))),
+        },
+        _ => Err(invalid(format!("malformed merge {}", merge))),
+    }
+}
+
+impl BpeTokenizer {
+    /// Builds a tokenizer from the contents of a HuggingFace
+    /// `tokenizer.json`. A `ByteLevel` pre-tokenizer or decoder selects
+    /// byte-level mode. The NFC, NFKC, Lowercase and StripAccents
+    /// normalizers and the ByteLevel, WhitespaceSplit, Digits, Punctuation
+    /// and GPT-2 `Split` pre-tokenizers become the [`TextPipeline`]. Every
+    /// entry of `added_tokens` is registered as a special token at its id.
+    /// Settings we cannot reproduce, such as other normalizers, the
+    /// `Whitespace` pre-tokenizer, `ByteLevel` with `add_prefix_space` or
+    /// `continuing_subword_prefix`, are rejected with `InvalidData`; the
+    /// post-processor, dropout and padding are ignored.
+    pub fn from_hf_json(json: &str) -> io::Result<Self> {
+        let root: Value = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        let model = root.get("model").ok_or_else(|| invalid("missing model"))?;
+        // older files omit the type of BPE models
+        match model.get("type").and_then(Value::as_str) {
+            Some("BPE") | None => {}
+            Some(other) => return Err(invalid(format!("unsupported model type {}", other))),
+        }
+        for key in ["continuing_subword_prefix", "end_of_word_suffix"] {
+            if model.get(key).and_then(Value::as_str).is_some_and(|s| !s.is_empty()) {
+                return Err(invalid(format!("unsupported {}", key)));
+            }
+        }
+        let normalizers = parse_normalizers(root.get("normalizer").unwrap_or(&Value::Null))?;
+        let (pre_tokenizers, byte_level_pre) = parse_pre_tokenizers(root.get("pre_tokenizer").unwrap_or(&Value::Null))?;
+        let decoder = root.get("decoder").unwrap_or(&Value::Null);
+        let byte_level = byte_level_pre || has_byte_level(decoder, "decoders");
+
+        let mut tokens: Vec<(String, usize)> = Vec::new();
+        let vocab = model
+            .get("vocab")
+            .and_then(Value::as_object)
+            .ok_or_else(|| invalid("missing model.vocab"))?;
+        for (token, id) in vocab {
+            let id = id.as_u64().ok_or_else(|| invalid(format!("bad id for {:?}", token)))?;
+            tokens.push((token.clone(), id as usize));
+        }
+        let mut added = Vec::new();
+        for entry in root.get("added_tokens").and_then(Value::as_array).into_iter().flatten() {
+            let content = entry.get("content").and_then(Value::as_str);
+            let id = entry.get("id").and_then(Value::as_u64);
+            match (content, id) {
+                (Some(content), Some(id)) => {
+                    tokens.push((content.to_string(), id as usize));
+                    added.push((content.to_string(), id as usize));
+                }
+                _ => return Err(invalid(format!("malformed added token {}", entry))),
+            }
+        }
+        let mut inv_vocab: Vec<Option<String>> = Vec::new();
+        for (token, id) in tokens {
+            if id >= inv_vocab.len() {
+                inv_vocab.resize(id + 1, None);
+            }
+            match &inv_vocab[id] {
+                Some(existing) if *existing != token => {
+                    return Err(invalid(format!("id {} used by {:?} and {:?}", id, existing, token)))
+                }
+                _ => inv_vocab[id] = Some(token),
+            }
+        }
+        let inv_vocab: Vec<String> = inv_vocab
+            .into_iter()
+            .enumerate()
+            .map(|(id, t)| t.ok_or_else(|| invalid(format!("no token has id {}", id))))
+            .collect::<io::Result<_>>()?;
+
+        let merges = model
+            .get("merges")
+            .and_then(Value::as_array)
+            .map(|merges| merges.iter().map(parse_merge).collect::<io::Result<Vec<_>>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        let mut tokenizer = if byte_level {
+            BpeTokenizer::new_byte_level(inv_vocab, merges)
+        } else {
+            let unk_id = match model.get("unk_token").and_then(Value::as_str) {
+                Some(unk) => inv_vocab
+                    .iter()
+                    .position(|t| t == unk)
+                    .ok_or_else(|| invalid(format!("unk_token {:?} not in vocab", unk)))?,
+                None => 0,
+            };
+            BpeTokenizer::new(inv_vocab, merges, unk_id)
+        };
+        for (content, _) in &added {
+            tokenizer.add_special_token(content);
+        }
+        tokenizer.set_pipeline(TextPipeline {
+            normalizers,
+            pre_tokenizers,
+        });
+        Ok(tokenizer)
+    }
+
+    /// Reads a HuggingFace `tokenizer.json` file, see
+    /// [`BpeTokenizer::from_hf_json`].
+    pub fn load_hf<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_hf_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Serializes the tokenizer in the HuggingFace `tokenizer.json` format.
+    /// Special tokens are written as added tokens. Merges use the
+    /// `"a b"` form unless a token contains a space. Outside the default
+    /// byte-level pipeline, GPT-2 splitting is written as a `Split` regex and
+    /// the byte mapping as a final `ByteLevel` step without regex.
+    pub fn to_hf_json(&self) -> Value {
+        let mut vocab = Map::new();
+        for id in 0..self.vocab_size() {
+            if let Some(token) = self.id_to_token(id) {
+                vocab.insert(token.to_string(), json!(id));
+            }
+        }
+        let merges: Vec<Value> = self
+            .merges()
+            .into_iter()
+            .map(|(a, b)| {
+                if a.contains(' ') || b.contains(' ') {
+                    json!([a, b])
+                } else {
+                    json!(format!("{} {}", a, b))
+                }
+            })
+            .collect();
+        let added_tokens: Vec<Value> = self
+            .special_tokens()
+            .into_iter()
+            .map(|(content, id)| {
+                json!({
+                    "id": id,
+                    "content": content,
+                    "single_word": false,
+                    "lstrip": false,
+                    "rstrip": false,
+                    "normalized": false,
+                    "special": true,
+                })
+            })
+            .collect();
+        let pipeline = self.pipeline();
+        let mut normalizers = Vec::new();
+        for normalizer in &pipeline.normalizers {
+            match normalizer {
+                Normalizer::Nfc => normalizers.push(json!({ "type": "NFC" })),
+                Normalizer::Nfkc => normalizers.push(json!({ "type": "NFKC" })),
+                Normalizer::Lowercase => normalizers.push(json!({ "type": "Lowercase" })),
+                // our accent stripping decomposes and recomposes itself
+                Normalizer::StripAccents => normalizers.extend([
+                    json!({ "type": "NFD" }),
+                    json!({ "type": "StripAccents" }),
+                    json!({ "type": "NFC" }),
+                ]),
+            }
+        }
+        let mut pre_tokenizers: Vec<Value> = pipeline
+            .pre_tokenizers
+            .iter()
+            .map(|pre| match pre {
+                PreTokenizer::Whitespace => json!({ "type": "WhitespaceSplit" }),
+                PreTokenizer::Gpt2 => json!({
+                    "type": "Split",
+                    "pattern": { "Regex": GPT2_PATTERN },
+                    "behavior": "Isolated",
+                    "invert": false,
+                }),
+                PreTokenizer::Digits { individual } => json!({ "type": "Digits", "individual_digits": individual }),
+                PreTokenizer::Punctuation => json!({ "type": "Punctuation", "behavior": "Isolated" }),
+            })
+            .collect();
+        let (decoder, unk_token) = if self.is_byte_level() {
+            if pipeline.pre_tokenizers == [PreTokenizer::Gpt2] {
+                pre_tokenizers = vec![byte_level_step(true)];
+            } else {
+                pre_tokenizers.push(byte_level_step(false));
+            }
+            (byte_level_step(true), Value::Null)
+        } else {
+            let unk = self.id_to_token(self.unk_id()).map_or(Value::Null, |t| json!(t));
+            (Value::Null, unk)
+        };
+        let normalizer = sequence(normalizers, "normalizers");
+        let pre_tokenizer = sequence(pre_tokenizers, "pretokenizers");
+        json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": added_tokens,
+            "normalizer": normalizer,
+            "pre_tokenizer": pre_tokenizer,
+            "post_processor": null,
+            "decoder": decoder,
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": unk_token,
+                "continuing_subword_prefix": null,
+                "end_of_word_suffix": null,
+                "fuse_unk": false,
+                "byte_fallback": false,
+                "vocab": vocab,
+                "merges": merges,
+            },
+        })
+    }
+
+    /// Writes the tokenizer to `path` as a HuggingFace `tokenizer.json`.
+    pub fn save_hf<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_hf_json()).map_err(|e| invalid(e.to_string()))?;
+        std::fs::write(path, json)
+    }
+}
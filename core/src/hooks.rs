@@ -0,0 +1,92 @@
+use serde::Serialize;
+
+/// Observer notified while [`Transformer::forward_with_observer`] runs.
+///
+/// All callbacks default to doing nothing so implementors only override the
+/// activations they care about. Matrices are (seq_len x embed_dim) and
+/// attention probabilities are (num_heads x seq_len x seq_len).
+///
+/// [`Transformer::forward_with_observer`]: crate::transformer::Transformer::forward_with_observer
+pub trait ForwardObserver {
+    /// Called with the input of block `layer`, i.e. the residual stream
+    /// entering it.
+    fn on_block_input(&mut self, _layer: usize, _states: &[Vec<f32>]) {}
+
+    /// Called with the softmax attention probabilities of block `layer`.
+    fn on_attention(&mut self, _layer: usize, _weights: &[Vec<Vec<f32>>]) {}
+
+    /// Called with the output of the attention sublayer of block `layer`.
+    fn on_attention_output(&mut self, _layer: usize, _states: &[Vec<f32>]) {}
+
+    /// Called with the output of block `layer`.
+    fn on_block_output(&mut self, _layer: usize, _states: &[Vec<f32>]) {}
+}
+
+/// Activations captured for a single decoder block.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LayerTrace {
+    pub input: Vec<Vec<f32>>,
+    pub attention: Vec<Vec<Vec<f32>>>,
+    pub attention_output: Vec<Vec<f32>>,
+    pub output: Vec<Vec<f32>>,
+}
+
+/// [`ForwardObserver`] that records every activation it sees.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ActivationRecorder {
+    pub layers: Vec<LayerTrace>,
+}
+
+impl ActivationRecorder {
+    /// Creates an empty recorder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn layer(&mut self, layer: usize) -> &mut LayerTrace {
+        if self.layers.len() <= layer {
+            self.layers.resize_with(layer + 1, LayerTrace::default);
+        }
+        &mut self.layers[layer]
+    }
+}
+
+impl ForwardObserver for ActivationRecorder {
+    fn on_block_input(&mut self, layer: usize, states: &[Vec<f32>]) {
+        self.layer(layer).input = states.to_vec();
+    }
+
+    fn on_attention(&mut self, layer: usize, weights: &[Vec<Vec<f32>>]) {
+        self.layer(layer).attention = weights.to_vec();
+    }
+
+    fn on_attention_output(&mut self, layer: usize, states: &[Vec<f32>]) {
+        self.layer(layer).attention_output = states.to_vec();
+    }
+
+    fn on_block_output(&mut self, layer: usize, states: &[Vec<f32>]) {
+        self.layer(layer).output = states.to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    #[test]
+    fn recorder_captures_every_layer() {
+        let model = Model::new(4, 4, 4, 2, 2);
+        let input = vec![0usize, 1, 2];
+        let mut recorder = ActivationRecorder::new();
+        let logits = model.forward_with_observer(&input, &mut recorder);
+        assert_eq!(logits, model.forward(&input));
+        assert_eq!(recorder.layers.len(), 2);
+        for trace in &recorder.layers {
+            assert_eq!(trace.attention.len(), 2);
+            assert_eq!(trace.attention[0].len(), 3);
+            assert_eq!(trace.output.len(), 3);
+        }
+        assert_eq!(recorder.layers[1].input, recorder.layers[0].output);
+    }
+}
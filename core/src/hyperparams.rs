@@ -4,5 +4,7 @@ pub const EMBED_DIM: usize = 4;
 pub const HIDDEN_DIM: usize = 4;
 pub const NUM_LAYERS: usize = 1;
 pub const NUM_HEADS: usize = 1;
+/// Maximum number of positions the model is trained to attend over.
+pub const MAX_CONTEXT: usize = 512;
 /// Learning rate used by the toy training example.
 pub const LEARNING_RATE: f32 = 0.1;
@@ -0,0 +1,59 @@
+/// Cached attention keys and values of one decoder block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayerKv {
+    pub keys: Vec<Vec<f32>>,   // shape: seq_len x embed_dim
+    pub values: Vec<Vec<f32>>, // shape: seq_len x embed_dim
+}
+
+impl LayerKv {
+    /// Returns a copy of the cached rows in `start..end`.
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        Self {
+            keys: self.keys[start..end].to_vec(),
+            values: self.values[start..end].to_vec(),
+        }
+    }
+}
+
+/// Attention keys and values for every block of a model, allowing a sequence
+/// to be extended without recomputing its prefix.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KvCache {
+    pub layers: Vec<LayerKv>,
+}
+
+impl KvCache {
+    /// Creates an empty cache for a model with `num_layers` blocks.
+    pub fn new(num_layers: usize) -> Self {
+        Self {
+            layers: vec![LayerKv::default(); num_layers],
+        }
+    }
+
+    /// Number of cached positions.
+    pub fn len(&self) -> usize {
+        self.layers.first().map(|l| l.keys.len()).unwrap_or(0)
+    }
+
+    /// Returns `true` if no positions are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every position from `len` onwards.
+    pub fn truncate(&mut self, len: usize) {
+        for layer in &mut self.layers {
+            layer.keys.truncate(len);
+            layer.values.truncate(len);
+        }
+    }
+
+    /// Approximate memory held by the cached tensors in bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.layers
+            .iter()
+            .flat_map(|l| l.keys.iter().chain(&l.values))
+            .map(|row| row.len() * std::mem::size_of::<f32>())
+            .sum()
+    }
+}
@@ -7,6 +7,13 @@ pub mod layernorm;
 pub mod rotary;
 pub mod model;
 pub mod tokenizer;
+pub mod bpe_trainer;
+pub mod bpe_encoder;
+pub mod hf_tokenizer;
+pub mod unigram;
+pub mod unigram_trainer;
+pub mod text_pipeline;
+pub mod tokenizer_file;
 pub mod loss;
 pub mod blas;
 pub mod serialization;
@@ -14,6 +21,23 @@ pub mod ffi;
 pub mod quant;
 pub mod hyperparams;
 pub mod dataset;
+pub mod sampling;
+pub mod samplers;
+pub mod speculative;
+pub mod generation;
+pub mod scoring;
+pub mod pooling;
+pub mod hooks;
+pub mod kv_cache;
+pub mod prefix_cache;
+pub mod scheduler;
+pub mod prompt;
+pub mod decoding;
+pub mod hallucination;
+pub mod copy_bias;
+pub mod entities;
+pub mod streaming;
+pub mod detokenizer;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
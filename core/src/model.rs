@@ -1,5 +1,11 @@
 use crate::{embedding::Embedding, transformer::Transformer, Linear, rotary::RotaryEmbedding};
 use crate::serialization::{self, Tensor};
+use crate::sampling;
+use crate::hooks::ForwardObserver;
+use crate::kv_cache::KvCache;
+use crate::hyperparams::MAX_CONTEXT;
+use crate::prompt::{self, AssembledPrompt, ContextOverflow, PromptParts, TruncationPolicy};
+use rand::Rng;
 use std::collections::BTreeMap;
 use serde_json::json;
 
@@ -12,6 +18,9 @@ pub struct Model {
     pub positional: RotaryEmbedding,
     pub transformer: Transformer,
     pub output_layer: Linear,
+    /// Maximum sequence length the model supports, prompt and generated
+    /// tokens included.
+    pub max_context: usize,
 }
 
 impl Model {
@@ -38,6 +47,7 @@ impl Model {
             positional: RotaryEmbedding::new(embed_dim),
             transformer: Transformer::new(num_layers, embed_dim, hidden_dim, num_heads),
             output_layer: Linear::new(output_weights, vec![0.0; vocab_size]),
+            max_context: MAX_CONTEXT,
         }
     }
 
@@ -49,28 +59,121 @@ impl Model {
         self.output_layer.forward(&transformed)
     }
 
+    /// Runs the model on `input` as the continuation of the sequence stored in
+    /// `cache` and returns logits for the new positions only.
+    pub fn forward_cached(&self, input: &[usize], cache: &mut KvCache) -> Vec<Vec<f32>> {
+        let start = cache.len();
+        let embedded = self.embedding.forward(input);
+        let positioned = self.positional.forward_from(&embedded, start);
+        let transformed = self.transformer.forward_cached(&positioned, cache);
+        self.output_layer.forward(&transformed)
+    }
+
+    /// Extends several cached sequences in one batched forward pass.
+    ///
+    /// `inputs[s]` holds the new tokens of the sequence cached in `caches[s]`.
+    /// Returns the logits of the new positions, grouped per sequence.
+    pub fn forward_batch_cached(&self, inputs: &[Vec<usize>], caches: &mut [&mut KvCache]) -> Vec<Vec<Vec<f32>>> {
+        assert_eq!(inputs.len(), caches.len());
+        let mut positioned = Vec::new();
+        for (tokens, cache) in inputs.iter().zip(caches.iter()) {
+            let embedded = self.embedding.forward(tokens);
+            positioned.extend(self.positional.forward_from(&embedded, cache.len()));
+        }
+        if positioned.is_empty() {
+            return vec![Vec::new(); inputs.len()];
+        }
+        let lens: Vec<usize> = inputs.iter().map(|t| t.len()).collect();
+        let transformed = self.transformer.forward_batch_cached(&positioned, &lens, caches);
+        let mut logits = self.output_layer.forward(&transformed).into_iter();
+        lens.iter().map(|&len| logits.by_ref().take(len).collect()).collect()
+    }
+
+    /// Like [`forward`](Self::forward) but reports the activations of every
+    /// transformer block to `observer`.
+    pub fn forward_with_observer(&self, input: &[usize], observer: &mut dyn ForwardObserver) -> Vec<Vec<f32>> {
+        let embedded = self.embedding.forward(input);
+        let positioned = self.positional.forward(&embedded);
+        let transformed = self.transformer.forward_with_observer(&positioned, observer);
+        self.output_layer.forward(&transformed)
+    }
+
+    /// Number of tokens that can still follow a sequence of `len` tokens
+    /// within [`max_context`](Self::max_context).
+    pub fn context_room(&self, len: usize) -> usize {
+        self.max_context.saturating_sub(len)
+    }
+
     /// Autoregressively generates additional tokens using greedy decoding.
     ///
     /// `steps` specifies how many new tokens to generate beyond the provided
-    /// `input`. The returned vector contains the original input followed by the
-    /// generated tokens.
+    /// `input`. Generation stops early once the sequence fills
+    /// [`max_context`](Self::max_context). The returned vector contains the
+    /// original input followed by the generated tokens.
     pub fn generate(&self, input: &[usize], steps: usize) -> Vec<usize> {
         let mut tokens = input.to_vec();
-        for _ in 0..steps {
+        for _ in 0..steps.min(self.context_room(input.len())) {
             let logits = self.forward(&tokens);
             if let Some(last) = logits.last() {
-                let next = last
-                    .iter()
-                    .enumerate()
-                    .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                    .map(|(i, _)| i)
-                    .unwrap_or(0);
-                tokens.push(next);
+                tokens.push(sampling::argmax(last));
             }
         }
         tokens
     }
 
+    /// Autoregressively generates tokens by sampling from the softmax of the
+    /// logits at `temperature`. A temperature of zero falls back to greedy
+    /// decoding. Like [`generate`](Self::generate) it stops at
+    /// [`max_context`](Self::max_context).
+    pub fn generate_sampled<R: Rng + ?Sized>(
+        &self,
+        input: &[usize],
+        steps: usize,
+        temperature: f32,
+        rng: &mut R,
+    ) -> Vec<usize> {
+        let mut tokens = input.to_vec();
+        for _ in 0..steps.min(self.context_room(input.len())) {
+            let logits = self.forward(&tokens);
+            if let Some(last) = logits.last() {
+                let probs = sampling::probabilities(last, temperature);
+                tokens.push(sampling::sample(&probs, rng));
+            }
+        }
+        tokens
+    }
+
+    /// Checks that a prompt of `prompt_len` tokens leaves room for
+    /// `max_new_tokens` generated tokens within [`max_context`](Self::max_context).
+    pub fn check_context(&self, prompt_len: usize, max_new_tokens: usize) -> Result<(), ContextOverflow> {
+        let required = prompt_len + max_new_tokens;
+        if required > self.max_context {
+            Err(ContextOverflow {
+                required,
+                available: self.max_context,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Assembles `parts` into a prompt that leaves room for `max_new_tokens`
+    /// generated tokens, truncating according to `policy` when needed.
+    pub fn assemble_prompt(
+        &self,
+        parts: &PromptParts,
+        max_new_tokens: usize,
+        policy: TruncationPolicy,
+    ) -> Result<AssembledPrompt, ContextOverflow> {
+        if max_new_tokens > self.max_context {
+            return Err(ContextOverflow {
+                required: max_new_tokens,
+                available: self.max_context,
+            });
+        }
+        prompt::assemble(parts, self.max_context - max_new_tokens, policy)
+    }
+
     /// Appends a new token to the embedding and output layers and returns its id.
     pub fn add_token(&mut self) -> usize {
         let id = self.embedding.add_token();
@@ -163,7 +266,10 @@ impl Model {
             add_linear(&mut tensors, &block.feedforward.w2, &format!("{}.ff.w2", prefix));
         }
 
-        let meta = json!({"num_layers": self.transformer.blocks.len()});
+        let meta = json!({
+            "num_layers": self.transformer.blocks.len(),
+            "max_context": self.max_context,
+        });
         serialization::write_safetensors(&tensors, path, Some(meta))
     }
 
@@ -185,8 +291,16 @@ impl Model {
             .expect("missing feedforward weight");
         let hidden_dim = ff.shape[1];
 
+        let max_context = meta
+            .as_ref()
+            .and_then(|m| m.get("max_context"))
+            .and_then(|v| v.as_u64())
+            .map(|v| v as usize)
+            .unwrap_or(MAX_CONTEXT);
+
         let num_heads = 1;
         let mut model = Model::new(vocab_size, embed_dim, hidden_dim, num_layers, num_heads);
+        model.max_context = max_context;
 
         model.embedding.weights = matrix(embed);
         model.output_layer.weight = matrix(tensors.get("output.weight").unwrap());
@@ -237,7 +351,6 @@ fn load_linear(linear: &mut Linear, tensors: &BTreeMap<String, Tensor>, name: &s
 }
 
 fn matrix(t: &Tensor) -> Vec<Vec<f32>> {
-    let rows = t.shape[0];
     let cols = t.shape[1];
     t.data.chunks(cols).map(|c| c.to_vec()).collect()
 }
@@ -265,6 +378,41 @@ mod tests {
         assert!(generated.iter().all(|&t| t < 2));
     }
 
+    #[test]
+    fn forward_cached_matches_forward() {
+        let model = Model::new(6, 4, 4, 2, 2);
+        let input = vec![0usize, 3, 1, 2, 5];
+        let full = model.forward(&input);
+        let mut cache = KvCache::new(2);
+        let mut incremental = model.forward_cached(&input[..3], &mut cache);
+        for &tok in &input[3..] {
+            incremental.extend(model.forward_cached(&[tok], &mut cache));
+        }
+        assert_eq!(cache.len(), input.len());
+        for (a, b) in full.iter().flatten().zip(incremental.iter().flatten()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn forward_batch_matches_single() {
+        let model = Model::new(6, 4, 4, 2, 2);
+        let mut a = KvCache::default();
+        let mut b = KvCache::default();
+        model.forward_cached(&[1, 2], &mut a);
+        let inputs = vec![vec![3usize], vec![0, 4, 5]];
+        let batched = model.forward_batch_cached(&inputs, &mut [&mut a, &mut b]);
+        assert_eq!(batched[0].len(), 1);
+        assert_eq!(batched[1].len(), 3);
+        assert_eq!(a.len(), 3);
+        let expected = [model.forward(&[1, 2, 3])[2..].to_vec(), model.forward(&[0, 4, 5])];
+        for (got, want) in batched.iter().zip(&expected) {
+            for (x, y) in got.iter().flatten().zip(want.iter().flatten()) {
+                assert!((x - y).abs() < 1e-4);
+            }
+        }
+    }
+
     #[test]
     fn model_save_load_roundtrip() {
         let model = Model::new(2, 2, 2, 1, 1);
@@ -274,5 +422,33 @@ mod tests {
         std::fs::remove_file(path).unwrap();
         assert_eq!(model.embedding.weights, loaded.embedding.weights);
         assert_eq!(model.output_layer.bias, loaded.output_layer.bias);
+        assert_eq!(model.max_context, loaded.max_context);
+    }
+
+    #[test]
+    fn generation_stops_at_context_limit() {
+        use rand::{rngs::StdRng, SeedableRng};
+        let mut model = Model::new(4, 4, 4, 1, 1);
+        model.max_context = 5;
+        assert_eq!(model.generate(&[1, 2, 3], 10).len(), 5);
+        let mut rng = StdRng::seed_from_u64(33);
+        assert_eq!(model.generate_sampled(&[1, 2, 3], 10, 1.0, &mut rng).len(), 5);
+        // a prompt already past the limit gets nothing appended
+        assert_eq!(model.generate(&[1; 7], 2).len(), 7);
+    }
+
+    #[test]
+    fn context_budget_reserves_generation() {
+        let mut model = Model::new(2, 2, 2, 1, 1);
+        model.max_context = 8;
+        assert!(model.check_context(6, 2).is_ok());
+        assert!(model.check_context(6, 3).is_err());
+        let parts = PromptParts {
+            query: vec![1; 10],
+            ..Default::default()
+        };
+        let out = model.assemble_prompt(&parts, 3, TruncationPolicy::LeftTruncate).unwrap();
+        assert_eq!(out.tokens.len(), 5);
+        assert_eq!(out.report.dropped_tokens, 5);
     }
 }
@@ -0,0 +1,144 @@
+use crate::model::Model;
+
+/// Strategy used to collapse per-token hidden states into one vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pooling {
+    /// Average of all token states.
+    Mean,
+    /// State of the final token, which has attended to the whole sequence.
+    LastToken,
+    /// Weighted average where the weights are the softmax of the scaled dot
+    /// product between the final token state and every token state.
+    AttentionWeighted,
+}
+
+impl Pooling {
+    /// Parses `mean`, `last` or `attention`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mean" => Some(Pooling::Mean),
+            "last" => Some(Pooling::LastToken),
+            "attention" => Some(Pooling::AttentionWeighted),
+            _ => None,
+        }
+    }
+}
+
+/// Options for [`Model::embed`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolingConfig {
+    pub pooling: Pooling,
+    /// Number of transformer blocks to run before pooling. `None` uses the
+    /// output of the final block, `Some(0)` pools the positioned embeddings.
+    pub layer: Option<usize>,
+    /// Scale the pooled vector to unit L2 norm.
+    pub normalize: bool,
+}
+
+impl Default for PoolingConfig {
+    fn default() -> Self {
+        Self {
+            pooling: Pooling::Mean,
+            layer: None,
+            normalize: true,
+        }
+    }
+}
+
+/// Pools `states` (seq_len x dim) into a single vector.
+pub fn pool(states: &[Vec<f32>], pooling: Pooling) -> Vec<f32> {
+    let dim = match states.first() {
+        Some(s) => s.len(),
+        None => return Vec::new(),
+    };
+    match pooling {
+        Pooling::Mean => weighted_sum(states, &vec![1.0 / states.len() as f32; states.len()], dim),
+        Pooling::LastToken => states[states.len() - 1].clone(),
+        Pooling::AttentionWeighted => {
+            let query = &states[states.len() - 1];
+            let scale = (dim as f32).sqrt();
+            let scores: Vec<f32> = states
+                .iter()
+                .map(|s| s.iter().zip(query).map(|(a, b)| a * b).sum::<f32>() / scale)
+                .collect();
+            let weights = crate::sampling::probabilities(&scores, 1.0);
+            weighted_sum(states, &weights, dim)
+        }
+    }
+}
+
+/// Scales `v` to unit L2 norm in place. Zero vectors are left unchanged.
+pub fn l2_normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+fn weighted_sum(states: &[Vec<f32>], weights: &[f32], dim: usize) -> Vec<f32> {
+    let mut out = vec![0.0f32; dim];
+    for (state, w) in states.iter().zip(weights) {
+        for (o, x) in out.iter_mut().zip(state) {
+            *o += w * x;
+        }
+    }
+    out
+}
+
+impl Model {
+    /// Returns the hidden states after the first `layer` transformer blocks,
+    /// or after all of them when `layer` is `None`.
+    pub fn hidden_states(&self, input: &[usize], layer: Option<usize>) -> Vec<Vec<f32>> {
+        let embedded = self.embedding.forward(input);
+        let positioned = self.positional.forward(&embedded);
+        match layer {
+            Some(n) => self.transformer.forward_layers(&positioned, n),
+            None => self.transformer.forward(&positioned),
+        }
+    }
+
+    /// Computes a fixed-size embedding of `tokens` by pooling hidden states.
+    ///
+    /// An empty input yields a zero vector of the embedding dimension.
+    pub fn embed(&self, tokens: &[usize], config: &PoolingConfig) -> Vec<f32> {
+        if tokens.is_empty() {
+            return vec![0.0; self.embedding.weights[0].len()];
+        }
+        let states = self.hidden_states(tokens, config.layer);
+        let mut pooled = pool(&states, config.pooling);
+        if config.normalize {
+            l2_normalize(&mut pooled);
+        }
+        pooled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pooling_strategies() {
+        let states = vec![vec![1.0f32, 0.0], vec![0.0, 3.0]];
+        assert_eq!(pool(&states, Pooling::Mean), vec![0.5, 1.5]);
+        assert_eq!(pool(&states, Pooling::LastToken), vec![0.0, 3.0]);
+        let attn = pool(&states, Pooling::AttentionWeighted);
+        // the last token attends mostly to itself
+        assert!(attn[1] > 1.5 && attn[0] < 0.5);
+    }
+
+    #[test]
+    fn embed_normalized() {
+        let model = Model::new(4, 4, 4, 2, 1);
+        let config = PoolingConfig {
+            pooling: Pooling::Mean,
+            layer: Some(1),
+            normalize: true,
+        };
+        let v = model.embed(&[0, 1, 2], &config);
+        assert_eq!(v.len(), 4);
+        let norm: f32 = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert_eq!(model.embed(&[], &config), vec![0.0; 4]);
+    }
+}
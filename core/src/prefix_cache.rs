@@ -0,0 +1,279 @@
+// Prompt prefix caching across requests.
+// Prompts are split into fixed-size token blocks. Each block is keyed by a hash
+// of its tokens chained with the hash of the preceding block, so a key
+// identifies the whole prefix up to and including that block. Blocks store
+// only their own positions' keys and values and are evicted least recently
+// used first once the memory budget is exceeded.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::kv_cache::{KvCache, LayerKv};
+use crate::model::Model;
+use crate::sampling;
+
+struct CachedBlock {
+    parent: Option<u64>,
+    tokens: Vec<usize>,
+    layers: Vec<LayerKv>,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Counters describing how effective a [`PrefixCache`] has been.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefixCacheStats {
+    /// Number of lookups performed.
+    pub lookups: usize,
+    /// Lookups that reused at least one block.
+    pub hits: usize,
+    /// Total prompt tokens whose keys and values were reused.
+    pub reused_tokens: usize,
+    /// Blocks evicted to stay within the memory budget.
+    pub evictions: usize,
+}
+
+/// Cache of attention keys and values for prompt prefixes shared between
+/// requests.
+pub struct PrefixCache {
+    block_size: usize,
+    budget_bytes: usize,
+    used_bytes: usize,
+    clock: u64,
+    blocks: HashMap<u64, CachedBlock>,
+    stats: PrefixCacheStats,
+}
+
+fn block_key(parent: Option<u64>, tokens: &[usize]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    parent.hash(&mut hasher);
+    tokens.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl PrefixCache {
+    /// Creates a cache that groups tokens into blocks of `block_size` and
+    /// holds at most `budget_bytes` of keys and values.
+    pub fn new(block_size: usize, budget_bytes: usize) -> Self {
+        assert!(block_size > 0, "block size must be positive");
+        Self {
+            block_size,
+            budget_bytes,
+            used_bytes: 0,
+            clock: 0,
+            blocks: HashMap::new(),
+            stats: PrefixCacheStats::default(),
+        }
+    }
+
+    /// Number of cached blocks.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Memory currently held by cached keys and values in bytes.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    /// Usage counters accumulated since creation.
+    pub fn stats(&self) -> PrefixCacheStats {
+        self.stats
+    }
+
+    /// Walks the block chain of `tokens` and returns the keys of every block
+    /// that is cached, stopping at the first miss.
+    fn cached_chain(&self, tokens: &[usize]) -> Vec<u64> {
+        let mut chain = Vec::new();
+        let mut parent = None;
+        for block in tokens.chunks_exact(self.block_size) {
+            let key = block_key(parent, block);
+            match self.blocks.get(&key) {
+                Some(b) if b.parent == parent && b.tokens == block => {
+                    chain.push(key);
+                    parent = Some(key);
+                }
+                _ => break,
+            }
+        }
+        chain
+    }
+
+    /// Marks `chain` as recently used. Blocks are touched from the end so
+    /// parents always look more recent than their children and are evicted
+    /// last.
+    fn touch(&mut self, chain: &[u64]) {
+        for key in chain.iter().rev() {
+            self.clock += 1;
+            if let Some(block) = self.blocks.get_mut(key) {
+                block.last_used = self.clock;
+            }
+        }
+    }
+
+    /// Returns the keys and values of the longest cached block-aligned prefix
+    /// of `tokens`. The result is empty when nothing matches.
+    pub fn lookup(&mut self, tokens: &[usize]) -> KvCache {
+        self.stats.lookups += 1;
+        let chain = self.cached_chain(tokens);
+        self.touch(&chain);
+        let mut cache = KvCache::default();
+        for key in &chain {
+            let block = &self.blocks[key];
+            if cache.layers.is_empty() {
+                cache.layers = vec![LayerKv::default(); block.layers.len()];
+            }
+            for (dst, src) in cache.layers.iter_mut().zip(&block.layers) {
+                dst.keys.extend(src.keys.iter().cloned());
+                dst.values.extend(src.values.iter().cloned());
+            }
+        }
+        if !chain.is_empty() {
+            self.stats.hits += 1;
+            self.stats.reused_tokens += cache.len();
+        }
+        cache
+    }
+
+    /// Stores every complete block of `tokens` covered by `cache`, where
+    /// `cache` holds the keys and values for a prefix of `tokens`.
+    pub fn insert(&mut self, tokens: &[usize], cache: &KvCache) {
+        let covered = cache.len().min(tokens.len());
+        let mut chain = Vec::new();
+        let mut parent = None;
+        for (i, block) in tokens[..covered].chunks_exact(self.block_size).enumerate() {
+            let key = block_key(parent, block);
+            let valid = match self.blocks.get(&key) {
+                Some(b) => b.parent == parent && b.tokens == block,
+                None => {
+                    let start = i * self.block_size;
+                    let layers: Vec<LayerKv> = cache
+                        .layers
+                        .iter()
+                        .map(|l| l.slice(start, start + self.block_size))
+                        .collect();
+                    let bytes = KvCache { layers: layers.clone() }.size_bytes();
+                    self.used_bytes += bytes;
+                    self.blocks.insert(
+                        key,
+                        CachedBlock {
+                            parent,
+                            tokens: block.to_vec(),
+                            layers,
+                            bytes,
+                            last_used: 0,
+                        },
+                    );
+                    true
+                }
+            };
+            // a hash collision with a different prefix ends the chain
+            if !valid {
+                break;
+            }
+            chain.push(key);
+            parent = Some(key);
+        }
+        self.touch(&chain);
+        self.evict();
+    }
+
+    /// Evicts least recently used blocks until the budget is respected.
+    fn evict(&mut self) {
+        while self.used_bytes > self.budget_bytes {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, b)| b.last_used)
+                .map(|(k, _)| *k);
+            match oldest {
+                Some(key) => {
+                    let block = self.blocks.remove(&key).unwrap();
+                    self.used_bytes -= block.bytes;
+                    self.stats.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Model {
+    /// Greedy generation that resumes from the longest prefix of `input`
+    /// found in `cache` and stores the prompt's blocks for later requests.
+    ///
+    /// Produces the same tokens as [`Model::generate`], including stopping
+    /// at [`Model::max_context`], while skipping the recomputation of cached
+    /// prefixes and of earlier positions during decoding.
+    pub fn generate_cached(&self, input: &[usize], steps: usize, cache: &mut PrefixCache) -> Vec<usize> {
+        let mut tokens = input.to_vec();
+        if tokens.is_empty() {
+            return tokens;
+        }
+        // keep at least one prompt token uncached so there are logits to decode from
+        let mut kv = cache.lookup(&input[..input.len() - 1]);
+        let mut logits = self.forward_cached(&input[kv.len()..], &mut kv);
+        cache.insert(input, &kv);
+        for _ in 0..steps.min(self.context_room(input.len())) {
+            let next = sampling::argmax(&logits[logits.len() - 1]);
+            tokens.push(next);
+            logits = self.forward_cached(&[next], &mut kv);
+        }
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_shared_prefix() {
+        let model = Model::new(8, 4, 4, 2, 1);
+        let mut cache = PrefixCache::new(2, usize::MAX);
+        let first = [1usize, 2, 3, 4, 5];
+        let second = [1usize, 2, 3, 4, 6, 7];
+        assert_eq!(model.generate_cached(&first, 3, &mut cache), model.generate(&first, 3));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(model.generate_cached(&second, 3, &mut cache), model.generate(&second, 3));
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.reused_tokens, 4);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn stops_at_context_limit() {
+        let mut model = Model::new(8, 4, 4, 1, 1);
+        model.max_context = 5;
+        let mut cache = PrefixCache::new(2, usize::MAX);
+        let tokens = model.generate_cached(&[1, 2, 3], 10, &mut cache);
+        assert_eq!(tokens, model.generate(&[1, 2, 3], 10));
+        assert_eq!(tokens.len(), 5);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let model = Model::new(8, 4, 4, 1, 1);
+        let mut probe = PrefixCache::new(2, usize::MAX);
+        model.generate_cached(&[1, 2, 3], 0, &mut probe);
+        let block_bytes = probe.used_bytes();
+
+        let mut cache = PrefixCache::new(2, 2 * block_bytes);
+        model.generate_cached(&[1, 2, 0], 0, &mut cache);
+        model.generate_cached(&[3, 4, 0], 0, &mut cache);
+        // touch the first prompt so the second becomes the eviction candidate
+        cache.lookup(&[1, 2]);
+        model.generate_cached(&[5, 6, 0], 0, &mut cache);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.lookup(&[1, 2]).len(), 2);
+        assert!(cache.lookup(&[3, 4]).is_empty());
+        assert!(cache.used_bytes() <= 2 * block_bytes);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+
+/// Error returned when a sequence does not fit the model's context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextOverflow {
+    /// Tokens that would have to fit.
+    pub required: usize,
+    /// Tokens available in the context window.
+    pub available: usize,
+}
+
+impl fmt::Display for ContextOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sequence of {} tokens exceeds the available context of {} tokens",
+            self.required, self.available
+        )
+    }
+}
+
+impl std::error::Error for ContextOverflow {}
+
+/// How [`assemble`] reacts when the prompt is longer than the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncationPolicy {
+    /// Fail with [`ContextOverflow`].
+    Error,
+    /// Drop tokens from the start of the assembled prompt.
+    LeftTruncate,
+    /// Drop whole chunks, worst ranked first. Fails if the prompt still does
+    /// not fit once every chunk is gone.
+    DropLowestRanked,
+    /// Drop tokens from the middle, keeping the beginning and the end.
+    MiddleOut,
+}
+
+/// A retrieved chunk. Lower `rank` means more relevant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub tokens: Vec<usize>,
+    pub rank: usize,
+}
+
+/// Token sequences making up a RAG prompt, assembled in field order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PromptParts {
+    pub system: Vec<usize>,
+    pub chunks: Vec<Chunk>,
+    pub query: Vec<usize>,
+}
+
+/// Describes what [`assemble`] removed to make the prompt fit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TruncationReport {
+    /// Length of the prompt before truncation.
+    pub original_len: usize,
+    /// Number of tokens removed.
+    pub dropped_tokens: usize,
+    /// Indices into [`PromptParts::chunks`] of chunks removed entirely.
+    pub dropped_chunks: Vec<usize>,
+    /// Half-open token ranges of the untruncated prompt that were removed.
+    pub dropped_ranges: Vec<(usize, usize)>,
+}
+
+impl TruncationReport {
+    /// Returns `true` if anything was removed.
+    pub fn truncated(&self) -> bool {
+        self.dropped_tokens > 0
+    }
+}
+
+/// A prompt that fits the context budget together with its truncation report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledPrompt {
+    pub tokens: Vec<usize>,
+    pub report: TruncationReport,
+}
+
+/// Concatenates `parts` and truncates the result to at most `budget` tokens
+/// according to `policy`.
+pub fn assemble(
+    parts: &PromptParts,
+    budget: usize,
+    policy: TruncationPolicy,
+) -> Result<AssembledPrompt, ContextOverflow> {
+    // token range occupied by each chunk in the untruncated prompt
+    let mut spans = Vec::with_capacity(parts.chunks.len());
+    let mut tokens = parts.system.clone();
+    for chunk in &parts.chunks {
+        let start = tokens.len();
+        tokens.extend_from_slice(&chunk.tokens);
+        spans.push((start, tokens.len()));
+    }
+    tokens.extend_from_slice(&parts.query);
+
+    let original_len = tokens.len();
+    let mut report = TruncationReport {
+        original_len,
+        ..Default::default()
+    };
+    if original_len <= budget {
+        return Ok(AssembledPrompt { tokens, report });
+    }
+    let excess = original_len - budget;
+
+    let dropped: Vec<(usize, usize)> = match policy {
+        TruncationPolicy::Error => {
+            return Err(ContextOverflow {
+                required: original_len,
+                available: budget,
+            })
+        }
+        TruncationPolicy::LeftTruncate => vec![(0, excess)],
+        TruncationPolicy::MiddleOut => {
+            let start = budget / 2;
+            vec![(start, start + excess)]
+        }
+        TruncationPolicy::DropLowestRanked => {
+            let mut order: Vec<usize> = (0..parts.chunks.len()).collect();
+            // worst rank first; among equal ranks drop later chunks first
+            order.sort_by(|&a, &b| parts.chunks[b].rank.cmp(&parts.chunks[a].rank).then(b.cmp(&a)));
+            let mut removed = 0;
+            let mut ranges = Vec::new();
+            for idx in order {
+                if removed >= excess {
+                    break;
+                }
+                let (start, end) = spans[idx];
+                removed += end - start;
+                ranges.push((start, end));
+            }
+            if removed < excess {
+                return Err(ContextOverflow {
+                    required: original_len - removed,
+                    available: budget,
+                });
+            }
+            ranges.sort();
+            ranges
+        }
+    };
+
+    for &(start, end) in &dropped {
+        report.dropped_tokens += end - start;
+    }
+    report.dropped_chunks = spans
+        .iter()
+        .enumerate()
+        .filter(|(_, &(start, end))| {
+            end > start && dropped.iter().any(|&(ds, de)| ds <= start && end <= de)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let kept = tokens
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !dropped.iter().any(|&(s, e)| s <= *i && *i < e))
+        .map(|(_, &t)| t)
+        .collect();
+    report.dropped_ranges = dropped;
+    Ok(AssembledPrompt { tokens: kept, report })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts() -> PromptParts {
+        PromptParts {
+            system: vec![1, 1],
+            chunks: vec![
+                Chunk { tokens: vec![2, 2, 2], rank: 0 },
+                Chunk { tokens: vec![3, 3], rank: 2 },
+                Chunk { tokens: vec![4, 4], rank: 1 },
+            ],
+            query: vec![5],
+        }
+    }
+
+    #[test]
+    fn fits_without_truncation() {
+        let out = assemble(&parts(), 10, TruncationPolicy::Error).unwrap();
+        assert_eq!(out.tokens, vec![1, 1, 2, 2, 2, 3, 3, 4, 4, 5]);
+        assert!(!out.report.truncated());
+    }
+
+    #[test]
+    fn error_policy_reports_overflow() {
+        let err = assemble(&parts(), 8, TruncationPolicy::Error).unwrap_err();
+        assert_eq!(err, ContextOverflow { required: 10, available: 8 });
+    }
+
+    #[test]
+    fn left_truncate() {
+        let out = assemble(&parts(), 6, TruncationPolicy::LeftTruncate).unwrap();
+        assert_eq!(out.tokens, vec![2, 3, 3, 4, 4, 5]);
+        assert_eq!(out.report.dropped_tokens, 4);
+        assert_eq!(out.report.dropped_chunks, Vec::<usize>::new());
+        assert_eq!(out.report.dropped_ranges, vec![(0, 4)]);
+    }
+
+    #[test]
+    fn drop_lowest_ranked() {
+        let out = assemble(&parts(), 8, TruncationPolicy::DropLowestRanked).unwrap();
+        assert_eq!(out.tokens, vec![1, 1, 2, 2, 2, 4, 4, 5]);
+        assert_eq!(out.report.dropped_chunks, vec![1]);
+
+        let out = assemble(&parts(), 6, TruncationPolicy::DropLowestRanked).unwrap();
+        assert_eq!(out.tokens, vec![1, 1, 2, 2, 2, 5]);
+        assert_eq!(out.report.dropped_chunks, vec![1, 2]);
+
+        assert!(assemble(&parts(), 2, TruncationPolicy::DropLowestRanked).is_err());
+    }
+
+    #[test]
+    fn middle_out() {
+        let out = assemble(&parts(), 4, TruncationPolicy::MiddleOut).unwrap();
+        assert_eq!(out.tokens, vec![1, 1, 4, 5]);
+        assert_eq!(out.report.dropped_ranges, vec![(2, 8)]);
+        assert_eq!(out.report.dropped_chunks, vec![0, 1]);
+    }
+}
@@ -8,7 +8,7 @@ pub fn quantize_i8(weights: &[Vec<f32>]) -> (Vec<Vec<i8>>, f32) {
         .iter()
         .map(|row| {
             row.iter()
-                .map(|&v| ((v / scale).round().clamp(-128.0, 127.0) as i8))
+                .map(|&v| (v / scale).round().clamp(-128.0, 127.0) as i8)
                 .collect::<Vec<i8>>()
         })
         .collect::<Vec<_>>();
@@ -49,8 +49,8 @@ impl QuantizedLinear {
                 (0..self.weight[0].len())
                     .map(|j| {
                         let mut sum = 0.0f32;
-                        for i in 0..row.len() {
-                            sum += row[i] * self.weight[i][j] as f32 * self.scale;
+                        for (x, w) in row.iter().zip(&self.weight) {
+                            sum += x * w[j] as f32 * self.scale;
                         }
                         sum + self.bias[j]
                     })
@@ -14,8 +14,15 @@ impl RotaryEmbedding {
     ///
     /// Each token vector in `input` has length `dim` and `dim` must be even.
     pub fn forward(&self, input: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        self.forward_from(input, 0)
+    }
+
+    /// Applies rotary positional encoding assuming the first row of `input`
+    /// sits at absolute position `start`.
+    pub fn forward_from(&self, input: &[Vec<f32>], start: usize) -> Vec<Vec<f32>> {
         let mut output = vec![vec![0.0f32; self.dim]; input.len()];
-        for (pos, token) in input.iter().enumerate() {
+        for (idx, token) in input.iter().enumerate() {
+            let pos = start + idx;
             for i in 0..self.dim / 2 {
                 let angle =
                     (pos as f32) / self.base.powf(2.0 * i as f32 / self.dim as f32);
@@ -23,8 +30,8 @@ impl RotaryEmbedding {
                 let sin = angle.sin();
                 let x1 = token[2 * i];
                 let x2 = token[2 * i + 1];
-                output[pos][2 * i] = x1 * cos - x2 * sin;
-                output[pos][2 * i + 1] = x1 * sin + x2 * cos;
+                output[idx][2 * i] = x1 * cos - x2 * sin;
+                output[idx][2 * i + 1] = x1 * sin + x2 * cos;
             }
         }
         output
@@ -43,4 +50,12 @@ mod tests {
         assert_eq!(output.len(), input.len());
         assert_eq!(output[0].len(), input[0].len());
     }
+
+    #[test]
+    fn rope_offset_matches_full() {
+        let rope = RotaryEmbedding::new(4);
+        let input = vec![vec![1.0f32, 0.0, 0.5, -0.5], vec![0.2, 0.4, -0.1, 0.3]];
+        let full = rope.forward(&input);
+        assert_eq!(rope.forward_from(&input[1..], 1)[0], full[1]);
+    }
 }
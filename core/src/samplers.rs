@@ -0,0 +1,255 @@
+// Stateful samplers.
+// Fixed-temperature sampling lets the surprise of long generations drift.
+// Mirostat keeps the observed surprise (in bits) near a target `tau` by
+// adapting a truncation threshold `mu` after every token; locally typical
+// sampling keeps the tokens whose surprise is closest to the entropy of the
+// distribution. The running state is reported with each generation result.
+use rand::Rng;
+use serde::Serialize;
+
+use crate::sampling;
+
+/// Sampling algorithm applied to the tempered distribution.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SamplerKind {
+    /// Plain sampling at the configured temperature; greedy at zero.
+    #[default]
+    Temperature,
+    /// Locally typical sampling keeping the smallest set of tokens, ordered
+    /// by how close their surprise is to the entropy, whose mass reaches
+    /// `mass`.
+    Typical { mass: f32 },
+    /// Mirostat v1: top-k sampling with `k` derived from a Zipf estimate over
+    /// the `m` most likely tokens.
+    MirostatV1 { tau: f32, eta: f32, m: usize },
+    /// Mirostat v2: drops tokens whose surprise exceeds `mu`.
+    MirostatV2 { tau: f32, eta: f32 },
+}
+
+/// Running state of a [`Sampler`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SamplerState {
+    /// Mirostat truncation threshold in bits. Zero for other samplers.
+    pub mu: f32,
+    /// Surprise in bits of the last sampled token, measured on the
+    /// truncated distribution for Mirostat v2 and on the tempered one
+    /// otherwise.
+    pub last_surprise: f32,
+    /// Average surprise in bits over all sampled tokens.
+    pub mean_surprise: f32,
+    /// Number of tokens sampled.
+    pub steps: usize,
+}
+
+/// A sampler together with its state for one request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampler {
+    kind: SamplerKind,
+    state: SamplerState,
+}
+
+fn surprise(p: f32) -> f32 {
+    -p.max(f32::MIN_POSITIVE).log2()
+}
+
+/// Keeps the locally typical tokens of `probs` covering at least `mass` and
+/// zeroes the rest.
+pub fn typical_filter(probs: &[f32], mass: f32) -> Vec<f32> {
+    let entropy: f32 = probs.iter().filter(|&&p| p > 0.0).map(|&p| -p * p.ln()).sum();
+    let mut order: Vec<usize> = (0..probs.len()).filter(|&i| probs[i] > 0.0).collect();
+    order.sort_by(|&a, &b| {
+        let da = (-probs[a].ln() - entropy).abs();
+        let db = (-probs[b].ln() - entropy).abs();
+        da.total_cmp(&db).then(b.cmp(&a))
+    });
+    let mut kept = vec![0.0; probs.len()];
+    let mut total = 0.0;
+    for i in order {
+        kept[i] = probs[i];
+        total += probs[i];
+        if total >= mass {
+            break;
+        }
+    }
+    kept
+}
+
+// Zipf exponent estimated from the `m` most likely probabilities
+fn zipf_exponent(sorted: &[f32], m: usize) -> f32 {
+    let m = m.min(sorted.len());
+    let mut num = 0.0;
+    let mut den = 0.0;
+    for i in 0..m.saturating_sub(1) {
+        if sorted[i + 1] <= 0.0 {
+            break;
+        }
+        let t = ((i + 2) as f32 / (i + 1) as f32).ln();
+        let b = (sorted[i] / sorted[i + 1]).ln();
+        num += t * b;
+        den += t * t;
+    }
+    if den > 0.0 {
+        num / den
+    } else {
+        1.0
+    }
+}
+
+impl Sampler {
+    /// Creates a sampler. Mirostat starts with `mu = 2 * tau`.
+    pub fn new(kind: SamplerKind) -> Self {
+        let mu = match kind {
+            SamplerKind::MirostatV1 { tau, .. } | SamplerKind::MirostatV2 { tau, .. } => 2.0 * tau,
+            _ => 0.0,
+        };
+        Self {
+            kind,
+            state: SamplerState {
+                mu,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Algorithm this sampler runs.
+    pub fn kind(&self) -> SamplerKind {
+        self.kind
+    }
+
+    /// Current state, updated after every sampled token.
+    pub fn state(&self) -> SamplerState {
+        self.state
+    }
+
+    /// Draws the next token from `logits` and updates the state.
+    ///
+    /// The adaptive samplers replace greedy decoding, so a temperature of
+    /// zero or below is treated as `1.0` for them.
+    pub fn sample<R: Rng + ?Sized>(&mut self, logits: &[f32], temperature: f32, rng: &mut R) -> usize {
+        let tempered = |t: f32| if t <= 0.0 { 1.0 } else { t };
+        let (token, p) = match self.kind {
+            SamplerKind::Temperature => {
+                let probs = sampling::probabilities(logits, temperature);
+                let token = sampling::sample(&probs, rng);
+                (token, probs[token])
+            }
+            SamplerKind::Typical { mass } => {
+                let probs = sampling::probabilities(logits, tempered(temperature));
+                let token = sampling::sample(&typical_filter(&probs, mass), rng);
+                (token, probs[token])
+            }
+            SamplerKind::MirostatV1 { tau, eta, m } => {
+                let probs = sampling::probabilities(logits, tempered(temperature));
+                let mut sorted = probs.clone();
+                sorted.sort_by(|a, b| b.total_cmp(a));
+                let s = zipf_exponent(&sorted, m);
+                let eps = s - 1.0;
+                let n = probs.len() as f32;
+                let k = ((eps * 2f32.powf(self.state.mu)) / (1.0 - n.powf(-eps))).powf(1.0 / s);
+                let k = if k.is_finite() { (k.round() as usize).clamp(1, probs.len()) } else { probs.len() };
+                let mut kept = vec![0.0; probs.len()];
+                for i in sampling::top_n(&probs, k) {
+                    kept[i] = probs[i];
+                }
+                let token = sampling::sample(&kept, rng);
+                self.state.mu -= eta * (surprise(probs[token]) - tau);
+                (token, probs[token])
+            }
+            SamplerKind::MirostatV2 { tau, eta } => {
+                let probs = sampling::probabilities(logits, tempered(temperature));
+                let mut kept: Vec<f32> = probs
+                    .iter()
+                    .map(|&p| if surprise(p) > self.state.mu { 0.0 } else { p })
+                    .collect();
+                if kept.iter().all(|&p| p == 0.0) {
+                    let best = sampling::argmax(&probs);
+                    kept[best] = probs[best];
+                }
+                let total: f32 = kept.iter().sum();
+                let token = sampling::sample(&kept, rng);
+                // surprise under the truncated distribution drives the update
+                let p = kept[token] / total;
+                self.state.mu -= eta * (surprise(p) - tau);
+                (token, p)
+            }
+        };
+        let s = surprise(p);
+        self.state.steps += 1;
+        self.state.last_surprise = s;
+        self.state.mean_surprise += (s - self.state.mean_surprise) / self.state.steps as f32;
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn zipf_logits(n: usize) -> Vec<f32> {
+        (0..n).map(|i| -((i + 1) as f32).ln()).collect()
+    }
+
+    #[test]
+    fn typical_keeps_mass() {
+        let probs = [0.5, 0.3, 0.15, 0.05];
+        let kept = typical_filter(&probs, 0.5);
+        assert!(kept.iter().sum::<f32>() >= 0.5);
+        assert!(kept.iter().filter(|&&p| p > 0.0).count() < probs.len());
+        assert_eq!(typical_filter(&probs, 1.0), probs);
+    }
+
+    #[test]
+    fn nan_probabilities_do_not_panic() {
+        let kept = typical_filter(&[0.5, f32::NAN, 0.2], 0.9);
+        assert_eq!(kept.len(), 3);
+        let mut rng = StdRng::seed_from_u64(0);
+        for kind in [
+            SamplerKind::Temperature,
+            SamplerKind::Typical { mass: 0.9 },
+            SamplerKind::MirostatV1 { tau: 3.0, eta: 0.1, m: 2 },
+            SamplerKind::MirostatV2 { tau: 3.0, eta: 0.1 },
+        ] {
+            for temperature in [0.0, 1.0] {
+                let token = Sampler::new(kind).sample(&[0.0, f32::NAN, 1.0], temperature, &mut rng);
+                assert!(token < 3);
+            }
+        }
+        // Mirostat v2 falls back to the argmax once mu truncates everything
+        let mut sampler = Sampler::new(SamplerKind::MirostatV2 { tau: 0.0, eta: 100.0 });
+        for _ in 0..3 {
+            assert!(sampler.sample(&[0.0, f32::NAN, 1.0, f32::NAN], 1.0, &mut rng) < 4);
+        }
+        assert!(sampling::argmax(&[f32::NAN, 1.0]) < 2);
+    }
+
+    #[test]
+    fn temperature_sampler_tracks_surprise() {
+        let mut sampler = Sampler::new(SamplerKind::Temperature);
+        let mut rng = StdRng::seed_from_u64(0);
+        let token = sampler.sample(&[0.0, 1.0, 0.0], 0.0, &mut rng);
+        assert_eq!(token, 1);
+        assert_eq!(sampler.state().steps, 1);
+        assert_eq!(sampler.state().last_surprise, 0.0);
+    }
+
+    #[test]
+    fn mirostat_converges_to_target() {
+        let logits = zipf_logits(200);
+        for kind in [
+            SamplerKind::MirostatV1 { tau: 3.0, eta: 0.1, m: 100 },
+            SamplerKind::MirostatV2 { tau: 3.0, eta: 0.1 },
+        ] {
+            let mut sampler = Sampler::new(kind);
+            assert_eq!(sampler.state().mu, 6.0);
+            let mut rng = StdRng::seed_from_u64(7);
+            for _ in 0..2000 {
+                sampler.sample(&logits, 1.0, &mut rng);
+            }
+            let state = sampler.state();
+            assert_eq!(state.steps, 2000);
+            assert!((state.mean_surprise - 3.0).abs() < 0.5, "{:?}: {:?}", kind, state);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use rand::Rng;
+
+/// Returns the index of the largest value in `values`.
+///
+/// Ties resolve to the last maximal index, matching the greedy decoding used
+/// throughout the crate. An empty slice yields `0`.
+pub fn argmax(values: &[f32]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// Converts logits into a probability distribution at the given temperature.
+///
+/// A temperature of zero or below yields a one-hot distribution on the argmax,
+/// so greedy decoding can share the same code path as sampling.
+pub fn probabilities(logits: &[f32], temperature: f32) -> Vec<f32> {
+    if temperature <= 0.0 {
+        let mut probs = vec![0.0f32; logits.len()];
+        if !logits.is_empty() {
+            probs[argmax(logits)] = 1.0;
+        }
+        return probs;
+    }
+    let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits
+        .iter()
+        .map(|x| ((*x - max) / temperature).exp())
+        .collect();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|e| e / sum).collect()
+}
+
+/// Computes log-probabilities from raw logits using a numerically stable
+/// log-sum-exp.
+pub fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|x| (*x - max).exp()).sum::<f32>().ln() + max;
+    logits.iter().map(|x| x - log_sum).collect()
+}
+
+/// Returns the indices of the `n` largest values in descending order.
+///
+/// Ties are ordered by descending index so the first entry agrees with
+/// [`argmax`].
+pub fn top_n(values: &[f32], n: usize) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    indices.sort_by(|&a, &b| values[b].total_cmp(&values[a]).then(b.cmp(&a)));
+    indices.truncate(n);
+    indices
+}
+
+/// Draws an index from the (not necessarily normalized) distribution `probs`.
+pub fn sample<R: Rng + ?Sized>(probs: &[f32], rng: &mut R) -> usize {
+    let total: f32 = probs.iter().sum();
+    if total <= 0.0 {
+        return argmax(probs);
+    }
+    let mut threshold = rng.gen::<f32>() * total;
+    let mut last = 0;
+    for (i, &p) in probs.iter().enumerate() {
+        if p <= 0.0 {
+            continue;
+        }
+        if threshold < p {
+            return i;
+        }
+        threshold -= p;
+        last = i;
+    }
+    // rounding can leave a tiny remainder; fall back to the last viable index
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn zero_temperature_is_one_hot() {
+        let probs = probabilities(&[0.1, 2.0, -1.0], 0.0);
+        assert_eq!(probs, vec![0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn probabilities_sum_to_one() {
+        let probs = probabilities(&[0.1, 2.0, -1.0], 0.7);
+        let sum: f32 = probs.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-5);
+        assert_eq!(argmax(&probs), 1);
+    }
+
+    #[test]
+    fn log_softmax_matches_probabilities() {
+        let logits = [0.5, -1.0, 2.0];
+        let probs = probabilities(&logits, 1.0);
+        for (lp, p) in log_softmax(&logits).iter().zip(&probs) {
+            assert!((lp.exp() - p).abs() < 1e-5);
+        }
+        assert_eq!(top_n(&logits, 2), vec![2, 0]);
+    }
+
+    #[test]
+    fn sample_skips_zero_mass() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            assert_eq!(sample(&[0.0, 0.0, 3.0, 0.0], &mut rng), 2);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// Continuous-batching generation scheduler.
+// Every step runs one batched forward over all active requests: new requests
+// contribute their whole prompt (prefill) while running ones contribute their
+// last generated token. Finished requests leave the batch immediately and
+// queued ones take their place on the next step.
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::kv_cache::KvCache;
+use crate::model::Model;
+use crate::sampling;
+
+/// A greedy generation request submitted to a [`Scheduler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationRequest {
+    /// Prompt token ids. Empty prompts and prompts that do not fit the
+    /// model's context window finish immediately.
+    pub prompt: Vec<usize>,
+    /// Maximum number of tokens to generate, further capped by the space left
+    /// in the model's context window.
+    pub max_tokens: usize,
+    /// Token that ends generation early. It is still streamed to the caller.
+    pub stop_token: Option<usize>,
+}
+
+struct Active {
+    cache: KvCache,
+    pending: Vec<usize>,
+    remaining: usize,
+    stop_token: Option<usize>,
+    sender: Sender<usize>,
+}
+
+/// Interleaves the decode steps of many requests into shared batched forward
+/// passes over one [`Model`].
+pub struct Scheduler {
+    model: Arc<Model>,
+    max_batch: usize,
+    queue: VecDeque<(GenerationRequest, Sender<usize>)>,
+    active: Vec<Active>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler running at most `max_batch` requests per step.
+    pub fn new(model: Arc<Model>, max_batch: usize) -> Self {
+        assert!(max_batch > 0, "batch size must be positive");
+        Self {
+            model,
+            max_batch,
+            queue: VecDeque::new(),
+            active: Vec::new(),
+        }
+    }
+
+    /// Queues `request` and returns the channel its tokens are streamed to.
+    /// The channel closes once the request finishes.
+    pub fn submit(&mut self, request: GenerationRequest) -> Receiver<usize> {
+        let (tx, rx) = mpsc::channel();
+        self.enqueue(request, tx);
+        rx
+    }
+
+    fn enqueue(&mut self, request: GenerationRequest, sender: Sender<usize>) {
+        self.queue.push_back((request, sender));
+    }
+
+    /// Number of requests currently in the batch.
+    pub fn active(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Number of requests waiting for a batch slot.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` when no request is running or waiting.
+    pub fn is_idle(&self) -> bool {
+        self.active.is_empty() && self.queue.is_empty()
+    }
+
+    fn admit(&mut self) {
+        while self.active.len() < self.max_batch {
+            let (request, sender) = match self.queue.pop_front() {
+                Some(job) => job,
+                None => break,
+            };
+            // dropping the sender closes the channel for requests with nothing to do
+            let room = self.model.max_context.saturating_sub(request.prompt.len());
+            let remaining = request.max_tokens.min(room);
+            if request.prompt.is_empty() || remaining == 0 {
+                continue;
+            }
+            self.active.push(Active {
+                cache: KvCache::default(),
+                pending: request.prompt,
+                remaining,
+                stop_token: request.stop_token,
+                sender,
+            });
+        }
+    }
+
+    /// Admits queued requests and advances every active request by one token
+    /// using a single batched forward pass. Returns the number of requests
+    /// that took part in the step.
+    pub fn step(&mut self) -> usize {
+        self.admit();
+        if self.active.is_empty() {
+            return 0;
+        }
+        let inputs: Vec<Vec<usize>> = self
+            .active
+            .iter_mut()
+            .map(|a| std::mem::take(&mut a.pending))
+            .collect();
+        let logits = {
+            let mut caches: Vec<&mut KvCache> = self.active.iter_mut().map(|a| &mut a.cache).collect();
+            self.model.forward_batch_cached(&inputs, &mut caches)
+        };
+        let batch = self.active.len();
+        let mut finished = Vec::new();
+        for (i, (request, seq_logits)) in self.active.iter_mut().zip(&logits).enumerate() {
+            let next = sampling::argmax(&seq_logits[seq_logits.len() - 1]);
+            request.remaining -= 1;
+            request.pending.push(next);
+            // a closed receiver means the caller is gone, so the request is cancelled
+            let delivered = request.sender.send(next).is_ok();
+            if !delivered || request.remaining == 0 || request.stop_token == Some(next) {
+                finished.push(i);
+            }
+        }
+        for i in finished.into_iter().rev() {
+            self.active.swap_remove(i);
+        }
+        batch
+    }
+
+    /// Steps until every submitted request has finished.
+    pub fn run_until_idle(&mut self) {
+        while !self.is_idle() {
+            self.step();
+        }
+    }
+
+    /// Runs the scheduler on a background thread and returns a handle for
+    /// submitting requests from any thread. The thread exits once every
+    /// handle has been dropped and all work is done.
+    pub fn spawn(model: Arc<Model>, max_batch: usize) -> (SchedulerHandle, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel::<(GenerationRequest, Sender<usize>)>();
+        let mut scheduler = Scheduler::new(model, max_batch);
+        let thread = std::thread::spawn(move || {
+            let mut open = true;
+            loop {
+                if scheduler.is_idle() {
+                    if !open {
+                        break;
+                    }
+                    // nothing to do: block until a request arrives
+                    match rx.recv() {
+                        Ok((request, sender)) => scheduler.enqueue(request, sender),
+                        Err(_) => break,
+                    }
+                }
+                loop {
+                    match rx.try_recv() {
+                        Ok((request, sender)) => scheduler.enqueue(request, sender),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            open = false;
+                            break;
+                        }
+                    }
+                }
+                scheduler.step();
+            }
+        });
+        (SchedulerHandle { sender: tx }, thread)
+    }
+}
+
+/// Cloneable handle used to submit requests to a scheduler thread started
+/// with [`Scheduler::spawn`].
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    sender: Sender<(GenerationRequest, Sender<usize>)>,
+}
+
+impl SchedulerHandle {
+    /// Submits `request` and returns the channel its tokens are streamed to.
+    /// If the scheduler thread has stopped the channel is closed immediately.
+    pub fn submit(&self, request: GenerationRequest) -> Receiver<usize> {
+        let (tx, rx) = mpsc::channel();
+        let _ = self.sender.send((request, tx));
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(prompt: Vec<usize>, max_tokens: usize) -> GenerationRequest {
+        GenerationRequest {
+            prompt,
+            max_tokens,
+            stop_token: None,
+        }
+    }
+
+    #[test]
+    fn batched_requests_match_sequential_generation() {
+        let model = Arc::new(Model::new(6, 6, 4, 2, 2));
+        let mut scheduler = Scheduler::new(model.clone(), 2);
+        let prompts = [vec![0usize, 1], vec![2], vec![3, 4, 5]];
+        let receivers: Vec<_> = prompts
+            .iter()
+            .enumerate()
+            .map(|(i, p)| scheduler.submit(request(p.clone(), 2 + i)))
+            .collect();
+        assert_eq!(scheduler.step(), 2);
+        assert_eq!(scheduler.queued(), 1);
+        scheduler.run_until_idle();
+        for (i, (prompt, rx)) in prompts.iter().zip(receivers).enumerate() {
+            let streamed: Vec<usize> = rx.iter().collect();
+            assert_eq!(streamed, model.generate(prompt, 2 + i)[prompt.len()..]);
+        }
+    }
+
+    #[test]
+    fn dropped_receiver_cancels_request() {
+        let model = Arc::new(Model::new(4, 4, 4, 1, 1));
+        let mut scheduler = Scheduler::new(model, 4);
+        drop(scheduler.submit(request(vec![1], 100)));
+        scheduler.step();
+        assert!(scheduler.is_idle());
+    }
+
+    #[test]
+    fn spawned_scheduler_serves_threads() {
+        let model = Arc::new(Model::new(6, 6, 4, 1, 1));
+        let (handle, thread) = Scheduler::spawn(model.clone(), 3);
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let handle = handle.clone();
+                std::thread::spawn(move || handle.submit(request(vec![i, i + 1], 3)).iter().collect::<Vec<_>>())
+            })
+            .collect();
+        for (i, worker) in workers.into_iter().enumerate() {
+            let prompt = vec![i, i + 1];
+            assert_eq!(worker.join().unwrap(), model.generate(&prompt, 3)[2..]);
+        }
+        drop(handle);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+use crate::kv_cache::KvCache;
+use crate::model::Model;
+use crate::sampling;
+use serde::Serialize;
+
+/// Log-likelihood of a continuation given its context.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContinuationScore {
+    /// Sum of the continuation token log-probabilities.
+    pub total: f32,
+    /// Log-probability of each continuation token.
+    pub token_logprobs: Vec<f32>,
+}
+
+impl ContinuationScore {
+    /// Average log-probability per continuation token, useful when comparing
+    /// candidates of different lengths.
+    pub fn mean(&self) -> f32 {
+        if self.token_logprobs.is_empty() {
+            0.0
+        } else {
+            self.total / self.token_logprobs.len() as f32
+        }
+    }
+}
+
+impl Model {
+    /// Scores `continuation` as a follow-up to `context`.
+    ///
+    /// Only the continuation tokens contribute to the result; the context is
+    /// used purely for conditioning. Returns `None` when `context` is empty,
+    /// since the first continuation token needs a preceding position to be
+    /// predicted from, or when a token is outside the vocabulary.
+    pub fn score(&self, context: &[usize], continuation: &[usize]) -> Option<ContinuationScore> {
+        if context.is_empty() || !self.in_vocab(context) || !self.in_vocab(continuation) {
+            return None;
+        }
+        if continuation.is_empty() {
+            return Some(ContinuationScore {
+                total: 0.0,
+                token_logprobs: Vec::new(),
+            });
+        }
+        let mut input = Vec::with_capacity(context.len() + continuation.len() - 1);
+        input.extend_from_slice(context);
+        input.extend_from_slice(&continuation[..continuation.len() - 1]);
+        let logits = self.forward(&input);
+        let token_logprobs: Vec<f32> = continuation
+            .iter()
+            .enumerate()
+            .map(|(i, &tok)| sampling::log_softmax(&logits[context.len() - 1 + i])[tok])
+            .collect();
+        Some(ContinuationScore {
+            total: token_logprobs.iter().sum(),
+            token_logprobs,
+        })
+    }
+
+    /// Scores every candidate continuation against the same `context`.
+    ///
+    /// The context is encoded once into a [`KvCache`] and every candidate
+    /// extends its own copy of that cache in a single batched forward pass.
+    /// Results are returned in the order of `candidates`, or `None` under
+    /// the same conditions as [`Model::score`].
+    pub fn score_batch(&self, context: &[usize], candidates: &[Vec<usize>]) -> Option<Vec<ContinuationScore>> {
+        if context.is_empty() || !self.in_vocab(context) || !candidates.iter().all(|c| self.in_vocab(c)) {
+            return None;
+        }
+        let mut cache = KvCache::default();
+        let context_logits = self.forward_cached(context, &mut cache);
+        let first = sampling::log_softmax(context_logits.last().expect("context is not empty"));
+        // the last token of a candidate is only predicted, never fed back
+        let inputs: Vec<Vec<usize>> = candidates
+            .iter()
+            .map(|c| c[..c.len().saturating_sub(1)].to_vec())
+            .collect();
+        let mut caches = vec![cache; candidates.len()];
+        let mut refs: Vec<&mut KvCache> = caches.iter_mut().collect();
+        let logits = self.forward_batch_cached(&inputs, &mut refs);
+        let scores = candidates
+            .iter()
+            .zip(logits)
+            .map(|(candidate, rows)| {
+                let token_logprobs: Vec<f32> = candidate
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &tok)| match i {
+                        0 => first[tok],
+                        _ => sampling::log_softmax(&rows[i - 1])[tok],
+                    })
+                    .collect();
+                ContinuationScore {
+                    total: token_logprobs.iter().sum(),
+                    token_logprobs,
+                }
+            })
+            .collect();
+        Some(scores)
+    }
+
+    fn in_vocab(&self, tokens: &[usize]) -> bool {
+        tokens.iter().all(|&t| t < self.vocab_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loss::cross_entropy;
+
+    #[test]
+    fn score_matches_cross_entropy() {
+        let model = Model::new(4, 4, 4, 1, 1);
+        let tokens = [1usize, 3, 0, 2];
+        let logits = model.forward(&tokens[..3]);
+        let loss = cross_entropy(&logits[1..], &tokens[2..]);
+        let score = model.score(&tokens[..2], &tokens[2..]).unwrap();
+        assert_eq!(score.token_logprobs.len(), 2);
+        assert!((score.mean() + loss).abs() < 1e-5);
+    }
+
+    #[test]
+    fn batch_preserves_order() {
+        let model = Model::new(4, 4, 4, 1, 1);
+        let context = [2usize, 1];
+        let candidates = vec![vec![0], vec![3, 1], vec![], vec![1, 1, 2]];
+        let batch = model.score_batch(&context, &candidates).unwrap();
+        assert_eq!(batch.len(), candidates.len());
+        for (score, cand) in batch.iter().zip(&candidates) {
+            let single = model.score(&context, cand).unwrap();
+            assert_eq!(score.token_logprobs.len(), single.token_logprobs.len());
+            for (a, b) in score.token_logprobs.iter().zip(&single.token_logprobs) {
+                assert!((a - b).abs() < 1e-4, "{} vs {}", a, b);
+            }
+        }
+        assert_eq!(batch[2].total, 0.0);
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let model = Model::new(4, 4, 4, 1, 1);
+        assert!(model.score(&[], &[1]).is_none());
+        assert!(model.score(&[1], &[4]).is_none());
+        assert!(model.score(&[9], &[1]).is_none());
+        assert!(model.score_batch(&[], &[vec![1]]).is_none());
+        assert!(model.score_batch(&[1], &[vec![1], vec![2, 4]]).is_none());
+        assert!(model.score_batch(&[1], &[vec![4]]).is_none());
+    }
+
+    #[test]
+    fn batch_of_single_tokens_needs_no_extension() {
+        let model = Model::new(4, 4, 4, 1, 1);
+        let batch = model.score_batch(&[2, 1], &[vec![3], vec![]]).unwrap();
+        assert_eq!(batch[0].token_logprobs.len(), 1);
+        assert!((batch[0].total - model.score(&[2, 1], &[3]).unwrap().total).abs() < 1e-4);
+        assert!(batch[1].token_logprobs.is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+// Speculative decoding with a smaller draft model.
+// The draft proposes a few tokens and the target verifies them in one forward
+// pass. Proposals are accepted with probability min(1, p(x) / q(x)); on
+// rejection a replacement is drawn from max(0, p - q), which keeps the output
+// distribution identical to sampling from the target alone.
+use crate::model::Model;
+use crate::sampling;
+use rand::Rng;
+
+/// Settings for [`Model::generate_speculative`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeculativeConfig {
+    /// Number of tokens proposed by the draft model per verification step.
+    pub lookahead: usize,
+    /// Sampling temperature shared by draft and target. Zero means greedy.
+    pub temperature: f32,
+}
+
+impl Default for SpeculativeConfig {
+    fn default() -> Self {
+        Self {
+            lookahead: 4,
+            temperature: 1.0,
+        }
+    }
+}
+
+/// Acceptance statistics collected during speculative generation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpeculativeStats {
+    /// Draft tokens submitted for verification.
+    pub proposed: usize,
+    /// Draft tokens accepted by the target model.
+    pub accepted: usize,
+    /// Forward passes run on the draft model.
+    pub draft_forwards: usize,
+    /// Forward passes run on the target model.
+    pub target_forwards: usize,
+}
+
+impl SpeculativeStats {
+    /// Fraction of proposed tokens that were accepted.
+    pub fn acceptance_rate(&self) -> f32 {
+        if self.proposed == 0 {
+            0.0
+        } else {
+            self.accepted as f32 / self.proposed as f32
+        }
+    }
+
+    /// Average number of tokens produced per target forward pass.
+    pub fn tokens_per_target_forward(&self, generated: usize) -> f32 {
+        if self.target_forwards == 0 {
+            0.0
+        } else {
+            generated as f32 / self.target_forwards as f32
+        }
+    }
+}
+
+impl Model {
+    /// Generates `steps` tokens using `draft` to propose continuations that
+    /// are verified by `self`.
+    ///
+    /// Both models must share the tokenizer, i.e. have the same vocabulary
+    /// size. Generation stops once the sequence fills the smaller
+    /// `max_context` of the two models. The returned vector contains the
+    /// original input followed by the generated tokens, alongside the
+    /// acceptance statistics for the run.
+    pub fn generate_speculative<R: Rng + ?Sized>(
+        &self,
+        draft: &Model,
+        input: &[usize],
+        steps: usize,
+        config: SpeculativeConfig,
+        rng: &mut R,
+    ) -> (Vec<usize>, SpeculativeStats) {
+        assert_eq!(
+            self.vocab_size(),
+            draft.vocab_size(),
+            "draft and target models must share a vocabulary"
+        );
+        let mut stats = SpeculativeStats::default();
+        let mut tokens = input.to_vec();
+        if tokens.is_empty() {
+            return (tokens, stats);
+        }
+        let room = self.context_room(input.len()).min(draft.context_room(input.len()));
+        let end = input.len() + steps.min(room);
+
+        while tokens.len() < end {
+            // leave room for the token sampled from the target after the proposals
+            let k = config.lookahead.min(end - tokens.len() - 1);
+            let base = tokens.len();
+
+            let mut proposed = tokens.clone();
+            let mut draft_probs = Vec::with_capacity(k);
+            for _ in 0..k {
+                let logits = draft.forward(&proposed);
+                stats.draft_forwards += 1;
+                let q = sampling::probabilities(&logits[logits.len() - 1], config.temperature);
+                proposed.push(sampling::sample(&q, rng));
+                draft_probs.push(q);
+            }
+
+            let logits = self.forward(&proposed);
+            stats.target_forwards += 1;
+
+            let mut rejected = false;
+            for (i, q) in draft_probs.iter().enumerate() {
+                let p = sampling::probabilities(&logits[base - 1 + i], config.temperature);
+                let x = proposed[base + i];
+                stats.proposed += 1;
+                let ratio = if q[x] > 0.0 { p[x] / q[x] } else { 0.0 };
+                if rng.gen::<f32>() < ratio {
+                    stats.accepted += 1;
+                    tokens.push(x);
+                } else {
+                    let residual: Vec<f32> = p
+                        .iter()
+                        .zip(q)
+                        .map(|(pi, qi)| (pi - qi).max(0.0))
+                        .collect();
+                    let next = if residual.iter().sum::<f32>() > 0.0 {
+                        sampling::sample(&residual, rng)
+                    } else {
+                        sampling::sample(&p, rng)
+                    };
+                    tokens.push(next);
+                    rejected = true;
+                    break;
+                }
+            }
+
+            if !rejected {
+                let p = sampling::probabilities(&logits[base - 1 + k], config.temperature);
+                tokens.push(sampling::sample(&p, rng));
+            }
+        }
+        (tokens, stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn greedy_matches_plain_generation() {
+        let target = Model::new(4, 4, 4, 1, 1);
+        let mut draft = Model::new(4, 4, 4, 1, 1);
+        draft.output_layer.bias = vec![0.0, 0.5, 0.0, 0.0];
+        let mut rng = StdRng::seed_from_u64(1);
+        let config = SpeculativeConfig { lookahead: 3, temperature: 0.0 };
+        let (tokens, stats) = target.generate_speculative(&draft, &[0, 2], 7, config, &mut rng);
+        assert_eq!(tokens, target.generate(&[0, 2], 7));
+        assert!(stats.target_forwards <= 7);
+    }
+
+    #[test]
+    fn identical_draft_accepts_everything() {
+        let target = Model::new(4, 4, 4, 1, 1);
+        let draft = Model::new(4, 4, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(2);
+        let config = SpeculativeConfig { lookahead: 4, temperature: 1.0 };
+        let (tokens, stats) = target.generate_speculative(&draft, &[1], 10, config, &mut rng);
+        assert_eq!(tokens.len(), 11);
+        assert_eq!(stats.accepted, stats.proposed);
+        assert_eq!(stats.target_forwards, 2);
+        assert!((stats.acceptance_rate() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stops_at_context_limit() {
+        let mut target = Model::new(4, 4, 4, 1, 1);
+        target.max_context = 6;
+        let mut draft = Model::new(4, 4, 4, 1, 1);
+        draft.max_context = 5;
+        let mut rng = StdRng::seed_from_u64(4);
+        let config = SpeculativeConfig { lookahead: 4, temperature: 1.0 };
+        let (tokens, _) = target.generate_speculative(&draft, &[1, 2], 10, config, &mut rng);
+        assert_eq!(tokens.len(), 5);
+    }
+
+    #[test]
+    fn preserves_target_distribution() {
+        let mut target = Model::new(4, 4, 4, 1, 1);
+        target.output_layer.bias = vec![0.3, -0.2, 0.1, 0.0];
+        let mut draft = Model::new(4, 4, 4, 1, 1);
+        draft.output_layer.bias = vec![-1.0, 1.0, 0.5, 0.0];
+        let input = [3usize];
+        let logits = target.forward(&input);
+        let expected = sampling::probabilities(&logits[0], 1.0);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let config = SpeculativeConfig { lookahead: 2, temperature: 1.0 };
+        let runs = 4000;
+        let mut counts = [0usize; 4];
+        let mut total = SpeculativeStats::default();
+        for _ in 0..runs {
+            let (tokens, stats) = target.generate_speculative(&draft, &input, 3, config, &mut rng);
+            counts[tokens[1]] += 1;
+            total.proposed += stats.proposed;
+            total.accepted += stats.accepted;
+        }
+        for (c, p) in counts.iter().zip(&expected) {
+            let freq = *c as f32 / runs as f32;
+            assert!((freq - p).abs() < 0.03, "freq {} expected {}", freq, p);
+        }
+        assert!(total.accepted < total.proposed);
+    }
+}
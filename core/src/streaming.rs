@@ -0,0 +1,237 @@
+// Streaming generation.
+// `TokenStream` yields every token as soon as it is chosen, together with the
+// text it adds to the output, so callers can forward tokens to a client
+// without waiting for the whole sequence. Generation can be cancelled from
+// another thread through a `CancelToken`.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::Serialize;
+
+use crate::detokenizer::IncrementalDecoder;
+use crate::generation::{GeneratedToken, GenerationConfig};
+use crate::model::Model;
+use crate::samplers::{Sampler, SamplerState};
+use crate::tokenizer::BpeTokenizer;
+
+/// Shared flag used to stop a [`TokenStream`] from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token that is not yet cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation. Streams sharing this token end before their
+    /// next forward pass.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` once [`CancelToken::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A token yielded by a [`TokenStream`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamEvent {
+    #[serde(flatten)]
+    pub generated: GeneratedToken,
+    /// Text added to the output by this token. Empty without a tokenizer or
+    /// while the token ends in the middle of a multi-byte character.
+    pub text: String,
+}
+
+/// Iterator generating one token per call to `next`.
+pub struct TokenStream<'a, R: Rng + ?Sized> {
+    model: &'a Model,
+    config: GenerationConfig,
+    sampler: Sampler,
+    rng: &'a mut R,
+    tokens: Vec<usize>,
+    produced: usize,
+    decoder: Option<IncrementalDecoder<'a>>,
+    cancel: Option<CancelToken>,
+    stop_token: Option<usize>,
+    done: bool,
+}
+
+impl<'a, R: Rng + ?Sized> TokenStream<'a, R> {
+    /// Decodes tokens incrementally with `tokenizer` to fill
+    /// [`StreamEvent::text`].
+    pub fn with_tokenizer(mut self, tokenizer: &'a BpeTokenizer) -> Self {
+        self.decoder = Some(IncrementalDecoder::new(tokenizer));
+        self
+    }
+
+    /// Ends the stream once `cancel` is cancelled.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Ends the stream after yielding `token`.
+    pub fn with_stop_token(mut self, token: usize) -> Self {
+        self.stop_token = Some(token);
+        self
+    }
+
+    /// Returns text still buffered by the decoder, which can happen when the
+    /// stream was cancelled or hit the context limit mid-character.
+    pub fn flush(&mut self) -> String {
+        self.decoder.as_mut().map(|d| d.finish()).unwrap_or_default()
+    }
+
+    /// Current state of the request's sampler.
+    pub fn sampler_state(&self) -> SamplerState {
+        self.sampler.state()
+    }
+
+    /// Prompt followed by every token yielded so far.
+    pub fn tokens(&self) -> &[usize] {
+        &self.tokens
+    }
+}
+
+impl<R: Rng + ?Sized> Iterator for TokenStream<'_, R> {
+    type Item = StreamEvent;
+
+    fn next(&mut self) -> Option<StreamEvent> {
+        if self.done || self.produced >= self.config.max_tokens {
+            return None;
+        }
+        if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+            self.done = true;
+            return None;
+        }
+        let generated = match self.model.next_token(&self.tokens, &self.config, &mut self.sampler, self.rng) {
+            Some(token) => token,
+            None => {
+                self.done = true;
+                return None;
+            }
+        };
+        self.tokens.push(generated.token);
+        self.produced += 1;
+        self.done = self.stop_token == Some(generated.token);
+        let last = self.done || self.produced >= self.config.max_tokens;
+        let text = match self.decoder.as_mut() {
+            Some(decoder) => {
+                let mut text = decoder.push(generated.token);
+                if last {
+                    text.push_str(&decoder.finish());
+                }
+                text
+            }
+            None => String::new(),
+        };
+        Some(StreamEvent { generated, text })
+    }
+}
+
+impl Model {
+    /// Returns an iterator generating up to `config.max_tokens` tokens after
+    /// `input`, one per step.
+    pub fn stream<'a, R: Rng + ?Sized>(
+        &'a self,
+        input: &[usize],
+        config: &GenerationConfig,
+        rng: &'a mut R,
+    ) -> TokenStream<'a, R> {
+        TokenStream {
+            model: self,
+            config: config.clone(),
+            sampler: Sampler::new(config.sampler),
+            rng,
+            tokens: input.to_vec(),
+            produced: 0,
+            decoder: None,
+            cancel: None,
+            stop_token: None,
+            done: false,
+        }
+    }
+
+    /// Generates like [`Model::generate_with`], handing each token to
+    /// `callback` as it is produced. Generation stops early when the callback
+    /// returns `false`. Returns the prompt followed by the generated tokens.
+    pub fn generate_streaming<R, F>(
+        &self,
+        input: &[usize],
+        config: &GenerationConfig,
+        rng: &mut R,
+        mut callback: F,
+    ) -> Vec<usize>
+    where
+        R: Rng + ?Sized,
+        F: FnMut(&StreamEvent) -> bool,
+    {
+        let mut stream = self.stream(input, config, rng);
+        for event in stream.by_ref() {
+            if !callback(&event) {
+                break;
+            }
+        }
+        stream.tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn config(max_tokens: usize) -> GenerationConfig {
+        GenerationConfig {
+            max_tokens,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stream_matches_generate_with() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let streamed: Vec<usize> = model.stream(&[1, 2], &config(4), &mut rng).map(|e| e.generated.token).collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let full = model.generate_with(&[1, 2], &config(4), &mut rng);
+        assert_eq!(streamed, full.tokens[2..]);
+    }
+
+    #[test]
+    fn cancel_stops_stream() {
+        let model = Model::new(6, 6, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let cancel = CancelToken::new();
+        let mut stream = model.stream(&[1], &config(10), &mut rng).with_cancel(cancel.clone());
+        assert!(stream.next().is_some());
+        cancel.cancel();
+        assert!(stream.next().is_none());
+        assert_eq!(stream.tokens().len(), 2);
+    }
+
+    #[test]
+    fn callback_stops_and_decodes() {
+        let vocab: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+        let tok = BpeTokenizer::new(vocab, Vec::new(), 0);
+        let model = Model::new(4, 4, 4, 1, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut seen = Vec::new();
+        let out = model.generate_streaming(&[1], &config(5), &mut rng, |e| {
+            seen.push(e.generated.token);
+            seen.len() < 2
+        });
+        assert_eq!(out[1..], seen[..]);
+        assert_eq!(seen.len(), 2);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let text: String = model.stream(&[1], &config(3), &mut rng).with_tokenizer(&tok).map(|e| e.text).collect();
+        assert_eq!(text, tok.decode(&model.generate(&[1], 3)[1..]));
+    }
+}
@@ -0,0 +1,329 @@
+// Normalization and pre-tokenization applied before subword encoding.
+// Normalizers rewrite the text (Unicode normalization, lowercasing, accent
+// stripping) while remembering which input bytes every output char came
+// from, so token offsets still point into the original text. Pre-tokenizers
+// then cut the normalized text into the pieces BPE merges within. The
+// pipeline is stored with the tokenizer so training and inference agree.
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use unicode_normalization::char::{canonical_combining_class, is_combining_mark};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::tokenizer::split_gpt2;
+
+/// File holding a [`TextPipeline`] as JSON, stored next to `vocab.txt`.
+pub const PIPELINE_FILE: &str = "pipeline.json";
+
+/// Text rewrite applied before pre-tokenization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Normalizer {
+    /// Canonical composition.
+    Nfc,
+    /// Compatibility composition, e.g. `ﬁ` becomes `fi`.
+    Nfkc,
+    Lowercase,
+    /// Removes combining marks, e.g. `é` becomes `e`.
+    StripAccents,
+}
+
+/// Rule splitting text into the pieces encoded independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PreTokenizer {
+    /// Splits on whitespace and drops it.
+    Whitespace,
+    /// GPT-2 style split keeping a leading space on words, see
+    /// [`split_gpt2`].
+    Gpt2,
+    /// Separates runs of digits from other characters, or every digit when
+    /// `individual` is set.
+    Digits { individual: bool },
+    /// Puts every punctuation character in a piece of its own.
+    Punctuation,
+}
+
+/// Normalizers followed by pre-tokenizers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextPipeline {
+    #[serde(default)]
+    pub normalizers: Vec<Normalizer>,
+    #[serde(default)]
+    pub pre_tokenizers: Vec<PreTokenizer>,
+}
+
+/// Normalized text with the byte range of the input every char came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedText {
+    pub text: String,
+    // per char of `text`: its byte offset and the input range it came from
+    starts: Vec<usize>,
+    align: Vec<(usize, usize)>,
+}
+
+// combining marks and conjoining Hangul vowels and finals attach to the
+// preceding character, so normalization never looks across a new group
+fn starts_group(c: char) -> bool {
+    canonical_combining_class(c) == 0 && !matches!(c, '\u{1160}'..='\u{11FF}')
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(c, '¡' | '§' | '«' | '¶' | '·' | '»' | '¿' | '\u{3000}'..='\u{303F}' | '\u{FF01}'..='\u{FF0F}')
+        || (matches!(c, '\u{2010}'..='\u{205E}') && !c.is_whitespace())
+}
+
+// splits `s` between every pair of neighbouring chars where `boundary` holds
+fn split_between(s: &str, boundary: impl Fn(char, char) -> bool) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut prev = None;
+    for (i, c) in s.char_indices() {
+        if let Some(p) = prev {
+            if boundary(p, c) {
+                pieces.push(&s[start..i]);
+                start = i;
+            }
+        }
+        prev = Some(c);
+    }
+    if start < s.len() {
+        pieces.push(&s[start..]);
+    }
+    pieces
+}
+
+impl NormalizedText {
+    /// Wraps `text` unchanged.
+    pub fn new(text: &str) -> Self {
+        let (starts, align) = text.char_indices().map(|(i, c)| (i, (i, i + c.len_utf8()))).unzip();
+        Self {
+            text: text.to_string(),
+            starts,
+            align,
+        }
+    }
+
+    // rewrites every group of chars (a char where `split` holds plus the
+    // chars up to the next one) with `f`; output chars of a rewritten group
+    // map to the input range of the whole group
+    fn rewrite(&self, split: impl Fn(char) -> bool, f: impl Fn(&str, &mut String)) -> Self {
+        let mut out = Self {
+            text: String::with_capacity(self.text.len()),
+            starts: Vec::with_capacity(self.starts.len()),
+            align: Vec::with_capacity(self.align.len()),
+        };
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let mut j = i + 1;
+            while j < chars.len() && !split(chars[j]) {
+                j += 1;
+            }
+            let group = &self.text[self.starts[i]..self.starts.get(j).copied().unwrap_or(self.text.len())];
+            let before = out.text.len();
+            f(group, &mut out.text);
+            if out.text[before..] == *group {
+                out.align.extend_from_slice(&self.align[i..j]);
+            } else {
+                let range = (self.align[i].0, self.align[j - 1].1);
+                out.align.extend(out.text[before..].chars().map(|_| range));
+            }
+            out.starts.extend(out.text[before..].char_indices().map(|(k, _)| before + k));
+            i = j;
+        }
+        out
+    }
+
+    /// Applies `normalizer`.
+    pub fn normalize(&self, normalizer: Normalizer) -> Self {
+        match normalizer {
+            Normalizer::Nfc => self.rewrite(starts_group, |s, out| out.extend(s.nfc())),
+            Normalizer::Nfkc => self.rewrite(starts_group, |s, out| out.extend(s.nfkc())),
+            Normalizer::Lowercase => self.rewrite(|_| true, |s, out| out.extend(s.chars().flat_map(char::to_lowercase))),
+            Normalizer::StripAccents => self.rewrite(starts_group, |s, out| {
+                out.extend(s.nfd().filter(|&c| !is_combining_mark(c)).nfc())
+            }),
+        }
+    }
+
+    /// Input byte range of the char at normalized byte offset `offset`.
+    pub fn char_range(&self, offset: usize) -> (usize, usize) {
+        match self.starts.binary_search(&offset) {
+            Ok(i) => self.align[i],
+            Err(i) => self.align[i - 1],
+        }
+    }
+
+    /// Maps the normalized byte range `start..end` back to the input. Inside
+    /// chars that were copied unchanged the mapping is byte exact, otherwise
+    /// the whole input range of the char is used.
+    pub fn original(&self, start: usize, end: usize) -> (usize, usize) {
+        let index = |offset: usize| match self.starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        if start >= end {
+            let at = match self.starts.binary_search(&start) {
+                Ok(i) => self.align[i].0,
+                Err(_) => self.align.last().map_or(0, |r| r.1),
+            };
+            return (at, at);
+        }
+        let exact = |i: usize| {
+            let len = self.starts.get(i + 1).copied().unwrap_or(self.text.len()) - self.starts[i];
+            self.align[i].1 - self.align[i].0 == len
+        };
+        let (first, last) = (index(start), index(end - 1));
+        let a = if exact(first) {
+            self.align[first].0 + (start - self.starts[first])
+        } else {
+            self.align[first].0
+        };
+        let b = if exact(last) {
+            self.align[last].0 + (end - self.starts[last])
+        } else {
+            self.align[last].1
+        };
+        (a, b)
+    }
+}
+
+/// Applies `normalizers` in order, keeping the alignment to `text`.
+pub fn normalize_aligned(normalizers: &[Normalizer], text: &str) -> NormalizedText {
+    let mut normalized = NormalizedText::new(text);
+    for &normalizer in normalizers {
+        normalized = normalized.normalize(normalizer);
+    }
+    normalized
+}
+
+impl PreTokenizer {
+    /// Splits `text` into pieces.
+    pub fn split<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        match *self {
+            PreTokenizer::Whitespace => text.split_whitespace().collect(),
+            PreTokenizer::Gpt2 => split_gpt2(text),
+            PreTokenizer::Digits { individual } => {
+                split_between(text, |a, b| a.is_numeric() != b.is_numeric() || (individual && a.is_numeric()))
+            }
+            PreTokenizer::Punctuation => split_between(text, |a, b| is_punctuation(a) || is_punctuation(b)),
+        }
+    }
+}
+
+impl TextPipeline {
+    /// Whitespace splitting without normalization, the character mode
+    /// default.
+    pub fn whitespace() -> Self {
+        Self {
+            normalizers: Vec::new(),
+            pre_tokenizers: vec![PreTokenizer::Whitespace],
+        }
+    }
+
+    /// GPT-2 splitting without normalization, the byte-level default.
+    pub fn gpt2() -> Self {
+        Self {
+            normalizers: Vec::new(),
+            pre_tokenizers: vec![PreTokenizer::Gpt2],
+        }
+    }
+
+    /// Default pipeline of a character or byte-level tokenizer.
+    pub fn default_for(byte_level: bool) -> Self {
+        if byte_level {
+            Self::gpt2()
+        } else {
+            Self::whitespace()
+        }
+    }
+
+    /// Applies the normalizers with alignment to `text`.
+    pub fn normalize_aligned(&self, text: &str) -> NormalizedText {
+        normalize_aligned(&self.normalizers, text)
+    }
+
+    /// Applies the normalizers.
+    pub fn normalize<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if self.normalizers.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(self.normalize_aligned(text).text)
+    }
+
+    /// Splits normalized text into the pieces encoded independently. Empty
+    /// pieces are dropped.
+    pub fn pre_tokenize<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let mut pieces = vec![text];
+        for pre in &self.pre_tokenizers {
+            pieces = pieces.into_iter().flat_map(|p| pre.split(p)).collect();
+        }
+        pieces.retain(|p| !p.is_empty());
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizers_rewrite_text() {
+        let nfc = NormalizedText::new("e\u{301}").normalize(Normalizer::Nfc);
+        assert_eq!(nfc.text, "é");
+        assert_eq!(NormalizedText::new("ﬁ①").normalize(Normalizer::Nfkc).text, "fi1");
+        assert_eq!(NormalizedText::new("ÄB").normalize(Normalizer::Lowercase).text, "äb");
+        let stripped = NormalizedText::new("Café 한국").normalize(Normalizer::StripAccents);
+        assert_eq!(stripped.text, "Cafe 한국");
+    }
+
+    #[test]
+    fn alignment_points_into_input() {
+        let text = "xe\u{301}ﬁ y";
+        let normalized = normalize_aligned(&[Normalizer::Nfkc, Normalizer::Lowercase], text);
+        assert_eq!(normalized.text, "xéfi y");
+        // "é" came from "e" plus the combining accent
+        assert_eq!(normalized.original(1, 3), (1, 4));
+        // "f" and "i" both map to the ligature
+        assert_eq!(normalized.original(3, 4), (4, 7));
+        assert_eq!(normalized.original(3, 5), (4, 7));
+        assert_eq!(normalized.original(6, 7), (8, 9));
+        assert_eq!(normalized.original(7, 7), (9, 9));
+        // unchanged multi-byte chars map byte by byte
+        let plain = NormalizedText::new("aé");
+        assert_eq!(plain.original(1, 2), (1, 2));
+        assert_eq!(plain.char_range(2), (1, 3));
+    }
+
+    #[test]
+    fn pre_tokenizers_split() {
+        let pipeline = TextPipeline {
+            normalizers: Vec::new(),
+            pre_tokenizers: vec![
+                PreTokenizer::Whitespace,
+                PreTokenizer::Digits { individual: false },
+                PreTokenizer::Punctuation,
+            ],
+        };
+        assert_eq!(pipeline.pre_tokenize("in 2024, v1.5!"), vec!["in", "2024", ",", "v", "1", ".", "5", "!"]);
+        let digits = PreTokenizer::Digits { individual: true };
+        assert_eq!(digits.split("ab123"), vec!["ab", "1", "2", "3"]);
+        assert_eq!(PreTokenizer::Gpt2.split("hi there"), vec!["hi", " there"]);
+    }
+
+    #[test]
+    fn pipeline_serializes() {
+        let pipeline = TextPipeline {
+            normalizers: vec![Normalizer::Nfkc, Normalizer::Lowercase],
+            pre_tokenizers: vec![PreTokenizer::Gpt2, PreTokenizer::Digits { individual: true }],
+        };
+        let json = serde_json::to_string(&pipeline).unwrap();
+        assert_eq!(
+            json,
+            r#"{"normalizers":[{"type":"nfkc"},{"type":"lowercase"}],"pre_tokenizers":[{"type":"gpt2"},{"type":"digits","individual":true}]}"#
+        );
+        assert_eq!(serde_json::from_str::<TextPipeline>(&json).unwrap(), pipeline);
+        assert_eq!(serde_json::from_str::<TextPipeline>("{}").unwrap(), TextPipeline::default());
+    }
+}
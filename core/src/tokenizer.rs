@@ -1,4 +1,136 @@
+use serde::Serialize;
 use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::bpe_encoder::{MergeTable, WordCache, WordCacheStats, UNKNOWN};
+use crate::text_pipeline::{TextPipeline, PIPELINE_FILE};
+
+/// Number of words kept in a [`BpeTokenizer`]'s encoding cache by default.
+pub const DEFAULT_WORD_CACHE: usize = 10_000;
+
+/// Beginning of sequence.
+pub const BOS: &str = "<bos>";
+/// End of sequence.
+pub const EOS: &str = "<eos>";
+/// Padding.
+pub const PAD: &str = "<pad>";
+/// Separator.
+pub const SEP: &str = "<sep>";
+/// Opens a retrieved chunk in a RAG prompt.
+pub const CHUNK_START: &str = "<chunk>";
+/// Closes a retrieved chunk.
+pub const CHUNK_END: &str = "</chunk>";
+/// Marks the user query.
+pub const QUERY: &str = "<query>";
+/// Marks the start of the answer.
+pub const ANSWER: &str = "<answer>";
+
+/// Special tokens registered by [`BpeTokenizer::add_default_special_tokens`].
+pub const DEFAULT_SPECIAL_TOKENS: [&str; 8] = [BOS, EOS, PAD, SEP, CHUNK_START, CHUNK_END, QUERY, ANSWER];
+
+/// File holding the special tokens, stored next to `vocab.txt`.
+pub const SPECIAL_TOKENS_FILE: &str = "special_tokens.txt";
+
+/// A token together with the part of the input it was encoded from.
+/// Spans are half-open ranges in bytes and in chars of the original text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TokenSpan {
+    pub id: usize,
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+}
+
+// byte offset of `part`, a subslice of `text`, within `text`
+pub(crate) fn subslice_offset(text: &str, part: &str) -> usize {
+    part.as_ptr() as usize - text.as_ptr() as usize
+}
+
+/// Adds char offsets to `(id, byte_start, byte_end)` triples over `text`.
+/// A byte offset inside a multi-byte character counts as that character.
+pub(crate) fn token_spans(text: &str, spans: Vec<(usize, usize, usize)>) -> Vec<TokenSpan> {
+    let starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    // index of the char holding byte `offset`, or the char count at the end
+    let char_at = |offset: usize| match starts.binary_search(&offset) {
+        Ok(i) => i,
+        Err(i) if offset >= text.len() => i,
+        Err(i) => i - 1,
+    };
+    spans
+        .into_iter()
+        .map(|(id, byte_start, byte_end)| TokenSpan {
+            id,
+            byte_start,
+            byte_end,
+            char_start: char_at(byte_start),
+            char_end: starts.partition_point(|&s| s < byte_end),
+        })
+        .collect()
+}
+
+/// Interface shared by the tokenizers so callers can swap implementations.
+pub trait Tokenizer {
+    /// Encodes `text` into token ids.
+    fn encode(&self, text: &str) -> Vec<usize>;
+    /// Encodes `text`, matching special tokens only when `allow_special` is
+    /// set. Tokenizers without special tokens ignore the flag.
+    fn encode_with_special(&self, text: &str, allow_special: bool) -> Vec<usize> {
+        let _ = allow_special;
+        self.encode(text)
+    }
+    /// Decodes token ids back into text.
+    fn decode(&self, tokens: &[usize]) -> String;
+    /// Number of tokens in the vocabulary.
+    fn vocab_size(&self) -> usize;
+    /// Returns the id of `token`.
+    fn token_to_id(&self, token: &str) -> Option<usize>;
+    /// Returns the token string of `id`.
+    fn id_to_token(&self, id: usize) -> Option<&str>;
+    /// Special tokens ordered by id.
+    fn special_tokens(&self) -> Vec<(&str, usize)> {
+        Vec::new()
+    }
+    /// Encodes `text` and returns every token with the span of `text` it
+    /// was encoded from, after normalization and pre-tokenization.
+    fn encode_with_offsets(&self, text: &str, allow_special: bool) -> Vec<TokenSpan>;
+}
+
+impl<T: Tokenizer + ?Sized> Tokenizer for Box<T> {
+    fn encode(&self, text: &str) -> Vec<usize> {
+        (**self).encode(text)
+    }
+
+    fn encode_with_special(&self, text: &str, allow_special: bool) -> Vec<usize> {
+        (**self).encode_with_special(text, allow_special)
+    }
+
+    fn decode(&self, tokens: &[usize]) -> String {
+        (**self).decode(tokens)
+    }
+
+    fn vocab_size(&self) -> usize {
+        (**self).vocab_size()
+    }
+
+    fn token_to_id(&self, token: &str) -> Option<usize> {
+        (**self).token_to_id(token)
+    }
+
+    fn id_to_token(&self, id: usize) -> Option<&str> {
+        (**self).id_to_token(id)
+    }
+
+    fn special_tokens(&self) -> Vec<(&str, usize)> {
+        (**self).special_tokens()
+    }
+
+    fn encode_with_offsets(&self, text: &str, allow_special: bool) -> Vec<TokenSpan> {
+        (**self).encode_with_offsets(text, allow_special)
+    }
+}
 
 /// Tokenizer that simply splits text on ASCII whitespace.
 ///
@@ -41,16 +173,166 @@ impl WhitespaceTokenizer {
     }
 }
 
+impl Tokenizer for WhitespaceTokenizer {
+    fn encode(&self, text: &str) -> Vec<usize> {
+        WhitespaceTokenizer::encode(self, text)
+    }
+
+    fn decode(&self, tokens: &[usize]) -> String {
+        WhitespaceTokenizer::decode(self, tokens)
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.inv_vocab.len()
+    }
+
+    fn token_to_id(&self, token: &str) -> Option<usize> {
+        self.vocab.get(token).copied()
+    }
+
+    fn id_to_token(&self, id: usize) -> Option<&str> {
+        self.inv_vocab.get(id).map(|t| t.as_str())
+    }
+
+    fn encode_with_offsets(&self, text: &str, _allow_special: bool) -> Vec<TokenSpan> {
+        let spans = text
+            .split_whitespace()
+            .map(|t| {
+                let start = subslice_offset(text, t);
+                (self.vocab.get(t).cloned().unwrap_or(self.unk_id), start, start + t.len())
+            })
+            .collect();
+        token_spans(text, spans)
+    }
+}
+
+// GPT-2 byte to unicode table: printable bytes map to themselves and the
+// rest to code points from U+0100 up, so every byte has a visible symbol and
+// token strings never contain whitespace.
+fn byte_table() -> &'static ([char; 256], HashMap<char, u8>) {
+    static TABLE: OnceLock<([char; 256], HashMap<char, u8>)> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut chars = ['\0'; 256];
+        let mut next = 256u32;
+        for b in 0..=255u8 {
+            let printable = matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+            chars[b as usize] = if printable {
+                b as char
+            } else {
+                next += 1;
+                char::from_u32(next - 1).unwrap_or_default()
+            };
+        }
+        let inverse = chars.iter().enumerate().map(|(b, &c)| (c, b as u8)).collect();
+        (chars, inverse)
+    })
+}
+
+/// Returns the 256 symbols of the byte-level alphabet in byte order. The
+/// space byte is `Ġ`.
+pub fn byte_level_alphabet() -> Vec<String> {
+    byte_table().0.iter().map(|c| c.to_string()).collect()
+}
+
+/// Returns `true` when `vocab` holds every symbol of the byte-level
+/// alphabet, as the vocabularies of byte-level tokenizers do. Character
+/// vocabularies practically never contain all of them.
+pub fn is_byte_level_vocab(vocab: &[String]) -> bool {
+    let tokens: std::collections::HashSet<&str> = vocab.iter().map(|t| t.as_str()).collect();
+    byte_level_alphabet().iter().all(|c| tokens.contains(c.as_str()))
+}
+
+/// Maps `bytes` to their byte-level symbols.
+pub fn bytes_to_symbols(bytes: &[u8]) -> String {
+    let table = &byte_table().0;
+    bytes.iter().map(|&b| table[b as usize]).collect()
+}
+
+/// Splits `text` into GPT-2 style pieces: contractions, letter, digit and
+/// punctuation runs each taking at most one leading space, and whitespace
+/// runs. A single space right before a word stays attached to that word.
+/// Concatenating the pieces gives back `text`.
+pub fn split_gpt2(text: &str) -> Vec<&str> {
+    #[derive(PartialEq, Clone, Copy)]
+    enum Class {
+        Letter,
+        Number,
+        Space,
+        Other,
+    }
+    fn class(c: char) -> Class {
+        if c.is_alphabetic() {
+            Class::Letter
+        } else if c.is_numeric() {
+            Class::Number
+        } else if c.is_whitespace() {
+            Class::Space
+        } else {
+            Class::Other
+        }
+    }
+    const CONTRACTIONS: [&str; 7] = ["'s", "'t", "'re", "'ve", "'m", "'ll", "'d"];
+
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let offset = |i: usize| chars.get(i).map(|c| c.0).unwrap_or(text.len());
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let rest = &text[chars[i].0..];
+        if let Some(c) = CONTRACTIONS.iter().find(|c| rest.starts_with(**c)) {
+            i += c.chars().count();
+        } else {
+            let (c, next) = (chars[i].1, chars.get(i + 1).map(|c| c.1));
+            let kind = match next {
+                Some(n) if c == ' ' && class(n) != Class::Space => {
+                    i += 1;
+                    class(n)
+                }
+                _ => class(c),
+            };
+            if kind == Class::Space {
+                while i < chars.len() && class(chars[i].1) == Class::Space {
+                    i += 1;
+                }
+                // leave the last space for the following word
+                if i < chars.len() && i - start > 1 {
+                    i -= 1;
+                }
+            } else {
+                while i < chars.len() && class(chars[i].1) == kind {
+                    i += 1;
+                }
+            }
+        }
+        pieces.push(&text[offset(start)..offset(i)]);
+    }
+    pieces
+}
+
 /// Simple byte pair encoding (BPE) tokenizer.
 ///
 /// The tokenizer loads a vocabulary and merge operations and applies
 /// merges greedily based on their rank. It is intentionally minimal and
 /// meant for demonstration only.
+///
+/// In byte-level mode (see [`BpeTokenizer::new_byte_level`]) text is split
+/// with [`split_gpt2`] and merged over the 256 byte symbols, so whitespace is
+/// kept and `decode(encode(s)) == s` for any string.
+///
+/// Text between special tokens goes through a [`TextPipeline`] before BPE:
+/// whitespace splitting by default, GPT-2 splitting in byte-level mode.
 pub struct BpeTokenizer {
     vocab: HashMap<String, usize>,
     inv_vocab: Vec<String>,
     merges: HashMap<(String, String), usize>,
     unk_id: usize,
+    byte_level: bool,
+    pipeline: TextPipeline,
+    // special tokens, longest first so matching prefers the longest one
+    special: Vec<(String, usize)>,
+    table: MergeTable,
+    cache: Mutex<WordCache>,
 }
 
 impl BpeTokenizer {
@@ -70,12 +352,110 @@ impl BpeTokenizer {
         for (rank, (a, b)) in merges.iter().enumerate() {
             merge_map.insert((a.clone(), b.clone()), rank);
         }
+        let mut table = MergeTable::default();
+        for (i, tok) in vocab.iter().enumerate() {
+            table.set_token(tok, i);
+        }
+        // duplicate tokens resolve to the last id, as in `vocab`
+        for (tok, &id) in &map {
+            table.set_token(tok, id);
+        }
+        let mut ranked: Vec<(&(String, String), &usize)> = merge_map.iter().collect();
+        ranked.sort_by_key(|m| *m.1);
+        for ((a, b), &rank) in ranked {
+            table.add_merge(a, b, rank);
+        }
         Self {
             vocab: map,
             inv_vocab: vocab,
             merges: merge_map,
             unk_id,
+            byte_level: false,
+            pipeline: TextPipeline::whitespace(),
+            special: Vec::new(),
+            table,
+            cache: Mutex::new(WordCache::new(DEFAULT_WORD_CACHE)),
+        }
+    }
+
+    /// Sets how many encoded words are cached. Zero disables the cache.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache = Mutex::new(WordCache::new(capacity));
+    }
+
+    /// Hit and miss counts of the word cache.
+    pub fn cache_stats(&self) -> WordCacheStats {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).stats()
+    }
+
+    // appends `token` to the vocabulary, or returns its id if present
+    fn push_token(&mut self, token: &str) -> usize {
+        if let Some(&id) = self.vocab.get(token) {
+            return id;
+        }
+        let id = self.inv_vocab.len();
+        self.vocab.insert(token.to_string(), id);
+        self.inv_vocab.push(token.to_string());
+        self.table.set_token(token, id);
+        self.clear_cache();
+        id
+    }
+
+    fn clear_cache(&mut self) {
+        self.cache.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Creates a byte-level tokenizer. Tokens are strings over the
+    /// [`byte_level_alphabet`]; alphabet symbols and merge results missing
+    /// from `vocab` are appended so every input can be encoded without
+    /// `<unk>`.
+    pub fn new_byte_level(vocab: Vec<String>, merges: Vec<(String, String)>) -> Self {
+        let mut vocab = vocab;
+        let mut known: std::collections::HashSet<String> = vocab.iter().cloned().collect();
+        let merged = merges.iter().map(|(a, b)| format!("{}{}", a, b));
+        for token in byte_level_alphabet().into_iter().chain(merged) {
+            if known.insert(token.clone()) {
+                vocab.push(token);
+            }
+        }
+        let mut tok = Self::new(vocab, merges, 0);
+        tok.byte_level = true;
+        tok.pipeline = TextPipeline::gpt2();
+        tok
+    }
+
+    /// Returns `true` for tokenizers created with
+    /// [`BpeTokenizer::new_byte_level`].
+    pub fn is_byte_level(&self) -> bool {
+        self.byte_level
+    }
+
+    /// Normalization and pre-tokenization applied before BPE.
+    pub fn pipeline(&self) -> &TextPipeline {
+        &self.pipeline
+    }
+
+    /// Replaces the text pipeline. It should match the one the merges were
+    /// trained with.
+    pub fn set_pipeline(&mut self, pipeline: TextPipeline) {
+        self.pipeline = pipeline;
+        self.clear_cache();
+    }
+
+    /// Loads [`PIPELINE_FILE`] from the directory of `vocab_path` if it
+    /// exists.
+    pub fn load_pipeline_near<P: AsRef<Path>>(&mut self, vocab_path: P) -> io::Result<()> {
+        let path = vocab_path
+            .as_ref()
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(PIPELINE_FILE);
+        if path.is_file() {
+            let json = std::fs::read_to_string(path)?;
+            let pipeline = serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.set_pipeline(pipeline);
         }
+        Ok(())
     }
 
     /// Returns the current vocabulary size.
@@ -83,98 +463,443 @@ impl BpeTokenizer {
         self.inv_vocab.len()
     }
 
+    /// Id returned for text that cannot be encoded.
+    pub fn unk_id(&self) -> usize {
+        self.unk_id
+    }
+
+    /// Returns the token string of `id`.
+    pub fn id_to_token(&self, id: usize) -> Option<&str> {
+        self.inv_vocab.get(id).map(|t| t.as_str())
+    }
+
+    /// Returns the id of `token`.
+    pub fn token_to_id(&self, token: &str) -> Option<usize> {
+        self.vocab.get(token).copied()
+    }
+
+    /// Merge pairs ordered by rank.
+    pub fn merges(&self) -> Vec<(&str, &str)> {
+        let mut ranked: Vec<(&(String, String), &usize)> = self.merges.iter().collect();
+        ranked.sort_by_key(|m| *m.1);
+        ranked.into_iter().map(|((a, b), _)| (a.as_str(), b.as_str())).collect()
+    }
+
     /// Adds a new merge pair to the tokenizer and returns the id of the newly
     /// created token. If the merged token already exists, its id is returned
     /// and the merge order is updated accordingly.
     pub fn add_merge(&mut self, a: &str, b: &str) -> usize {
-        let merged = format!("{}{}", a, b);
-        let id = if let Some(&existing) = self.vocab.get(&merged) {
-            existing
-        } else {
-            let new_id = self.inv_vocab.len();
-            self.vocab.insert(merged.clone(), new_id);
-            self.inv_vocab.push(merged.clone());
-            new_id
-        };
+        let id = self.push_token(&format!("{}{}", a, b));
         let rank = self.merges.len();
-        self.merges
+        let rank = *self
+            .merges
             .entry((a.to_string(), b.to_string()))
             .or_insert(rank);
+        self.table.add_merge(a, b, rank);
+        self.clear_cache();
         id
     }
 
     /// Learns up to `num_merges` new merges from `text` based on token pair
-    /// frequencies. Merges are applied greedily one at a time.
+    /// frequencies, starting from the current segmentation of its words.
+    /// See [`crate::bpe_trainer`] for training a vocabulary from scratch.
     pub fn learn_merges(&mut self, text: &str, num_merges: usize) {
-        for _ in 0..num_merges {
-            let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
-            for word in text.split_whitespace() {
-                let tokens = self.encode(word);
-                for pair in tokens.windows(2) {
-                    *counts.entry((pair[0], pair[1])).or_insert(0) += 1;
-                }
-            }
-            let ((a, b), _) = match counts.into_iter().max_by_key(|p| p.1) {
-                Some(p) => p,
-                None => break,
+        let mut counts: HashMap<Vec<String>, u64> = HashMap::new();
+        let normalized = self.pipeline.normalize(text);
+        for piece in self.pipeline.pre_tokenize(&normalized) {
+            let word = if self.byte_level {
+                self.merge_word(&bytes_to_symbols(piece.as_bytes()))
+            } else {
+                self.merge_word(piece)
             };
-            let a_tok = self.inv_vocab[a].clone();
-            let b_tok = self.inv_vocab[b].clone();
-            self.add_merge(&a_tok, &b_tok);
+            *counts.entry(word).or_insert(0) += 1;
+        }
+        let mut words: Vec<(Vec<String>, u64)> = counts.into_iter().collect();
+        words.sort();
+        for (a, b) in crate::bpe_trainer::learn_merges(words, num_merges, 1) {
+            self.add_merge(&a, &b);
         }
     }
 
+    // applies the merges to the characters of `word`
+    fn merge_word(&self, word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.chars().collect();
+        self.table
+            .merge(word)
+            .into_iter()
+            .map(|(sym, start, end)| match sym {
+                UNKNOWN => chars[start..end].iter().collect(),
+                _ => self.table.name(sym).to_string(),
+            })
+            .collect()
+    }
+
     fn encode_word(&self, word: &str) -> Vec<usize> {
-        let mut pieces: Vec<String> = word.chars().map(|c| c.to_string()).collect();
-        if pieces.is_empty() {
-            return Vec::new();
-        }
-        loop {
-            let mut best: Option<(usize, usize)> = None; // (rank, index)
-            for i in 0..pieces.len() - 1 {
-                let pair = (pieces[i].clone(), pieces[i + 1].clone());
-                if let Some(&rank) = self.merges.get(&pair) {
-                    if best.map(|b| rank < b.0).unwrap_or(true) {
-                        best = Some((rank, i));
+        // the lock is released while merging so threads sharing the
+        // tokenizer only serialize on cache lookups
+        if let Some(ids) = self.cache.lock().unwrap_or_else(|e| e.into_inner()).get(word) {
+            return ids;
+        }
+        let ids: Vec<usize> = self.word_tokens(word).into_iter().map(|(id, _, _)| id).collect();
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).insert(word, ids.clone());
+        ids
+    }
+
+    // tokens of `word` with the char range each covers
+    fn word_tokens(&self, word: &str) -> Vec<(usize, usize, usize)> {
+        let mut tokens = Vec::new();
+        for (sym, start, end) in self.table.merge(word) {
+            match self.table.token_id(sym) {
+                // special ids are reserved, so a piece spelling a special
+                // token falls back to its characters
+                Some(id) if self.is_special(id) => {
+                    for (i, c) in word.chars().skip(start).take(end - start).enumerate() {
+                        let id = self.table.token_id(self.table.char_symbol(c));
+                        let id = id.filter(|&id| !self.is_special(id)).unwrap_or(self.unk_id);
+                        tokens.push((id, start + i, start + i + 1));
                     }
                 }
+                Some(id) => tokens.push((id, start, end)),
+                None => tokens.push((self.unk_id, start, end)),
             }
-            match best {
-                Some((_rank, idx)) => {
-                    let merged = format!("{}{}", pieces[idx], pieces[idx + 1]);
-                    pieces[idx] = merged;
-                    pieces.remove(idx + 1);
-                    if pieces.len() == 1 {
-                        break;
-                    }
+        }
+        tokens
+    }
+
+    /// Registers `token` as a special token and returns its id. A token
+    /// already in the vocabulary keeps its id, otherwise it is appended.
+    pub fn add_special_token(&mut self, token: &str) -> usize {
+        if let Some(id) = self.special_token_id(token) {
+            return id;
+        }
+        let id = self.push_token(token);
+        self.special.push((token.to_string(), id));
+        self.special.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.1.cmp(&b.1)));
+        self.clear_cache();
+        id
+    }
+
+    /// Registers [`DEFAULT_SPECIAL_TOKENS`].
+    pub fn add_default_special_tokens(&mut self) {
+        for token in DEFAULT_SPECIAL_TOKENS {
+            self.add_special_token(token);
+        }
+    }
+
+    /// Returns the id of special token `token`.
+    pub fn special_token_id(&self, token: &str) -> Option<usize> {
+        self.special.iter().find(|(t, _)| t == token).map(|&(_, id)| id)
+    }
+
+    /// Returns `true` if `id` belongs to a special token.
+    pub fn is_special(&self, id: usize) -> bool {
+        self.special.iter().any(|&(_, s)| s == id)
+    }
+
+    /// Registered special tokens ordered by id.
+    pub fn special_tokens(&self) -> Vec<(&str, usize)> {
+        let mut tokens: Vec<(&str, usize)> = self.special.iter().map(|(t, id)| (t.as_str(), *id)).collect();
+        tokens.sort_by_key(|t| t.1);
+        tokens
+    }
+
+    /// Writes the special tokens to `path`, one `token<TAB>id` line per
+    /// token in id order.
+    pub fn save_special_tokens<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = String::new();
+        for (token, id) in self.special_tokens() {
+            out.push_str(&format!("{}\t{}\n", token, id));
+        }
+        std::fs::write(path, out)
+    }
+
+    /// Registers the special tokens listed in `path`. A line carrying an id
+    /// must resolve to that id, otherwise the file belongs to a different
+    /// vocabulary and an `InvalidData` error is returned. Lines holding only
+    /// the token, as written by older versions, are registered as is.
+    pub fn load_special_tokens<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        for line in std::fs::read_to_string(path)?.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            let (token, expected) = match line.rsplit_once('\t') {
+                Some((token, id)) => {
+                    let id = id.parse::<usize>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    (token, Some(id))
                 }
-                None => break,
+                None => (line, None),
+            };
+            let id = self.add_special_token(token);
+            if let Some(expected) = expected.filter(|&expected| expected != id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("special token {:?} has id {} instead of {}", token, id, expected),
+                ));
             }
         }
-        pieces
-            .into_iter()
-            .map(|p| self.vocab.get(&p).cloned().unwrap_or(self.unk_id))
-            .collect()
+        Ok(())
     }
 
-    /// Encodes text into token ids using greedy BPE merges. Words are split on
-    /// ASCII whitespace before BPE is applied.
-    pub fn encode(&self, text: &str) -> Vec<usize> {
+    /// Loads [`SPECIAL_TOKENS_FILE`] from the directory of `vocab_path` if it
+    /// exists.
+    pub fn load_special_tokens_near<P: AsRef<Path>>(&mut self, vocab_path: P) -> io::Result<()> {
+        let path = vocab_path
+            .as_ref()
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(SPECIAL_TOKENS_FILE);
+        if path.is_file() {
+            self.load_special_tokens(path)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the legacy layout: `vocab.txt` with one token per line,
+    /// `merges_path` with one space separated pair per line, and the
+    /// [`SPECIAL_TOKENS_FILE`] and [`PIPELINE_FILE`] next to the vocabulary
+    /// if present. The files record neither `unk_id` nor the mode, so
+    /// `unk_id` is passed in and byte-level mode is used when `byte_level`
+    /// is set or [`is_byte_level_vocab`] holds for the vocabulary. Tokens
+    /// containing spaces cannot be represented; see
+    /// [`crate::tokenizer_file`] for the single-file format.
+    pub fn load_legacy<P: AsRef<Path>, Q: AsRef<Path>>(
+        vocab_path: P,
+        merges_path: Q,
+        unk_id: usize,
+        byte_level: bool,
+    ) -> io::Result<Self> {
+        let vocab: Vec<String> = std::fs::read_to_string(&vocab_path)?.lines().map(|s| s.to_string()).collect();
+        let merges = std::fs::read_to_string(merges_path)?
+            .lines()
+            .filter_map(|l| {
+                let mut parts = l.split_whitespace();
+                let a = parts.next()?.to_string();
+                let b = parts.next()?.to_string();
+                Some((a, b))
+            })
+            .collect();
+        let mut tok = if byte_level || is_byte_level_vocab(&vocab) {
+            BpeTokenizer::new_byte_level(vocab, merges)
+        } else {
+            BpeTokenizer::new(vocab, merges, unk_id)
+        };
+        tok.load_special_tokens_near(&vocab_path)?;
+        tok.load_pipeline_near(&vocab_path)?;
+        Ok(tok)
+    }
+
+    fn encode_plain(&self, text: &str, ids: &mut Vec<usize>) {
+        let normalized = self.pipeline.normalize(text);
+        for piece in self.pipeline.pre_tokenize(&normalized) {
+            if self.byte_level {
+                ids.extend(self.encode_word(&bytes_to_symbols(piece.as_bytes())));
+            } else {
+                ids.extend(self.encode_word(piece));
+            }
+        }
+    }
+
+    // like `encode_plain` but records the byte range of `text` each token
+    // covers, shifted by `offset`
+    fn encode_plain_spans(&self, text: &str, offset: usize, spans: &mut Vec<(usize, usize, usize)>) {
+        let normalized = self.pipeline.normalize_aligned(text);
+        for piece in self.pipeline.pre_tokenize(&normalized.text) {
+            let start = subslice_offset(&normalized.text, piece);
+            // byte-level words have one symbol per byte
+            let (bounds, tokens): (Vec<usize>, _) = if self.byte_level {
+                ((0..=piece.len()).collect(), self.word_tokens(&bytes_to_symbols(piece.as_bytes())))
+            } else {
+                let bounds = piece.char_indices().map(|(i, _)| i).chain([piece.len()]).collect();
+                (bounds, self.word_tokens(piece))
+            };
+            for (id, a, b) in tokens {
+                let (a, b) = normalized.original(start + bounds[a], start + bounds[b]);
+                spans.push((id, offset + a, offset + b));
+            }
+        }
+    }
+
+    // splits `text` into plain segments and special token matches, each
+    // with its byte offset
+    fn segments<'t>(&self, text: &'t str, allow_special: bool) -> Vec<(usize, &'t str, Option<usize>)> {
+        if !allow_special || self.special.is_empty() {
+            return vec![(0, text, None)];
+        }
+        let mut segments = Vec::new();
+        let mut plain_start = 0;
+        let mut pos = 0;
+        while pos < text.len() {
+            let rest = &text[pos..];
+            match self.special.iter().find(|(t, _)| rest.starts_with(t.as_str())) {
+                Some((token, id)) => {
+                    segments.push((plain_start, &text[plain_start..pos], None));
+                    segments.push((pos, &text[pos..pos + token.len()], Some(*id)));
+                    pos += token.len();
+                    plain_start = pos;
+                }
+                None => pos += rest.chars().next().map(char::len_utf8).unwrap_or(1),
+            }
+        }
+        segments.push((plain_start, &text[plain_start..], None));
+        segments
+    }
+
+    /// Encodes `text`, matching special tokens atomically before BPE when
+    /// `allow_special` is set. Pass `false` for untrusted text so it cannot
+    /// inject control tokens; special token strings are then encoded as
+    /// ordinary text.
+    pub fn encode_with_special(&self, text: &str, allow_special: bool) -> Vec<usize> {
         let mut ids = Vec::new();
-        for word in text.split_whitespace() {
-            ids.extend(self.encode_word(word));
+        for (_, segment, special) in self.segments(text, allow_special) {
+            match special {
+                Some(id) => ids.push(id),
+                None => self.encode_plain(segment, &mut ids),
+            }
         }
         ids
     }
 
-    /// Decodes token ids back into text by simply concatenating token strings.
+    /// Encodes `text` like [`BpeTokenizer::encode_with_special`] and returns
+    /// every token with the span of `text` it was encoded from. Byte-level
+    /// tokens holding part of a multi-byte character get the span of the
+    /// whole character in chars.
+    pub fn encode_with_offsets(&self, text: &str, allow_special: bool) -> Vec<TokenSpan> {
+        let mut spans = Vec::new();
+        for (offset, segment, special) in self.segments(text, allow_special) {
+            match special {
+                Some(id) => spans.push((id, offset, offset + segment.len())),
+                None => self.encode_plain_spans(segment, offset, &mut spans),
+            }
+        }
+        token_spans(text, spans)
+    }
+
+    /// Encodes text into token ids using greedy BPE merges. Special tokens
+    /// are matched first; the text between them goes through the
+    /// [`TextPipeline`], by default splitting on whitespace or with
+    /// [`split_gpt2`] in byte-level mode, before BPE is applied.
+    pub fn encode(&self, text: &str) -> Vec<usize> {
+        self.encode_with_special(text, true)
+    }
+
+    /// Returns the raw bytes of token `id`. In byte-level mode every symbol
+    /// maps back to its byte. Otherwise byte fallback tokens of the form
+    /// `<0xNN>` stand for a single byte and a leading word-boundary marker
+    /// (`Ġ` or `▁`) stands for a space. Unknown ids yield no bytes.
+    pub fn token_bytes(&self, id: usize) -> Vec<u8> {
+        let token = match self.inv_vocab.get(id) {
+            Some(t) => t.as_str(),
+            None => return Vec::new(),
+        };
+        if self.is_special(id) {
+            return token.as_bytes().to_vec();
+        }
+        if self.byte_level {
+            let inverse = &byte_table().1;
+            return token.chars().filter_map(|c| inverse.get(&c).copied()).collect();
+        }
+        if let Some(hex) = token.strip_prefix("<0x").and_then(|t| t.strip_suffix('>')) {
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                if hex.len() == 2 {
+                    return vec![byte];
+                }
+            }
+        }
+        match token.strip_prefix(['Ġ', '▁']) {
+            Some(rest) => {
+                let mut bytes = vec![b' '];
+                bytes.extend_from_slice(rest.as_bytes());
+                bytes
+            }
+            None => token.as_bytes().to_vec(),
+        }
+    }
+
+    /// Decodes token ids back into text by concatenating the token bytes.
+    /// Outside byte-level mode the space implied by a word-boundary marker on
+    /// the first token is dropped. Invalid UTF-8 is replaced with `U+FFFD`.
     pub fn decode(&self, tokens: &[usize]) -> String {
-        tokens
-            .iter()
-            .map(|&id| self.inv_vocab.get(id).cloned().unwrap_or_default())
-            .collect::<Vec<String>>()
-            .join("")
+        let mut bytes = Vec::new();
+        for &id in tokens {
+            bytes.extend(self.token_bytes(id));
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        if self.byte_level {
+            return text.into_owned();
+        }
+        text.strip_prefix(' ').unwrap_or(&text).to_string()
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn encode(&self, text: &str) -> Vec<usize> {
+        BpeTokenizer::encode(self, text)
+    }
+
+    fn encode_with_special(&self, text: &str, allow_special: bool) -> Vec<usize> {
+        BpeTokenizer::encode_with_special(self, text, allow_special)
+    }
+
+    fn decode(&self, tokens: &[usize]) -> String {
+        BpeTokenizer::decode(self, tokens)
+    }
+
+    fn vocab_size(&self) -> usize {
+        BpeTokenizer::vocab_size(self)
+    }
+
+    fn token_to_id(&self, token: &str) -> Option<usize> {
+        BpeTokenizer::token_to_id(self, token)
+    }
+
+    fn id_to_token(&self, id: usize) -> Option<&str> {
+        BpeTokenizer::id_to_token(self, id)
+    }
+
+    fn special_tokens(&self) -> Vec<(&str, usize)> {
+        BpeTokenizer::special_tokens(self)
+    }
+
+    fn encode_with_offsets(&self, text: &str, allow_special: bool) -> Vec<TokenSpan> {
+        BpeTokenizer::encode_with_offsets(self, text, allow_special)
+    }
+}
+
+/// Returns `true` when `path` names a tokenizer stored in a single file
+/// (`.json` or `.model`), which needs no merges file.
+pub fn is_single_file(path: &str) -> bool {
+    path.ends_with(".json") || path.ends_with(".model")
+}
+
+/// Takes the tokenizer arguments of a command line tool from `args`: a
+/// single tokenizer file, or a `vocab.txt` followed by its `merges.txt`.
+/// Returns the paths for [`load_tokenizer`], the merges path being empty
+/// for a single file, or `None` when the arguments run out.
+pub fn tokenizer_args(args: &mut impl Iterator<Item = String>) -> Option<(String, String)> {
+    let vocab_path = args.next()?;
+    let merges_path = if is_single_file(&vocab_path) { String::new() } else { args.next()? };
+    Some((vocab_path, merges_path))
+}
+
+/// Loads a tokenizer for the command line tools and FFI. A path ending in
+/// `.model` is read as a SentencePiece unigram model. One ending in `.json`
+/// is a [`crate::tokenizer_file::TokenizerFile`], or a HuggingFace
+/// `tokenizer.json` when it lacks the format marker; `merges_path` and
+/// `unk_id` are then unused. Anything else is a legacy `vocab.txt` read with
+/// [`BpeTokenizer::load_legacy`], in byte-level mode when the vocabulary
+/// holds the whole byte-level alphabet.
+pub fn load_tokenizer(vocab_path: &str, merges_path: &str, unk_id: usize) -> io::Result<Box<dyn Tokenizer + Send + Sync>> {
+    if vocab_path.ends_with(".model") {
+        return Ok(Box::new(crate::unigram::UnigramTokenizer::load_model(vocab_path)?));
     }
+    if vocab_path.ends_with(".json") {
+        let json = std::fs::read_to_string(vocab_path)?;
+        if crate::tokenizer_file::is_tokenizer_file(&json) {
+            return crate::tokenizer_file::TokenizerFile::from_json(&json)?.into_tokenizer();
+        }
+        return Ok(Box::new(BpeTokenizer::from_hf_json(&json)?));
+    }
+    Ok(Box::new(BpeTokenizer::load_legacy(vocab_path, merges_path, unk_id, false)?))
 }
 
 #[cfg(test)]
@@ -287,6 +1012,85 @@ mod tests {
         assert_eq!(decoded, "ab");
     }
 
+    #[test]
+    fn gpt2_split_keeps_spaces_with_words() {
+        assert_eq!(split_gpt2("Hello world's  42!\n"), vec!["Hello", " world", "'s", " ", " 42", "!", "\n"]);
+        assert_eq!(split_gpt2("a \tb"), vec!["a", " ", "\t", "b"]);
+    }
+
+    #[test]
+    fn byte_level_roundtrip() {
+        let merges = vec![("h".into(), "e".into()), ("Ġ".into(), "w".into())];
+        let tok = BpeTokenizer::new_byte_level(Vec::new(), merges);
+        assert_eq!(tok.vocab_size(), 258);
+        let text = "he said  hello\n\twörld 🙂";
+        assert_eq!(tok.decode(&tok.encode(text)), text);
+        assert_eq!(byte_level_alphabet()[b' ' as usize], "Ġ");
+    }
+
+    #[test]
+    fn concurrent_encodes_share_cache() {
+        let merges = vec![("h".into(), "e".into()), ("Ġ".into(), "w".into())];
+        let tok = BpeTokenizer::new_byte_level(Vec::new(), merges);
+        let text = "he went where he went";
+        let expected = tok.encode(text);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        assert_eq!(tok.encode(text), expected);
+                    }
+                });
+            }
+        });
+        assert!(tok.cache_stats().hits > 0);
+    }
+
+    #[test]
+    fn special_tokens_are_atomic() {
+        let vocab = vec!["<unk>".into(), "a".into(), "<".into(), ">".into(), "e".into(), "o".into(), "s".into()];
+        let mut tok = BpeTokenizer::new(vocab, Vec::new(), 0);
+        tok.add_default_special_tokens();
+        let eos = tok.special_token_id(EOS).unwrap();
+        assert_eq!(eos, 8);
+        assert_eq!(tok.add_special_token(EOS), eos);
+        assert_eq!(tok.encode("a<eos>a"), vec![1, eos, 1]);
+        assert_eq!(tok.decode(&[1, eos]), "a<eos>");
+        // untrusted text cannot produce the reserved id
+        let plain = tok.encode_with_special("a<eos>", false);
+        assert_eq!(plain, vec![1, 2, 4, 5, 6, 3]);
+        assert!(!plain.iter().any(|&id| tok.is_special(id)));
+    }
+
+    #[test]
+    fn special_tokens_persist() {
+        let mut tok = BpeTokenizer::new_byte_level(Vec::new(), Vec::new());
+        tok.add_special_token(CHUNK_START);
+        tok.add_special_token(CHUNK_END);
+        let text = "<chunk> a b</chunk>";
+        let ids = tok.encode(text);
+        assert_eq!(ids.len(), 6);
+        assert_eq!(tok.decode(&ids), text);
+
+        let dir = std::env::temp_dir().join(format!("dragon_special_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        tok.save_special_tokens(dir.join(SPECIAL_TOKENS_FILE)).unwrap();
+        let mut loaded = BpeTokenizer::new_byte_level(Vec::new(), Vec::new());
+        loaded.load_special_tokens_near(dir.join("vocab.txt")).unwrap();
+        assert_eq!(loaded.special_tokens(), tok.special_tokens());
+
+        // ids that do not match the vocabulary are rejected
+        std::fs::write(dir.join(SPECIAL_TOKENS_FILE), "<chunk>\t3\n").unwrap();
+        let mut other = BpeTokenizer::new_byte_level(Vec::new(), Vec::new());
+        assert!(other.load_special_tokens_near(dir.join("vocab.txt")).is_err());
+        // name-only lines from older files still load
+        std::fs::write(dir.join(SPECIAL_TOKENS_FILE), "<chunk>\n").unwrap();
+        let mut old = BpeTokenizer::new_byte_level(Vec::new(), Vec::new());
+        old.load_special_tokens_near(dir.join("vocab.txt")).unwrap();
+        assert!(old.special_token_id(CHUNK_START).is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[test]
     fn learn_merges_from_text() {
         let vocab = vec!["<unk>".into(), "a".into(), "b".into()];
@@ -298,4 +1102,53 @@ mod tests {
         let decoded = tok.decode(&encoded);
         assert_eq!(decoded, "ab");
     }
+
+    #[test]
+    fn tokenizers_share_trait() {
+        let words: Vec<String> = ["<unk>", "a", "b"].iter().map(|s| s.to_string()).collect();
+        let unigram = crate::unigram::UnigramTokenizer::new(
+            vec![("<unk>".into(), 0.0), ("▁a".into(), -1.0), ("▁b".into(), -1.0)],
+            0,
+        );
+        let mut bpe = BpeTokenizer::new(words.clone(), Vec::new(), 0);
+        bpe.add_special_token("<eos>");
+        let tokenizers: Vec<Box<dyn Tokenizer>> = vec![
+            Box::new(WhitespaceTokenizer::new(words, 0)),
+            Box::new(bpe),
+            Box::new(unigram),
+        ];
+        for tok in &tokenizers {
+            assert_eq!(tok.encode("a b").len(), 2);
+            assert_eq!(tok.decode(&tok.encode("a")), "a");
+            for id in 0..tok.vocab_size() {
+                let token = tok.id_to_token(id).unwrap();
+                assert_eq!(tok.token_to_id(token), Some(id));
+            }
+            assert_eq!(tok.id_to_token(tok.vocab_size()), None);
+            for (token, id) in tok.special_tokens() {
+                assert_eq!(tok.encode_with_special(token, true), vec![id]);
+            }
+        }
+        assert_eq!(tokenizers[1].special_tokens(), vec![("<eos>", 3)]);
+    }
+
+    #[test]
+    fn offsets_point_into_input() {
+        let vocab: Vec<String> = ["<unk>", "h", "é", "he", "hé"].iter().map(|s| s.to_string()).collect();
+        let mut tok = BpeTokenizer::new(vocab, vec![("h".into(), "é".into())], 0);
+        let eos = tok.add_special_token("<eos>");
+        let spans = tok.encode_with_offsets("  hé  h<eos>", true);
+        let ranges: Vec<(usize, usize, usize, usize, usize)> = spans
+            .iter()
+            .map(|s| (s.id, s.byte_start, s.byte_end, s.char_start, s.char_end))
+            .collect();
+        assert_eq!(ranges, vec![(4, 2, 5, 2, 4), (1, 7, 8, 6, 7), (eos, 8, 13, 7, 12)]);
+
+        // a byte-level token holding half of "é" maps to the whole char
+        let tok = BpeTokenizer::new_byte_level(Vec::new(), Vec::new());
+        let spans = tok.encode_with_offsets("aé", true);
+        assert_eq!(spans.len(), 3);
+        assert_eq!((spans[1].byte_start, spans[1].byte_end, spans[1].char_start, spans[1].char_end), (1, 2, 1, 2));
+        assert_eq!((spans[2].byte_start, spans[2].byte_end, spans[2].char_start, spans[2].char_end), (2, 3, 1, 2));
+    }
 }
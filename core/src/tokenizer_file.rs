@@ -0,0 +1,321 @@
+// Single-file tokenizer format.
+// One JSON document holds everything needed to rebuild a tokenizer: the
+// model (BPE vocabulary and merges, or unigram pieces), the unknown token,
+// special tokens and the text pipeline. Merges are stored as pairs so tokens
+// may contain spaces. `format` and `version` make the file self-describing;
+// files written by a newer version are rejected instead of misread. The
+// legacy `vocab.txt` / `merges.txt` layout is still read through
+// `BpeTokenizer::load_legacy`.
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+use crate::text_pipeline::TextPipeline;
+use crate::tokenizer::{BpeTokenizer, Tokenizer};
+use crate::unigram::{PieceKind, UnigramTokenizer};
+
+/// Value of the `format` field.
+pub const FORMAT_NAME: &str = "dragon-tokenizer";
+/// Newest format version this build reads and the one it writes.
+pub const FORMAT_VERSION: u32 = 1;
+/// Default file name, also used by HuggingFace; the two are told apart by
+/// the `format` field.
+pub const TOKENIZER_FILE: &str = "tokenizer.json";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Model part of a [`TokenizerFile`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModelSpec {
+    Bpe {
+        byte_level: bool,
+        unk_id: usize,
+        /// Tokens in id order.
+        vocab: Vec<String>,
+        /// Merge pairs in priority order.
+        merges: Vec<(String, String)>,
+    },
+    Unigram {
+        /// Pieces in id order with their log probability and kind.
+        pieces: Vec<(String, f32, PieceKind)>,
+        add_dummy_prefix: bool,
+        remove_extra_whitespaces: bool,
+    },
+}
+
+/// A special token and its id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecialToken {
+    pub content: String,
+    pub id: usize,
+}
+
+/// Contents of a tokenizer file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenizerFile {
+    pub format: String,
+    pub version: u32,
+    pub model: ModelSpec,
+    #[serde(default)]
+    pub special_tokens: Vec<SpecialToken>,
+    /// Unigram models only use the normalizers.
+    #[serde(default)]
+    pub pipeline: TextPipeline,
+}
+
+fn special_tokens(tokens: Vec<(&str, usize)>) -> Vec<SpecialToken> {
+    tokens
+        .into_iter()
+        .map(|(content, id)| SpecialToken {
+            content: content.to_string(),
+            id,
+        })
+        .collect()
+}
+
+// registers the special tokens, which must keep the ids they were saved with
+fn check_special(special: &[SpecialToken], mut add: impl FnMut(&str) -> usize) -> io::Result<()> {
+    for token in special {
+        let id = add(&token.content);
+        if id != token.id {
+            return Err(invalid(format!("special token {:?} has id {} instead of {}", token.content, id, token.id)));
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` when `json` is a tokenizer file rather than, for example,
+/// a HuggingFace `tokenizer.json`.
+pub fn is_tokenizer_file(json: &str) -> bool {
+    #[derive(Deserialize)]
+    struct Header {
+        format: Option<String>,
+    }
+    serde_json::from_str::<Header>(json).is_ok_and(|h| h.format.as_deref() == Some(FORMAT_NAME))
+}
+
+impl TokenizerFile {
+    /// Describes a BPE tokenizer.
+    pub fn from_bpe(tok: &BpeTokenizer) -> Self {
+        let vocab = (0..tok.vocab_size()).filter_map(|id| tok.id_to_token(id)).map(str::to_string).collect();
+        let merges = tok.merges().into_iter().map(|(a, b)| (a.to_string(), b.to_string())).collect();
+        Self {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            model: ModelSpec::Bpe {
+                byte_level: tok.is_byte_level(),
+                unk_id: tok.unk_id(),
+                vocab,
+                merges,
+            },
+            special_tokens: special_tokens(tok.special_tokens()),
+            pipeline: tok.pipeline().clone(),
+        }
+    }
+
+    /// Describes a unigram tokenizer.
+    pub fn from_unigram(tok: &UnigramTokenizer) -> Self {
+        let pieces = (0..tok.vocab_size())
+            .map(|id| {
+                let piece = tok.id_to_piece(id).unwrap_or_default().to_string();
+                (piece, tok.score(id).unwrap_or(0.0), tok.kind(id).unwrap_or(PieceKind::Normal))
+            })
+            .collect();
+        Self {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            model: ModelSpec::Unigram {
+                pieces,
+                add_dummy_prefix: tok.add_dummy_prefix,
+                remove_extra_whitespaces: tok.remove_extra_whitespaces,
+            },
+            special_tokens: special_tokens(tok.special_tokens()),
+            pipeline: TextPipeline {
+                normalizers: tok.normalizers.clone(),
+                pre_tokenizers: Vec::new(),
+            },
+        }
+    }
+
+    /// Parses a tokenizer file, rejecting other formats and newer versions.
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        if !is_tokenizer_file(json) {
+            return Err(invalid(format!("not a {} file", FORMAT_NAME)));
+        }
+        let file: Self = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        if file.version > FORMAT_VERSION {
+            return Err(invalid(format!(
+                "tokenizer file version {} is newer than the supported version {}",
+                file.version, FORMAT_VERSION
+            )));
+        }
+        Ok(file)
+    }
+
+    /// Serializes the file as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("tokenizer file serializes")
+    }
+
+    /// Reads a tokenizer file from `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the file to `path`.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    /// Builds the BPE tokenizer described by the file.
+    pub fn into_bpe(self) -> io::Result<BpeTokenizer> {
+        let (byte_level, unk_id, vocab, merges) = match self.model {
+            ModelSpec::Bpe {
+                byte_level,
+                unk_id,
+                vocab,
+                merges,
+            } => (byte_level, unk_id, vocab, merges),
+            ModelSpec::Unigram { .. } => return Err(invalid("expected a BPE model, found unigram")),
+        };
+        if unk_id >= vocab.len() && !byte_level {
+            return Err(invalid(format!("unk_id {} is outside the vocabulary", unk_id)));
+        }
+        let mut tok = if byte_level {
+            BpeTokenizer::new_byte_level(vocab, merges)
+        } else {
+            BpeTokenizer::new(vocab, merges, unk_id)
+        };
+        check_special(&self.special_tokens, |t| tok.add_special_token(t))?;
+        tok.set_pipeline(self.pipeline);
+        Ok(tok)
+    }
+
+    /// Builds the unigram tokenizer described by the file.
+    pub fn into_unigram(self) -> io::Result<UnigramTokenizer> {
+        let (pieces, add_dummy_prefix, remove_extra_whitespaces) = match self.model {
+            ModelSpec::Unigram {
+                pieces,
+                add_dummy_prefix,
+                remove_extra_whitespaces,
+            } => (pieces, add_dummy_prefix, remove_extra_whitespaces),
+            ModelSpec::Bpe { .. } => return Err(invalid("expected a unigram model, found BPE")),
+        };
+        if pieces.is_empty() {
+            return Err(invalid("model has no pieces"));
+        }
+        if !self.pipeline.pre_tokenizers.is_empty() {
+            return Err(invalid("unigram models do not use pre-tokenizers"));
+        }
+        let mut tok = UnigramTokenizer::from_pieces(pieces);
+        tok.add_dummy_prefix = add_dummy_prefix;
+        tok.remove_extra_whitespaces = remove_extra_whitespaces;
+        tok.normalizers = self.pipeline.normalizers;
+        check_special(&self.special_tokens, |t| tok.add_special_token(t))?;
+        Ok(tok)
+    }
+
+    /// Builds whichever tokenizer the file describes.
+    pub fn into_tokenizer(self) -> io::Result<Box<dyn Tokenizer + Send + Sync>> {
+        Ok(match self.model {
+            ModelSpec::Bpe { .. } => Box::new(self.into_bpe()?),
+            ModelSpec::Unigram { .. } => Box::new(self.into_unigram()?),
+        })
+    }
+}
+
+impl BpeTokenizer {
+    /// Writes the tokenizer to `path` as a single [`TokenizerFile`].
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        TokenizerFile::from_bpe(self).write(path)
+    }
+
+    /// Reads a tokenizer written by [`BpeTokenizer::save`].
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        TokenizerFile::read(path)?.into_bpe()
+    }
+}
+
+impl UnigramTokenizer {
+    /// Writes the tokenizer to `path` as a single [`TokenizerFile`].
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        TokenizerFile::from_unigram(self).write(path)
+    }
+
+    /// Reads a tokenizer written by [`UnigramTokenizer::save`].
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        TokenizerFile::read(path)?.into_unigram()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_pipeline::{Normalizer, PreTokenizer};
+
+    #[test]
+    fn bpe_roundtrips_with_spaces_in_tokens() {
+        let vocab = ["<unk>", "a", " ", "b", "a ", "a b"].iter().map(|s| s.to_string()).collect();
+        let merges = vec![("a".to_string(), " ".to_string()), ("a ".to_string(), "b".to_string())];
+        let mut tok = BpeTokenizer::new(vocab, merges, 0);
+        tok.add_special_token("<eos>");
+        // no pre-tokenizer, so spaces reach the merges
+        tok.set_pipeline(TextPipeline {
+            normalizers: vec![Normalizer::Lowercase],
+            pre_tokenizers: Vec::new(),
+        });
+        assert_eq!(tok.encode("A B<eos>"), vec![5, 6]);
+
+        let json = TokenizerFile::from_bpe(&tok).to_json();
+        let file = TokenizerFile::from_json(&json).unwrap();
+        assert_eq!(file.version, FORMAT_VERSION);
+        let loaded = file.into_bpe().unwrap();
+        assert_eq!(loaded.merges(), tok.merges());
+        assert_eq!(loaded.special_tokens(), tok.special_tokens());
+        assert_eq!(loaded.pipeline(), tok.pipeline());
+        assert_eq!(loaded.encode("A B<eos>"), vec![5, 6]);
+    }
+
+    #[test]
+    fn unigram_roundtrips() {
+        let pieces = vec![("<unk>".to_string(), 0.0), ("▁ab".to_string(), -1.25), ("▁".to_string(), -3.0)];
+        let mut tok = UnigramTokenizer::new(pieces, 0);
+        tok.add_special_token("<s>");
+        tok.normalizers = vec![Normalizer::Nfkc];
+        tok.add_dummy_prefix = false;
+        let loaded = TokenizerFile::from_json(&TokenizerFile::from_unigram(&tok).to_json())
+            .unwrap()
+            .into_unigram()
+            .unwrap();
+        assert_eq!(loaded.vocab_size(), 4);
+        assert_eq!(loaded.score(1), Some(-1.25));
+        assert_eq!(loaded.special_tokens(), tok.special_tokens());
+        assert_eq!(loaded.normalizers, tok.normalizers);
+        assert!(!loaded.add_dummy_prefix);
+        assert_eq!(loaded.encode(" ab<s>"), tok.encode(" ab<s>"));
+    }
+
+    #[test]
+    fn rejects_foreign_and_newer_files() {
+        let tok = BpeTokenizer::new(vec!["<unk>".into(), "a".into()], Vec::new(), 0);
+        let mut file = TokenizerFile::from_bpe(&tok);
+        file.version = FORMAT_VERSION + 1;
+        assert!(TokenizerFile::from_json(&file.to_json()).is_err());
+        assert!(TokenizerFile::from_json(r#"{"model": {"type": "BPE"}}"#).is_err());
+        assert!(!is_tokenizer_file(r#"{"model": {"type": "BPE"}}"#));
+
+        let mut file = TokenizerFile::from_bpe(&tok);
+        file.special_tokens.push(SpecialToken {
+            content: "<eos>".into(),
+            id: 7,
+        });
+        assert!(file.into_bpe().is_err());
+
+        let mut file = TokenizerFile::from_bpe(&tok);
+        file.pipeline.pre_tokenizers = vec![PreTokenizer::Whitespace];
+        assert!(file.into_unigram().is_err());
+    }
+}
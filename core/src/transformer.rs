@@ -1,8 +1,10 @@
 use crate::decoder::DecoderBlock;
+use crate::hooks::ForwardObserver;
+use crate::kv_cache::KvCache;
 
 /// Simple Transformer consisting of repeated [`DecoderBlock`]s.
 pub struct Transformer {
-    blocks: Vec<DecoderBlock>,
+    pub blocks: Vec<DecoderBlock>,
 }
 
 impl Transformer {
@@ -19,6 +21,60 @@ impl Transformer {
     pub fn forward(&self, input: &[Vec<f32>]) -> Vec<Vec<f32>> {
         self.blocks.iter().fold(input.to_owned(), |acc, block| block.forward(&acc))
     }
+
+    /// Runs the transformer while reporting per-layer activations and
+    /// attention probabilities to `observer`.
+    pub fn forward_with_observer(
+        &self,
+        input: &[Vec<f32>],
+        observer: &mut dyn ForwardObserver,
+    ) -> Vec<Vec<f32>> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .fold(input.to_owned(), |acc, (i, block)| block.forward_observed(&acc, i, observer))
+    }
+
+    /// Runs the transformer on positions following those held in `cache`,
+    /// appending their keys and values to it.
+    pub fn forward_cached(&self, input: &[Vec<f32>], cache: &mut KvCache) -> Vec<Vec<f32>> {
+        if cache.layers.len() < self.blocks.len() {
+            cache.layers.resize_with(self.blocks.len(), Default::default);
+        }
+        self.blocks
+            .iter()
+            .zip(cache.layers.iter_mut())
+            .fold(input.to_owned(), |acc, (block, layer)| block.forward_cached(&acc, layer))
+    }
+
+    /// Runs the stacked new rows of several sequences through every block,
+    /// where `lens[s]` rows belong to the sequence cached in `caches[s]`.
+    pub fn forward_batch_cached(
+        &self,
+        input: &[Vec<f32>],
+        lens: &[usize],
+        caches: &mut [&mut KvCache],
+    ) -> Vec<Vec<f32>> {
+        for cache in caches.iter_mut() {
+            if cache.layers.len() < self.blocks.len() {
+                cache.layers.resize_with(self.blocks.len(), Default::default);
+            }
+        }
+        let mut hidden = input.to_owned();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut layers: Vec<_> = caches.iter_mut().map(|c| &mut c.layers[i]).collect();
+            hidden = block.forward_batch_cached(&hidden, lens, &mut layers);
+        }
+        hidden
+    }
+
+    /// Runs only the first `num_layers` blocks and returns their output.
+    pub fn forward_layers(&self, input: &[Vec<f32>], num_layers: usize) -> Vec<Vec<f32>> {
+        self.blocks
+            .iter()
+            .take(num_layers)
+            .fold(input.to_owned(), |acc, block| block.forward(&acc))
+    }
 }
 
 #[cfg(test)]
@@ -33,4 +89,12 @@ mod tests {
         assert_eq!(output.len(), input.len());
         assert_eq!(output[0].len(), input[0].len());
     }
+
+    #[test]
+    fn forward_layers_prefix() {
+        let model = Transformer::new(3, 2, 2, 1);
+        let input = vec![vec![1.0f32, -1.0], vec![0.5, 0.25]];
+        assert_eq!(model.forward_layers(&input, 0), input);
+        assert_eq!(model.forward_layers(&input, 3), model.forward(&input));
+    }
 }
@@ -0,0 +1,989 @@
+// SentencePiece style unigram language model tokenizer.
+// Every piece carries a log probability and encoding picks the segmentation
+// with the highest total score by a Viterbi search over the lattice of
+// pieces matching the text. Whitespace is escaped to `▁` and one `▁` is
+// prepended to the input, so words keep their leading space inside pieces.
+// Besides the best path the lattice yields n-best lists and sampled
+// segmentations for subword regularization. Optional Unicode normalizers run
+// before the whitespace handling. Models are read from and written to
+// SentencePiece `.model` files, a protobuf parsed by hand below.
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use crate::text_pipeline::{normalize_aligned, Normalizer};
+use crate::tokenizer::{subslice_offset, token_spans, TokenSpan, Tokenizer};
+
+/// Marker that replaces spaces inside pieces.
+pub const SPACE_MARKER: char = '▁';
+
+// text emitted for unknown pieces, as in SentencePiece
+const UNK_SURFACE: &str = " \u{2047} ";
+// unknown characters score this far below the worst piece
+const UNK_PENALTY: f32 = 10.0;
+
+/// Role of a piece, matching SentencePiece's piece types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PieceKind {
+    Normal,
+    Unknown,
+    /// Control symbols such as `<s>`; never produced by segmentation.
+    Control,
+    /// Pieces matched atomically before segmentation.
+    UserDefined,
+    Unused,
+    /// Byte fallback pieces `<0x00>` to `<0xFF>`.
+    Byte,
+}
+
+impl PieceKind {
+    fn from_proto(value: u64) -> Self {
+        match value {
+            2 => PieceKind::Unknown,
+            3 => PieceKind::Control,
+            4 => PieceKind::UserDefined,
+            5 => PieceKind::Unused,
+            6 => PieceKind::Byte,
+            _ => PieceKind::Normal,
+        }
+    }
+
+    fn to_proto(self) -> u64 {
+        match self {
+            PieceKind::Normal => 1,
+            PieceKind::Unknown => 2,
+            PieceKind::Control => 3,
+            PieceKind::UserDefined => 4,
+            PieceKind::Unused => 5,
+            PieceKind::Byte => 6,
+        }
+    }
+}
+
+fn byte_piece(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b;
+    }
+    if b == f64::NEG_INFINITY {
+        return a;
+    }
+    let (hi, lo) = if a > b { (a, b) } else { (b, a) };
+    hi + (lo - hi).exp().ln_1p()
+}
+
+// byte offset of every char of `s` followed by `s.len()`
+fn char_bounds(s: &str) -> Vec<usize> {
+    s.char_indices().map(|(i, _)| i).chain(std::iter::once(s.len())).collect()
+}
+
+/// Unigram language model tokenizer.
+pub struct UnigramTokenizer {
+    pieces: Vec<(String, f32)>,
+    kinds: Vec<PieceKind>,
+    index: HashMap<String, usize>,
+    unk_id: usize,
+    min_score: f32,
+    max_piece_chars: usize,
+    // special tokens, longest first so matching prefers the longest one
+    special: Vec<(String, usize)>,
+    // ids of the 256 byte pieces when the model has byte fallback
+    byte_ids: Option<Vec<usize>>,
+    /// Prepend `▁` to the input so the first word looks like the others.
+    pub add_dummy_prefix: bool,
+    /// Trim the input and collapse runs of whitespace to one space.
+    pub remove_extra_whitespaces: bool,
+    /// Unicode normalization applied before the whitespace handling.
+    pub normalizers: Vec<Normalizer>,
+}
+
+impl UnigramTokenizer {
+    /// Creates a tokenizer from `(piece, log probability)` pairs in id order.
+    /// Pieces of the form `<0xNN>` are byte fallback pieces; when all 256
+    /// are present unknown characters are encoded as their UTF-8 bytes
+    /// instead of `unk_id`.
+    pub fn new(pieces: Vec<(String, f32)>, unk_id: usize) -> Self {
+        let pieces = pieces
+            .into_iter()
+            .enumerate()
+            .map(|(id, (piece, score))| {
+                let kind = if id == unk_id {
+                    PieceKind::Unknown
+                } else if byte_piece(&piece).is_some() {
+                    PieceKind::Byte
+                } else {
+                    PieceKind::Normal
+                };
+                (piece, score, kind)
+            })
+            .collect();
+        Self::from_pieces(pieces)
+    }
+
+    /// Creates a tokenizer from pieces with explicit kinds. The first
+    /// [`PieceKind::Unknown`] piece is used for unknown characters, and
+    /// user defined pieces become special tokens matched in the input.
+    /// Control pieces such as `<s>` are never matched in text; they can only
+    /// be looked up by name and are dropped when decoding.
+    pub fn from_pieces(pieces: Vec<(String, f32, PieceKind)>) -> Self {
+        let mut tok = Self {
+            pieces: Vec::with_capacity(pieces.len()),
+            kinds: Vec::with_capacity(pieces.len()),
+            index: HashMap::new(),
+            unk_id: 0,
+            min_score: 0.0,
+            max_piece_chars: 1,
+            special: Vec::new(),
+            byte_ids: None,
+            add_dummy_prefix: true,
+            remove_extra_whitespaces: true,
+            normalizers: Vec::new(),
+        };
+        let mut unk = None;
+        for (piece, score, kind) in pieces {
+            let id = tok.pieces.len();
+            tok.index.entry(piece.clone()).or_insert(id);
+            tok.pieces.push((piece, score));
+            tok.kinds.push(kind);
+            match kind {
+                PieceKind::Unknown => {
+                    unk.get_or_insert(id);
+                }
+                PieceKind::UserDefined => tok.push_special(id),
+                _ => {}
+            }
+        }
+        tok.unk_id = unk.unwrap_or(0);
+        tok.refresh();
+        tok
+    }
+
+    // recomputes the values derived from the normal pieces
+    fn refresh(&mut self) {
+        let normal = || (0..self.pieces.len()).filter(|&id| self.kinds[id] == PieceKind::Normal);
+        self.min_score = normal().map(|id| self.pieces[id].1).fold(0.0, f32::min);
+        self.max_piece_chars = normal().map(|id| self.pieces[id].0.chars().count()).max().unwrap_or(1);
+        let mut bytes = vec![usize::MAX; 256];
+        for (id, (piece, _)) in self.pieces.iter().enumerate() {
+            if self.kinds[id] == PieceKind::Byte {
+                if let Some(b) = byte_piece(piece) {
+                    bytes[b as usize] = id;
+                }
+            }
+        }
+        self.byte_ids = if bytes.contains(&usize::MAX) { None } else { Some(bytes) };
+    }
+
+    fn push_special(&mut self, id: usize) {
+        self.special.push((self.pieces[id].0.clone(), id));
+        self.special.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.1.cmp(&b.1)));
+    }
+
+    /// Number of pieces.
+    pub fn vocab_size(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Id used for characters no piece covers.
+    pub fn unk_id(&self) -> usize {
+        self.unk_id
+    }
+
+    /// Returns the piece string of `id`.
+    pub fn id_to_piece(&self, id: usize) -> Option<&str> {
+        self.pieces.get(id).map(|(p, _)| p.as_str())
+    }
+
+    /// Returns the id of `piece`.
+    pub fn piece_to_id(&self, piece: &str) -> Option<usize> {
+        self.index.get(piece).copied()
+    }
+
+    /// Log probability of piece `id`.
+    pub fn score(&self, id: usize) -> Option<f32> {
+        self.pieces.get(id).map(|&(_, s)| s)
+    }
+
+    /// Kind of piece `id`.
+    pub fn kind(&self, id: usize) -> Option<PieceKind> {
+        self.kinds.get(id).copied()
+    }
+
+    /// Returns `true` when unknown characters fall back to byte pieces.
+    pub fn has_byte_fallback(&self) -> bool {
+        self.byte_ids.is_some()
+    }
+
+    /// Registers `token` as a special token and returns its id. An existing
+    /// piece keeps its id, otherwise a user defined piece is appended.
+    pub fn add_special_token(&mut self, token: &str) -> usize {
+        if let Some(&(_, id)) = self.special.iter().find(|(t, _)| t == token) {
+            return id;
+        }
+        let id = match self.index.get(token) {
+            Some(&id) => {
+                self.kinds[id] = PieceKind::UserDefined;
+                id
+            }
+            None => {
+                let id = self.pieces.len();
+                self.index.insert(token.to_string(), id);
+                self.pieces.push((token.to_string(), 0.0));
+                self.kinds.push(PieceKind::UserDefined);
+                id
+            }
+        };
+        self.push_special(id);
+        self.refresh();
+        id
+    }
+
+    /// Returns the id of special token `token`, including control pieces.
+    pub fn special_token_id(&self, token: &str) -> Option<usize> {
+        self.special
+            .iter()
+            .find(|(t, _)| t == token)
+            .map(|&(_, id)| id)
+            .or_else(|| self.piece_to_id(token).filter(|&id| self.kinds[id] == PieceKind::Control))
+    }
+
+    /// Returns `true` if `id` belongs to a special token or a control piece.
+    pub fn is_special(&self, id: usize) -> bool {
+        self.kinds.get(id) == Some(&PieceKind::Control) || self.special.iter().any(|&(_, s)| s == id)
+    }
+
+    /// Registered special tokens ordered by id.
+    pub fn special_tokens(&self) -> Vec<(&str, usize)> {
+        let mut tokens: Vec<(&str, usize)> = self.special.iter().map(|(t, id)| (t.as_str(), *id)).collect();
+        tokens.sort_by_key(|t| t.1);
+        tokens
+    }
+
+    /// Applies the normalizers and the whitespace handling to a whole input:
+    /// trims it and collapses whitespace runs when `remove_extra_whitespaces`
+    /// is set, replaces spaces with `▁` and prepends the dummy prefix.
+    pub fn normalize(&self, text: &str) -> String {
+        let text = if self.remove_extra_whitespaces { text.trim() } else { text };
+        self.normalize_segment(text, true).0
+    }
+
+    // normalizes a piece of the input without trimming it, so text next to
+    // a special token keeps its boundary space. Also returns the byte range
+    // of `text` each normalized char comes from; the dummy prefix covers an
+    // empty range and a collapsed `▁` the whole whitespace run.
+    fn normalize_segment(&self, text: &str, at_start: bool) -> (String, Vec<(usize, usize)>) {
+        let mut out = String::with_capacity(text.len() + 3);
+        let mut align = Vec::with_capacity(text.len() + 1);
+        if at_start && self.add_dummy_prefix && !text.is_empty() {
+            out.push(SPACE_MARKER);
+            align.push((0, 0));
+        }
+        let normalized = normalize_aligned(&self.normalizers, text);
+        let mut in_space = false;
+        for (k, c) in normalized.text.char_indices() {
+            let (i, end) = normalized.char_range(k);
+            if self.remove_extra_whitespaces && c.is_whitespace() {
+                match align.last_mut() {
+                    Some(last) if in_space => last.1 = end,
+                    _ => {
+                        out.push(SPACE_MARKER);
+                        align.push((i, end));
+                    }
+                }
+                in_space = true;
+                continue;
+            }
+            in_space = false;
+            out.push(if c == ' ' { SPACE_MARKER } else { c });
+            align.push((i, end));
+        }
+        (out, align)
+    }
+
+    // for every start char, the pieces matching there as (end char, id, score)
+    fn lattice(&self, s: &str, exclude: Option<usize>) -> Vec<Vec<(usize, usize, f32)>> {
+        let bounds = char_bounds(s);
+        let n = bounds.len() - 1;
+        let mut edges = vec![Vec::new(); n];
+        for (start, out) in edges.iter_mut().enumerate() {
+            let mut has_char = false;
+            for end in start + 1..=(start + self.max_piece_chars).min(n) {
+                match self.index.get(&s[bounds[start]..bounds[end]]) {
+                    Some(&id) if self.kinds[id] == PieceKind::Normal && Some(id) != exclude => {
+                        has_char |= end == start + 1;
+                        out.push((end, id, self.pieces[id].1));
+                    }
+                    _ => {}
+                }
+            }
+            if !has_char {
+                out.push((start + 1, self.unk_id, self.min_score - UNK_PENALTY));
+            }
+        }
+        edges
+    }
+
+    // best path through the lattice as (id, start char, end char) and its
+    // score, optionally without piece `exclude`
+    pub(crate) fn viterbi(&self, s: &str, exclude: Option<usize>) -> (Vec<(usize, usize, usize)>, f32) {
+        let edges = self.lattice(s, exclude);
+        let n = edges.len();
+        let mut best = vec![f32::NEG_INFINITY; n + 1];
+        let mut back = vec![(0, 0); n + 1];
+        best[0] = 0.0;
+        for start in 0..n {
+            for &(end, id, score) in &edges[start] {
+                let total = best[start] + score;
+                if total > best[end] {
+                    best[end] = total;
+                    back[end] = (start, id);
+                }
+            }
+        }
+        let mut path = Vec::new();
+        let mut pos = n;
+        while pos > 0 {
+            let (start, id) = back[pos];
+            path.push((id, start, pos));
+            pos = start;
+        }
+        path.reverse();
+        (path, best[n])
+    }
+
+    // appends the tokens of `path` over normalized text `s` with the byte
+    // range of the input they cover, using the alignment from
+    // `normalize_segment` shifted by `offset`. Unknown characters are
+    // spelled as bytes when byte fallback is available.
+    fn push_path(
+        &self,
+        s: &str,
+        align: &[(usize, usize)],
+        offset: usize,
+        path: &[(usize, usize, usize)],
+        out: &mut Vec<(usize, usize, usize)>,
+    ) {
+        let bounds = char_bounds(s);
+        for &(id, start, end) in path {
+            let (a, b) = (offset + align[start].0, offset + align[end - 1].1);
+            match &self.byte_ids {
+                Some(bytes) if id == self.unk_id => {
+                    let piece = &s[bounds[start]..bounds[end]];
+                    for (i, byte) in piece.bytes().enumerate() {
+                        // byte spans only when the char was not rewritten
+                        let span = if b - a == piece.len() { (a + i, a + i + 1) } else { (a, b) };
+                        out.push((bytes[byte as usize], span.0, span.1));
+                    }
+                }
+                _ => out.push((id, a, b)),
+            }
+        }
+    }
+
+    // splits `text` around special tokens, segments the normalized plain
+    // parts with `segment` and returns every token with its byte range
+    fn encode_spans<F>(&self, text: &str, allow_special: bool, mut segment: F) -> Vec<(usize, usize, usize)>
+    where
+        F: FnMut(&str) -> Vec<(usize, usize, usize)>,
+    {
+        let trimmed = if self.remove_extra_whitespaces { text.trim() } else { text };
+        let base = subslice_offset(text, trimmed);
+        let text = trimmed;
+        let mut out = Vec::new();
+        let mut plain = |start: usize, end: usize, out: &mut Vec<(usize, usize, usize)>| {
+            if start < end {
+                let (s, align) = self.normalize_segment(&text[start..end], start == 0);
+                self.push_path(&s, &align, base + start, &segment(&s), out);
+            }
+        };
+        if !allow_special || self.special.is_empty() {
+            plain(0, text.len(), &mut out);
+            return out;
+        }
+        let mut plain_start = 0;
+        let mut pos = 0;
+        while pos < text.len() {
+            let rest = &text[pos..];
+            match self.special.iter().find(|(t, _)| rest.starts_with(t.as_str())) {
+                Some((token, id)) => {
+                    plain(plain_start, pos, &mut out);
+                    out.push((*id, base + pos, base + pos + token.len()));
+                    pos += token.len();
+                    plain_start = pos;
+                }
+                None => pos += rest.chars().next().map(char::len_utf8).unwrap_or(1),
+            }
+        }
+        plain(plain_start, text.len(), &mut out);
+        out
+    }
+
+    /// Encodes `text` with the most likely segmentation, matching special
+    /// tokens atomically first when `allow_special` is set.
+    pub fn encode_with_special(&self, text: &str, allow_special: bool) -> Vec<usize> {
+        self.encode_spans(text, allow_special, |s| self.viterbi(s, None).0)
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect()
+    }
+
+    /// Encodes `text` like [`UnigramTokenizer::encode_with_special`] and
+    /// returns every token with the span of `text` it was encoded from. A
+    /// piece starting with `▁` includes the whitespace it replaced; the
+    /// dummy prefix covers no input.
+    pub fn encode_with_offsets(&self, text: &str, allow_special: bool) -> Vec<TokenSpan> {
+        token_spans(text, self.encode_spans(text, allow_special, |s| self.viterbi(s, None).0))
+    }
+
+    /// Encodes `text` with the most likely segmentation.
+    pub fn encode(&self, text: &str) -> Vec<usize> {
+        self.encode_with_special(text, true)
+    }
+
+    /// Returns up to `n` segmentations of `text` with their scores, best
+    /// first. Special tokens are not matched.
+    pub fn encode_nbest(&self, text: &str, n: usize) -> Vec<(Vec<usize>, f32)> {
+        let trimmed = if self.remove_extra_whitespaces { text.trim() } else { text };
+        let (s, align) = self.normalize_segment(trimmed, true);
+        let edges = self.lattice(&s, None);
+        let len = edges.len();
+        // per end position the best partial paths as
+        // (score, start, id, rank of the path at start)
+        let mut paths: Vec<Vec<(f32, usize, usize, usize)>> = vec![Vec::new(); len + 1];
+        paths[0].push((0.0, 0, 0, 0));
+        for start in 0..len {
+            paths[start].sort_by(|a, b| b.0.total_cmp(&a.0));
+            paths[start].truncate(n);
+            for &(end, id, score) in &edges[start] {
+                for rank in 0..paths[start].len() {
+                    let total = paths[start][rank].0 + score;
+                    paths[end].push((total, start, id, rank));
+                }
+            }
+        }
+        paths[len].sort_by(|a, b| b.0.total_cmp(&a.0));
+        paths[len].truncate(n);
+        (0..paths[len].len())
+            .map(|best| {
+                let mut path = Vec::new();
+                let (mut pos, mut rank) = (len, best);
+                while pos > 0 {
+                    let (_, start, id, prev) = paths[pos][rank];
+                    path.push((id, start, pos));
+                    pos = start;
+                    rank = prev;
+                }
+                path.reverse();
+                let mut tokens = Vec::new();
+                self.push_path(&s, &align, 0, &path, &mut tokens);
+                (tokens.into_iter().map(|(id, _, _)| id).collect(), paths[len][best].0)
+            })
+            .collect()
+    }
+
+    /// Samples a segmentation of `text` with probability proportional to
+    /// its likelihood raised to `alpha` (subword regularization). Large
+    /// `alpha` approaches [`UnigramTokenizer::encode`], small `alpha`
+    /// approaches a uniform choice among segmentations.
+    pub fn sample_encode<R: Rng + ?Sized>(&self, text: &str, alpha: f32, rng: &mut R) -> Vec<usize> {
+        let tokens = self.encode_spans(text, true, |s| {
+            let edges = self.lattice(s, None);
+            let n = edges.len();
+            let mut forward = vec![f64::NEG_INFINITY; n + 1];
+            let mut incoming = vec![Vec::new(); n + 1];
+            forward[0] = 0.0;
+            for start in 0..n {
+                for &(end, id, score) in &edges[start] {
+                    let weight = (alpha * score) as f64;
+                    forward[end] = log_add(forward[end], forward[start] + weight);
+                    incoming[end].push((start, id, weight));
+                }
+            }
+            // backward sampling of one path
+            let mut path = Vec::new();
+            let mut pos = n;
+            while pos > 0 {
+                let mut r = rng.gen::<f64>();
+                let mut chosen = incoming[pos][0];
+                for &edge in &incoming[pos] {
+                    chosen = edge;
+                    r -= (forward[edge.0] + edge.2 - forward[pos]).exp();
+                    if r <= 0.0 {
+                        break;
+                    }
+                }
+                path.push((chosen.1, chosen.0, pos));
+                pos = chosen.0;
+            }
+            path.reverse();
+            path
+        });
+        tokens.into_iter().map(|(id, _, _)| id).collect()
+    }
+
+    // adds the posterior count of every piece in the lattice of `s`, weighted
+    // by `freq`, to `counts` and returns `freq` times the log likelihood
+    pub(crate) fn expected_counts(&self, s: &str, freq: f64, counts: &mut [f64]) -> f64 {
+        let edges = self.lattice(s, None);
+        let n = edges.len();
+        let mut forward = vec![f64::NEG_INFINITY; n + 1];
+        let mut backward = vec![f64::NEG_INFINITY; n + 1];
+        forward[0] = 0.0;
+        backward[n] = 0.0;
+        for start in 0..n {
+            for &(end, _, score) in &edges[start] {
+                forward[end] = log_add(forward[end], forward[start] + score as f64);
+            }
+        }
+        for start in (0..n).rev() {
+            for &(end, _, score) in &edges[start] {
+                backward[start] = log_add(backward[start], backward[end] + score as f64);
+            }
+        }
+        let z = forward[n];
+        for start in 0..n {
+            for &(end, id, score) in &edges[start] {
+                counts[id] += freq * (forward[start] + score as f64 + backward[end] - z).exp();
+            }
+        }
+        freq * z
+    }
+
+    /// Decodes ids back into text. `▁` becomes a space, byte pieces are
+    /// joined into UTF-8, control pieces are dropped and unknown pieces are
+    /// shown as ` ⁇ `.
+    pub fn decode(&self, tokens: &[usize]) -> String {
+        let mut bytes = Vec::new();
+        for &id in tokens {
+            let (piece, kind) = match (self.pieces.get(id), self.kinds.get(id)) {
+                (Some((piece, _)), Some(&kind)) => (piece, kind),
+                _ => continue,
+            };
+            match kind {
+                PieceKind::Byte => bytes.extend(byte_piece(piece)),
+                PieceKind::Control => {}
+                PieceKind::Unknown => bytes.extend_from_slice(UNK_SURFACE.as_bytes()),
+                PieceKind::UserDefined => bytes.extend_from_slice(piece.as_bytes()),
+                PieceKind::Normal | PieceKind::Unused => {
+                    bytes.extend_from_slice(piece.replace(SPACE_MARKER, " ").as_bytes());
+                }
+            }
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        match text.strip_prefix(' ') {
+            Some(rest) if self.add_dummy_prefix => rest.to_string(),
+            _ => text.into_owned(),
+        }
+    }
+
+    /// Parses a SentencePiece `.model` file. Only unigram models are
+    /// supported. The `nfkc` and `nfkc_cf` normalization rules (with or
+    /// without the `nmt_` prefix) map to [`Normalizer::Nfkc`], followed by
+    /// [`Normalizer::Lowercase`] for `_cf`; other rules and precompiled
+    /// character maps are not applied, only the whitespace options.
+    pub fn from_model_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut pieces = Vec::new();
+        let mut normalizers = Vec::new();
+        let mut add_dummy_prefix = true;
+        let mut remove_extra_whitespaces = true;
+        for (field, value) in parse_message(data)? {
+            match (field, value) {
+                (1, Field::Bytes(piece)) => {
+                    let mut text = String::new();
+                    let mut score = 0.0;
+                    let mut kind = PieceKind::Normal;
+                    for (field, value) in parse_message(piece)? {
+                        match (field, value) {
+                            (1, Field::Bytes(b)) => {
+                                text = String::from_utf8(b.to_vec()).map_err(|e| invalid(e.to_string()))?
+                            }
+                            (2, Field::Fixed32(bits)) => score = f32::from_bits(bits),
+                            (3, Field::Varint(v)) => kind = PieceKind::from_proto(v),
+                            _ => {}
+                        }
+                    }
+                    pieces.push((text, score, kind));
+                }
+                (2, Field::Bytes(trainer)) => {
+                    for (field, value) in parse_message(trainer)? {
+                        if let (3, Field::Varint(model_type)) = (field, value) {
+                            if model_type != 1 {
+                                return Err(invalid(format!("unsupported model type {}", model_type)));
+                            }
+                        }
+                    }
+                }
+                (3, Field::Bytes(normalizer)) => {
+                    for (field, value) in parse_message(normalizer)? {
+                        match (field, value) {
+                            (1, Field::Bytes(name)) => {
+                                normalizers = match name.strip_prefix(b"nmt_").unwrap_or(name) {
+                                    b"nfkc" => vec![Normalizer::Nfkc],
+                                    b"nfkc_cf" => vec![Normalizer::Nfkc, Normalizer::Lowercase],
+                                    _ => Vec::new(),
+                                }
+                            }
+                            (3, Field::Varint(v)) => add_dummy_prefix = v != 0,
+                            (4, Field::Varint(v)) => remove_extra_whitespaces = v != 0,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if pieces.is_empty() {
+            return Err(invalid("model has no pieces"));
+        }
+        let mut tok = Self::from_pieces(pieces);
+        tok.add_dummy_prefix = add_dummy_prefix;
+        tok.remove_extra_whitespaces = remove_extra_whitespaces;
+        tok.normalizers = normalizers;
+        Ok(tok)
+    }
+
+    /// Reads a SentencePiece `.model` file, see
+    /// [`UnigramTokenizer::from_model_bytes`].
+    pub fn load_model<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_model_bytes(&std::fs::read(path)?)
+    }
+
+    /// Serializes the tokenizer as a SentencePiece `.model` protobuf. The
+    /// normalizers are written as a rule name, which only exists for none,
+    /// NFKC and NFKC followed by lowercasing; other combinations are an
+    /// `InvalidData` error, use [`UnigramTokenizer::save`] for those.
+    pub fn to_model_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for (id, (piece, score)) in self.pieces.iter().enumerate() {
+            let mut msg = Vec::new();
+            write_bytes(&mut msg, 1, piece.as_bytes());
+            write_tag(&mut msg, 2, 5);
+            msg.extend_from_slice(&score.to_bits().to_le_bytes());
+            write_tag(&mut msg, 3, 0);
+            write_varint(&mut msg, self.kinds[id].to_proto());
+            write_bytes(&mut out, 1, &msg);
+        }
+        let mut trainer = Vec::new();
+        write_tag(&mut trainer, 3, 0);
+        write_varint(&mut trainer, 1);
+        write_bytes(&mut out, 2, &trainer);
+        let mut normalizer = Vec::new();
+        let name: &[u8] = match self.normalizers.as_slice() {
+            [Normalizer::Nfkc] => b"nmt_nfkc",
+            [Normalizer::Nfkc, Normalizer::Lowercase] => b"nmt_nfkc_cf",
+            [] => b"identity",
+            other => return Err(invalid(format!("normalizers {:?} have no SentencePiece rule", other))),
+        };
+        write_bytes(&mut normalizer, 1, name);
+        for (field, flag) in [(3, self.add_dummy_prefix), (4, self.remove_extra_whitespaces), (5, true)] {
+            write_tag(&mut normalizer, field, 0);
+            write_varint(&mut normalizer, flag as u64);
+        }
+        write_bytes(&mut out, 3, &normalizer);
+        Ok(out)
+    }
+
+    /// Writes the tokenizer to `path` as a SentencePiece `.model` file. See
+    /// [`UnigramTokenizer::to_model_bytes`] for the normalizers it can hold.
+    pub fn save_model<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_model_bytes()?)
+    }
+}
+
+impl Tokenizer for UnigramTokenizer {
+    fn encode(&self, text: &str) -> Vec<usize> {
+        UnigramTokenizer::encode(self, text)
+    }
+
+    fn encode_with_special(&self, text: &str, allow_special: bool) -> Vec<usize> {
+        UnigramTokenizer::encode_with_special(self, text, allow_special)
+    }
+
+    fn decode(&self, tokens: &[usize]) -> String {
+        UnigramTokenizer::decode(self, tokens)
+    }
+
+    fn vocab_size(&self) -> usize {
+        UnigramTokenizer::vocab_size(self)
+    }
+
+    fn token_to_id(&self, token: &str) -> Option<usize> {
+        self.piece_to_id(token)
+    }
+
+    fn id_to_token(&self, id: usize) -> Option<&str> {
+        self.id_to_piece(id)
+    }
+
+    fn special_tokens(&self) -> Vec<(&str, usize)> {
+        UnigramTokenizer::special_tokens(self)
+    }
+
+    fn encode_with_offsets(&self, text: &str, allow_special: bool) -> Vec<TokenSpan> {
+        UnigramTokenizer::encode_with_offsets(self, text, allow_special)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+// protobuf field values by wire type
+enum Field<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or_else(|| invalid("truncated varint"))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn parse_message(data: &[u8]) -> io::Result<Vec<(u64, Field<'_>)>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let value = match key & 7 {
+            0 => Field::Varint(read_varint(data, &mut pos)?),
+            1 => {
+                pos += 8;
+                Field::Fixed64
+            }
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let bytes = data
+                    .get(pos..pos.saturating_add(len))
+                    .ok_or_else(|| invalid("truncated field"))?;
+                pos += len;
+                Field::Bytes(bytes)
+            }
+            5 => {
+                let bytes = data.get(pos..pos + 4).ok_or_else(|| invalid("truncated field"))?;
+                pos += 4;
+                Field::Fixed32(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            wire => return Err(invalid(format!("unsupported wire type {}", wire))),
+        };
+        if pos > data.len() {
+            return Err(invalid("truncated field"));
+        }
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_tag(out: &mut Vec<u8>, field: u64, wire: u64) {
+    write_varint(out, field << 3 | wire);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_tag(out, field, 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn tokenizer() -> UnigramTokenizer {
+        let pieces = [
+            ("<unk>", 0.0),
+            ("▁hello", -2.0),
+            ("▁he", -3.0),
+            ("llo", -3.0),
+            ("▁world", -2.5),
+            ("▁", -4.0),
+            ("h", -5.0),
+            ("e", -5.0),
+            ("l", -5.0),
+            ("o", -5.0),
+            ("w", -5.0),
+            ("r", -5.0),
+            ("d", -5.0),
+        ];
+        UnigramTokenizer::new(pieces.iter().map(|&(p, s)| (p.to_string(), s)).collect(), 0)
+    }
+
+    #[test]
+    fn viterbi_picks_best_segmentation() {
+        let tok = tokenizer();
+        assert_eq!(tok.encode("hello world"), vec![1, 4]);
+        assert_eq!(tok.encode("  hello   world "), vec![1, 4]);
+        assert_eq!(tok.encode("hellod"), vec![1, 12]);
+        assert_eq!(tok.decode(&[1, 4]), "hello world");
+        // unknown characters map to <unk>
+        assert_eq!(tok.encode("hez"), vec![2, 0]);
+        assert_eq!(tok.decode(&[2, 0]), "he ⁇ ");
+    }
+
+    #[test]
+    fn byte_fallback_spells_unknown_chars() {
+        let mut pieces: Vec<(String, f32)> = tokenizer().pieces.clone();
+        pieces.extend((0..=255u8).map(|b| (format!("<0x{:02X}>", b), 0.0)));
+        let tok = UnigramTokenizer::new(pieces, 0);
+        assert!(tok.has_byte_fallback());
+        let ids = tok.encode("hello wörld");
+        assert!(!ids.contains(&0));
+        assert_eq!(tok.decode(&ids), "hello wörld");
+    }
+
+    #[test]
+    fn nbest_is_sorted_and_starts_with_viterbi() {
+        let tok = tokenizer();
+        let nbest = tok.encode_nbest("hello", 4);
+        assert_eq!(nbest.len(), 4);
+        assert_eq!(nbest[0].0, tok.encode("hello"));
+        assert_eq!(nbest[1].0, vec![2, 3]);
+        for pair in nbest.windows(2) {
+            assert!(pair[0].1 >= pair[1].1);
+            assert_ne!(pair[0].0, pair[1].0);
+        }
+        for (ids, _) in &nbest {
+            assert_eq!(tok.decode(ids), "hello");
+        }
+    }
+
+    #[test]
+    fn nan_scores_do_not_panic() {
+        // `.model` files can carry any bit pattern as a score
+        let pieces = vec![
+            ("<unk>".to_string(), 0.0, PieceKind::Unknown),
+            ("▁he".to_string(), f32::NAN, PieceKind::Normal),
+            ("▁".to_string(), -1.0, PieceKind::Normal),
+            ("h".to_string(), -1.0, PieceKind::Normal),
+            ("e".to_string(), -1.0, PieceKind::Normal),
+        ];
+        let tok = UnigramTokenizer::from_pieces(pieces);
+        let nbest = tok.encode_nbest("he", 3);
+        assert!(!nbest.is_empty());
+        for (ids, _) in &nbest {
+            assert_eq!(tok.decode(ids), "he");
+        }
+    }
+
+    #[test]
+    fn sampling_explores_segmentations() {
+        let tok = tokenizer();
+        let mut rng = StdRng::seed_from_u64(46);
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..200 {
+            let ids = tok.sample_encode("hello world", 0.1, &mut rng);
+            assert_eq!(tok.decode(&ids), "hello world");
+            seen.insert(ids);
+        }
+        assert!(seen.len() > 3);
+        for _ in 0..20 {
+            assert_eq!(tok.sample_encode("hello world", 50.0, &mut rng), vec![1, 4]);
+        }
+    }
+
+    #[test]
+    fn special_tokens_are_atomic() {
+        let mut tok = tokenizer();
+        let eos = tok.add_special_token("<eos>");
+        assert_eq!(eos, 13);
+        assert_eq!(tok.encode("hello<eos>world"), vec![1, eos, 10, 9, 11, 8, 12]);
+        assert_ne!(tok.encode_with_special("<eos>", false), vec![eos]);
+        assert_eq!(tok.decode(&[1, eos]), "hello<eos>");
+    }
+
+    #[test]
+    fn parses_model_protobuf() {
+        // pieces "<unk>" (unknown), "▁a" (-1.5) and "<s>" (control), plus a
+        // normalizer spec disabling the dummy prefix
+        let mut data = Vec::new();
+        for (piece, score, kind) in [("<unk>", 0.0f32, 2u8), ("▁a", -1.5, 1), ("<s>", 0.0, 3)] {
+            let mut msg = vec![0x0a, piece.len() as u8];
+            msg.extend_from_slice(piece.as_bytes());
+            msg.push(0x15);
+            msg.extend_from_slice(&score.to_le_bytes());
+            msg.extend_from_slice(&[0x18, kind]);
+            data.extend_from_slice(&[0x0a, msg.len() as u8]);
+            data.extend_from_slice(&msg);
+        }
+        data.extend_from_slice(&[0x12, 0x02, 0x18, 0x01, 0x1a, 0x02, 0x18, 0x00]);
+        let tok = UnigramTokenizer::from_model_bytes(&data).unwrap();
+        assert_eq!(tok.vocab_size(), 3);
+        assert_eq!(tok.score(1), Some(-1.5));
+        assert_eq!(tok.kind(2), Some(PieceKind::Control));
+        assert_eq!(tok.special_token_id("<s>"), Some(2));
+        assert!(!tok.add_dummy_prefix);
+        // control pieces are looked up by name but never matched in text
+        assert!(tok.special_tokens().is_empty());
+        assert!(!tok.encode("<s> a").contains(&2));
+        assert_eq!(tok.encode("<s> a").last(), Some(&1));
+        assert_eq!(tok.decode(&[2, 1]), " a");
+
+        let reloaded = UnigramTokenizer::from_model_bytes(&tok.to_model_bytes().unwrap()).unwrap();
+        assert_eq!(reloaded.pieces, tok.pieces);
+        assert_eq!(reloaded.kinds, tok.kinds);
+        assert!(!reloaded.add_dummy_prefix);
+
+        // BPE models are rejected
+        data.extend_from_slice(&[0x12, 0x02, 0x18, 0x02]);
+        assert!(UnigramTokenizer::from_model_bytes(&data).is_err());
+        assert!(UnigramTokenizer::from_model_bytes(&[0x0a, 0x05]).is_err());
+    }
+
+    #[test]
+    fn offsets_follow_normalization() {
+        let tok = tokenizer();
+        let spans: Vec<(usize, usize, usize)> = tok
+            .encode_with_offsets(" hello \t world", true)
+            .iter()
+            .map(|s| (s.id, s.byte_start, s.byte_end))
+            .collect();
+        // the dummy prefix covers nothing, the collapsed run is part of "▁world"
+        assert_eq!(spans, vec![(1, 1, 6), (4, 6, 14)]);
+    }
+
+    #[test]
+    fn normalizers_run_first() {
+        let mut tok = tokenizer();
+        tok.normalizers = vec![Normalizer::Nfkc, Normalizer::Lowercase];
+        // fullwidth letters fold to "hello"
+        let text = "ＨＥＬＬＯ world";
+        assert_eq!(tok.encode(text), tokenizer().encode("hello world"));
+        let spans = tok.encode_with_offsets(text, true);
+        assert_eq!((spans[0].byte_start, spans[0].byte_end), (0, 15));
+
+        let reloaded = UnigramTokenizer::from_model_bytes(&tok.to_model_bytes().unwrap()).unwrap();
+        assert_eq!(reloaded.normalizers, tok.normalizers);
+
+        // lowercasing alone has no SentencePiece rule
+        tok.normalizers = vec![Normalizer::Lowercase];
+        assert!(tok.to_model_bytes().is_err());
+    }
+}
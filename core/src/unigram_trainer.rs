@@ -0,0 +1,292 @@
+// Unigram language model trainer.
+// Training starts from every character plus the most frequent substrings of
+// the corpus words. It then alternates EM re-estimation of the piece
+// probabilities with pruning: each piece is scored by how much likelihood
+// the corpus loses when the piece is replaced by its best alternative
+// segmentation, and the cheapest pieces are dropped until the vocabulary
+// reaches the requested size. Characters are never pruned so every word
+// stays encodable. Words are counted once while the corpus streams in.
+use std::collections::HashMap;
+
+use crate::text_pipeline::{normalize_aligned, Normalizer};
+use crate::unigram::{PieceKind, UnigramTokenizer, SPACE_MARKER};
+
+/// Options for [`UnigramTrainer`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnigramTrainerConfig {
+    /// Vocabulary size to stop at, `<unk>` and special tokens included. The
+    /// result is larger when the corpus has more distinct characters.
+    pub vocab_size: usize,
+    /// Longest piece in characters.
+    pub max_piece_len: usize,
+    /// Substrings seen fewer times than this are not used as seed pieces.
+    pub min_frequency: u64,
+    /// Number of seed pieces besides the characters.
+    pub seed_size: usize,
+    /// Fraction of the pieces kept by each pruning round.
+    pub shrinking_factor: f64,
+    /// EM iterations run before each pruning round.
+    pub em_iterations: usize,
+    /// Tokens reserved right after `<unk>` and registered as special tokens.
+    pub special_tokens: Vec<String>,
+    /// Unicode normalization applied to the corpus and stored in the model.
+    pub normalizers: Vec<Normalizer>,
+}
+
+impl Default for UnigramTrainerConfig {
+    fn default() -> Self {
+        Self {
+            vocab_size: 8_000,
+            max_piece_len: 16,
+            min_frequency: 2,
+            seed_size: 100_000,
+            shrinking_factor: 0.75,
+            em_iterations: 2,
+            special_tokens: Vec::new(),
+            normalizers: Vec::new(),
+        }
+    }
+}
+
+/// Streaming unigram trainer producing a [`UnigramTokenizer`].
+pub struct UnigramTrainer {
+    config: UnigramTrainerConfig,
+    word_counts: HashMap<String, u64>,
+}
+
+// tokenizer over the current pieces with `<unk>` at id 0
+fn model(pieces: &[(String, f64)]) -> UnigramTokenizer {
+    let mut all = vec![("<unk>".to_string(), 0.0, PieceKind::Unknown)];
+    all.extend(pieces.iter().map(|(p, s)| (p.clone(), *s as f32, PieceKind::Normal)));
+    UnigramTokenizer::from_pieces(all)
+}
+
+// converts expected counts into log probabilities
+fn normalize_scores(counts: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    let total: f64 = counts.iter().map(|(_, c)| c).sum();
+    counts.into_iter().map(|(p, c)| (p, c.ln() - total.ln())).collect()
+}
+
+impl UnigramTrainer {
+    pub fn new(config: UnigramTrainerConfig) -> Self {
+        Self {
+            config,
+            word_counts: HashMap::new(),
+        }
+    }
+
+    /// Number of distinct words seen so far.
+    pub fn distinct_words(&self) -> usize {
+        self.word_counts.len()
+    }
+
+    /// Counts the whitespace separated words of `text` after the
+    /// normalizers, each with a leading `▁`. Occurrences of special tokens
+    /// are skipped.
+    pub fn feed(&mut self, text: &str) {
+        let mut segments = vec![text];
+        for special in &self.config.special_tokens {
+            segments = segments.into_iter().flat_map(|s| s.split(special.as_str())).collect();
+        }
+        for segment in segments {
+            let normalized = normalize_aligned(&self.config.normalizers, segment).text;
+            for word in normalized.split_whitespace() {
+                *self.word_counts.entry(format!("{}{}", SPACE_MARKER, word)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // characters of the corpus with their counts, most frequent first
+    fn characters(&self, words: &[(&String, u64)]) -> Vec<(String, f64)> {
+        let mut counts: HashMap<char, u64> = HashMap::new();
+        for (word, freq) in words {
+            for c in word.chars() {
+                *counts.entry(c).or_insert(0) += freq;
+            }
+        }
+        let mut chars: Vec<(char, u64)> = counts.into_iter().collect();
+        chars.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        chars.into_iter().map(|(c, n)| (c.to_string(), n as f64)).collect()
+    }
+
+    // frequent multi-character substrings that do not cross a word start
+    fn seeds(&self, words: &[(&String, u64)]) -> Vec<(String, f64)> {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for (word, freq) in words {
+            let bounds: Vec<usize> = word.char_indices().map(|(i, _)| i).chain([word.len()]).collect();
+            for start in 0..bounds.len() - 1 {
+                for end in start + 2..bounds.len().min(start + self.config.max_piece_len + 1) {
+                    let sub = &word[bounds[start]..bounds[end]];
+                    if sub[bounds[start + 1] - bounds[start]..].contains(SPACE_MARKER) {
+                        break;
+                    }
+                    *counts.entry(sub).or_insert(0) += freq;
+                }
+            }
+        }
+        let mut seeds: Vec<(&str, u64)> = counts
+            .into_iter()
+            .filter(|&(_, n)| n >= self.config.min_frequency)
+            .collect();
+        // frequent long substrings cover the most text
+        seeds.sort_by(|a, b| {
+            let score = |s: &(&str, u64)| s.1 * s.0.chars().count() as u64;
+            score(b).cmp(&score(a)).then(a.0.cmp(b.0))
+        });
+        seeds.truncate(self.config.seed_size);
+        seeds.into_iter().map(|(s, n)| (s.to_string(), n as f64)).collect()
+    }
+
+    // one EM step: posterior piece counts under the current model become the
+    // new probabilities; unused multi-character pieces are dropped
+    fn em_step(&self, words: &[(&String, u64)], pieces: &[(String, f64)], required: usize) -> Vec<(String, f64)> {
+        let model = model(pieces);
+        let mut counts = vec![0.0; model.vocab_size()];
+        for (word, freq) in words {
+            model.expected_counts(word, *freq as f64, &mut counts);
+        }
+        let kept = pieces
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (piece, _))| {
+                let count = counts[i + 1];
+                if i < required {
+                    Some((piece.clone(), count.max(1e-6)))
+                } else if count >= 0.5 {
+                    Some((piece.clone(), count))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        normalize_scores(kept)
+    }
+
+    // drops the multi-character pieces whose loss is smallest, keeping at
+    // least `target` pieces
+    fn prune(&self, words: &[(&String, u64)], pieces: &[(String, f64)], required: usize, target: usize) -> Vec<(String, f64)> {
+        let model = model(pieces);
+        let mut freq = vec![0.0; model.vocab_size()];
+        for (word, n) in words {
+            for (id, _, _) in model.viterbi(word, None).0 {
+                freq[id] += *n as f64;
+            }
+        }
+        let mut losses: Vec<(f64, usize)> = (required..pieces.len())
+            .map(|i| {
+                let (piece, score) = &pieces[i];
+                let alternative = model.viterbi(piece, Some(i + 1)).1 as f64;
+                (freq[i + 1] * (score - alternative), i)
+            })
+            .collect();
+        losses.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        let size = ((pieces.len() as f64 * self.config.shrinking_factor) as usize).max(target);
+        let mut keep: Vec<usize> = losses.iter().take(size.saturating_sub(required)).map(|&(_, i)| i).collect();
+        keep.sort();
+        pieces[..required]
+            .iter()
+            .chain(keep.iter().map(|&i| &pieces[i]))
+            .cloned()
+            .collect()
+    }
+
+    /// Trains the tokenizer. Ids start with `<unk>` and the special tokens,
+    /// followed by the pieces from most to least likely.
+    pub fn train(&self) -> UnigramTokenizer {
+        let mut words: Vec<(&String, u64)> = self.word_counts.iter().map(|(w, &n)| (w, n)).collect();
+        words.sort();
+        let chars = self.characters(&words);
+        let required = chars.len();
+        let mut pieces = chars;
+        pieces.extend(self.seeds(&words));
+        pieces = normalize_scores(pieces);
+        let target = self
+            .config
+            .vocab_size
+            .saturating_sub(1 + self.config.special_tokens.len())
+            .max(required);
+        loop {
+            for _ in 0..self.config.em_iterations {
+                pieces = self.em_step(&words, &pieces, required);
+            }
+            if pieces.len() <= target {
+                break;
+            }
+            pieces = self.prune(&words, &pieces, required, target);
+        }
+        pieces.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut all = vec![("<unk>".to_string(), 0.0, PieceKind::Unknown)];
+        for token in &self.config.special_tokens {
+            all.push((token.clone(), 0.0, PieceKind::UserDefined));
+        }
+        all.extend(pieces.into_iter().map(|(p, s)| (p, s as f32, PieceKind::Normal)));
+        let mut tok = UnigramTokenizer::from_pieces(all);
+        tok.normalizers = self.config.normalizers.clone();
+        tok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learns_frequent_words() {
+        let mut trainer = UnigramTrainer::new(UnigramTrainerConfig {
+            vocab_size: 30,
+            special_tokens: vec!["<eos>".into()],
+            ..Default::default()
+        });
+        let corpus = "the cat sat on the mat<eos> the hat and that cat sat";
+        for _ in 0..20 {
+            trainer.feed(corpus);
+        }
+        let tok = trainer.train();
+        assert!(tok.vocab_size() <= 30, "{}", tok.vocab_size());
+        assert_eq!(tok.id_to_piece(0), Some("<unk>"));
+        assert_eq!(tok.special_token_id("<eos>"), Some(1));
+        assert_eq!(tok.encode("the"), vec![tok.piece_to_id("▁the").unwrap()]);
+        let text = "the cat sat on the mat<eos> the hat and that cat sat";
+        assert!(!tok.encode(text).contains(&tok.unk_id()));
+        assert_eq!(tok.decode(&tok.encode(text)), text);
+        for id in 2..tok.vocab_size() - 1 {
+            assert!(tok.score(id) >= tok.score(id + 1));
+        }
+    }
+
+    #[test]
+    fn keeps_every_character() {
+        let mut trainer = UnigramTrainer::new(UnigramTrainerConfig {
+            vocab_size: 2,
+            ..Default::default()
+        });
+        trainer.feed("abc abd xyz");
+        let tok = trainer.train();
+        // <unk>, ▁ and the seven letters
+        assert_eq!(tok.vocab_size(), 9);
+        assert_eq!(tok.decode(&tok.encode("zyx cab")), "zyx cab");
+    }
+
+    #[test]
+    fn lowercase_survives_save() {
+        let mut trainer = UnigramTrainer::new(UnigramTrainerConfig {
+            vocab_size: 30,
+            normalizers: vec![Normalizer::Lowercase],
+            ..Default::default()
+        });
+        for _ in 0..20 {
+            trainer.feed("The Cat sat on THE mat and the cat sat");
+        }
+        let tok = trainer.train();
+        let path = std::env::temp_dir().join(format!("dragon_unigram_lower_{}.json", std::process::id()));
+        tok.save(&path).unwrap();
+        let loaded = UnigramTokenizer::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let text = "THE CAT Sat";
+        assert_eq!(loaded.encode(text), tok.encode(text));
+        assert_eq!(loaded.encode(text), tok.encode("the cat sat"));
+        // a SentencePiece model cannot express lowercasing alone
+        assert!(tok.to_model_bytes().is_err());
+    }
+}
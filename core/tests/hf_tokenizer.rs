@@ -0,0 +1,152 @@
+use dragon_core::text_pipeline::{Normalizer, PreTokenizer, TextPipeline};
+use dragon_core::tokenizer::{load_tokenizer, BpeTokenizer};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(path).expect("missing fixture")
+}
+
+#[test]
+fn loads_byte_level_tokenizer() {
+    let tok = BpeTokenizer::from_hf_json(&fixture("byte_level_bpe.json")).unwrap();
+    assert!(tok.is_byte_level());
+    assert_eq!(tok.vocab_size(), 266);
+    assert_eq!(tok.special_token_id("<|endoftext|>"), Some(265));
+    // "hello" and " world" are single merged tokens
+    assert_eq!(tok.encode("hello world<|endoftext|>"), vec![259, 264, 265]);
+    assert_eq!(tok.encode_with_special("<|endoftext|>", false).len(), 13);
+    let text = "hello  wörld\n\t<|endoftext|> 🐉";
+    assert_eq!(tok.decode(&tok.encode(text)), text);
+}
+
+#[test]
+fn loads_char_tokenizer_with_array_merges() {
+    let tok = BpeTokenizer::from_hf_json(&fixture("char_bpe.json")).unwrap();
+    assert!(!tok.is_byte_level());
+    assert_eq!(tok.unk_id(), 0);
+    assert_eq!(tok.special_token_id("[SEP]"), Some(10));
+    assert_eq!(tok.encode("hello hex [SEP]"), vec![9, 6, 5, 10]);
+    assert_eq!(tok.encode("hz"), vec![1, 0]);
+}
+
+#[test]
+fn export_roundtrips() {
+    for name in ["byte_level_bpe.json", "char_bpe.json"] {
+        let original: Value = serde_json::from_str(&fixture(name)).unwrap();
+        let tok = BpeTokenizer::from_hf_json(&original.to_string()).unwrap();
+        let exported = tok.to_hf_json();
+        // added tokens are also written to the model vocabulary
+        for (token, id) in original["model"]["vocab"].as_object().unwrap() {
+            assert_eq!(&exported["model"]["vocab"][token], id, "{}: {}", name, token);
+        }
+        assert_eq!(exported["added_tokens"][0]["id"], original["added_tokens"][0]["id"]);
+        let merges: Vec<String> = tok.merges().iter().map(|(a, b)| format!("{} {}", a, b)).collect();
+        assert_eq!(exported["model"]["merges"], serde_json::json!(merges));
+
+        let reloaded = BpeTokenizer::from_hf_json(&exported.to_string()).unwrap();
+        assert_eq!(reloaded.is_byte_level(), tok.is_byte_level());
+        assert_eq!(reloaded.merges(), tok.merges());
+        let mut rng = StdRng::seed_from_u64(45);
+        for _ in 0..200 {
+            let text: String = (0..rng.gen_range(0..20))
+                .map(|_| ['h', 'e', 'l', 'o', ' ', 'w', 'r', 'd', 'é', '\n'][rng.gen_range(0..10)])
+                .collect();
+            assert_eq!(reloaded.encode(&text), tok.encode(&text), "{:?}", text);
+        }
+    }
+}
+
+#[test]
+fn imports_normalizers_and_pre_tokenizers() {
+    let mut doc: Value = serde_json::from_str(&fixture("char_bpe.json")).unwrap();
+    doc["normalizer"] = serde_json::json!({
+        "type": "Sequence",
+        "normalizers": [{ "type": "NFD" }, { "type": "StripAccents" }, { "type": "Lowercase" }],
+    });
+    doc["pre_tokenizer"] = serde_json::json!({
+        "type": "Sequence",
+        "pretokenizers": [{ "type": "WhitespaceSplit" }, { "type": "Digits", "individual_digits": true }],
+    });
+    let tok = BpeTokenizer::from_hf_json(&doc.to_string()).unwrap();
+    assert_eq!(tok.pipeline().normalizers, vec![Normalizer::StripAccents, Normalizer::Lowercase]);
+    assert_eq!(
+        tok.pipeline().pre_tokenizers,
+        vec![PreTokenizer::Whitespace, PreTokenizer::Digits { individual: true }]
+    );
+    assert_eq!(tok.encode("HÉLLO"), tok.encode("hello"));
+}
+
+#[test]
+fn pipeline_roundtrips() {
+    let pipelines = [
+        TextPipeline {
+            normalizers: vec![Normalizer::Nfkc, Normalizer::StripAccents, Normalizer::Lowercase],
+            pre_tokenizers: vec![PreTokenizer::Whitespace, PreTokenizer::Punctuation],
+        },
+        TextPipeline {
+            normalizers: vec![Normalizer::Nfc],
+            pre_tokenizers: vec![PreTokenizer::Gpt2, PreTokenizer::Digits { individual: false }],
+        },
+    ];
+    for name in ["byte_level_bpe.json", "char_bpe.json"] {
+        for pipeline in &pipelines {
+            let mut tok = BpeTokenizer::from_hf_json(&fixture(name)).unwrap();
+            tok.set_pipeline(pipeline.clone());
+            let reloaded = BpeTokenizer::from_hf_json(&tok.to_hf_json().to_string()).unwrap();
+            assert_eq!(reloaded.pipeline(), pipeline, "{}", name);
+            assert_eq!(reloaded.is_byte_level(), tok.is_byte_level());
+        }
+    }
+    // the default byte-level pipeline is a single ByteLevel step
+    let tok = BpeTokenizer::from_hf_json(&fixture("byte_level_bpe.json")).unwrap();
+    assert_eq!(tok.to_hf_json()["pre_tokenizer"]["type"], "ByteLevel");
+}
+
+#[test]
+fn saves_and_loads_files() {
+    let tok = BpeTokenizer::from_hf_json(&fixture("byte_level_bpe.json")).unwrap();
+    let path = std::env::temp_dir().join(format!("dragon_hf_tokenizer_test_{}.json", std::process::id()));
+    tok.save_hf(&path).unwrap();
+    let loaded = BpeTokenizer::load_hf(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(loaded.encode("hello world"), tok.encode("hello world"));
+}
+
+#[test]
+fn rejects_unsupported_files() {
+    let mut doc: Value = serde_json::from_str(&fixture("char_bpe.json")).unwrap();
+    doc["model"]["type"] = "WordPiece".into();
+    assert!(BpeTokenizer::from_hf_json(&doc.to_string()).is_err());
+
+    let mut doc: Value = serde_json::from_str(&fixture("char_bpe.json")).unwrap();
+    doc["normalizer"] = serde_json::json!({ "type": "BertNormalizer" });
+    assert!(BpeTokenizer::from_hf_json(&doc.to_string()).is_err());
+
+    let mut doc: Value = serde_json::from_str(&fixture("char_bpe.json")).unwrap();
+    doc["pre_tokenizer"] = serde_json::json!({ "type": "Metaspace" });
+    assert!(BpeTokenizer::from_hf_json(&doc.to_string()).is_err());
+
+    // splits that cannot be reproduced exactly
+    doc["pre_tokenizer"] = serde_json::json!({ "type": "Whitespace" });
+    assert!(BpeTokenizer::from_hf_json(&doc.to_string()).is_err());
+    doc["pre_tokenizer"] = serde_json::json!({ "type": "ByteLevel", "add_prefix_space": true });
+    assert!(BpeTokenizer::from_hf_json(&doc.to_string()).is_err());
+
+    let mut doc: Value = serde_json::from_str(&fixture("char_bpe.json")).unwrap();
+    doc["added_tokens"][0]["id"] = 3.into();
+    assert!(BpeTokenizer::from_hf_json(&doc.to_string()).is_err());
+
+    assert!(BpeTokenizer::from_hf_json("{").is_err());
+}
+
+#[test]
+fn load_tokenizer_picks_format_by_content() {
+    let path = format!("{}/tests/fixtures/byte_level_bpe.json", env!("CARGO_MANIFEST_DIR"));
+    let tok = load_tokenizer(&path, "", 0).unwrap();
+    assert_eq!(tok.vocab_size(), 266);
+    assert_eq!(tok.encode("hello world"), vec![259, 264]);
+    assert_eq!(tok.special_tokens(), vec![("<|endoftext|>", 265)]);
+}
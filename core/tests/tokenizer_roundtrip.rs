@@ -1,4 +1,9 @@
-use dragon_core::tokenizer::BpeTokenizer;
+use dragon_core::detokenizer::IncrementalDecoder;
+use dragon_core::text_pipeline::{Normalizer, PreTokenizer, TextPipeline};
+use dragon_core::tokenizer::{byte_level_alphabet, BpeTokenizer, TokenSpan, Tokenizer};
+use dragon_core::unigram::UnigramTokenizer;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 #[test]
 fn encode_decode_encode_identity() {
@@ -37,3 +42,115 @@ fn encode_decode_encode_identity() {
     let ids2 = tok.encode(&decoded);
     assert_eq!(ids1, ids2);
 }
+
+// characters drawn from ASCII, whitespace, accented Latin, CJK, emoji and
+// combining marks so multi-byte sequences and odd spacing are covered
+fn random_string(rng: &mut StdRng) -> String {
+    const POOL: &[char] = &[
+        'a', 'b', 'e', 'h', 'l', 'o', 't', 'A', 'Z', '0', '7', ' ', ' ', '\n', '\t', '\r', '\'', '!', '.', ',',
+        '\u{0}', 'é', 'ß', 'ø', 'Ġ', '中', '文', '한', '🙂', '🚀', '\u{301}', '\u{a0}', '\u{2028}',
+    ];
+    let len = rng.gen_range(0..40);
+    (0..len)
+        .map(|_| {
+            if rng.gen_bool(0.1) {
+                // any scalar value
+                loop {
+                    if let Some(c) = char::from_u32(rng.gen_range(0..0x11_0000)) {
+                        break c;
+                    }
+                }
+            } else {
+                POOL[rng.gen_range(0..POOL.len())]
+            }
+        })
+        .collect()
+}
+
+fn byte_level_tokenizer() -> BpeTokenizer {
+    let alphabet = byte_level_alphabet();
+    let merges: Vec<(String, String)> = [("Ġ", "t"), ("h", "e"), ("Ġt", "he"), ("l", "l"), ("Ġ", "Ġ"), ("ä", "¸")]
+        .iter()
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+        .collect();
+    BpeTokenizer::new_byte_level(alphabet, merges)
+}
+
+#[test]
+fn byte_level_roundtrips_random_strings() {
+    let tok = byte_level_tokenizer();
+    let mut rng = StdRng::seed_from_u64(41);
+    for _ in 0..2000 {
+        let text = random_string(&mut rng);
+        let ids = tok.encode(&text);
+        assert_eq!(tok.decode(&ids), text, "ids {:?}", ids);
+    }
+}
+
+#[test]
+fn byte_level_incremental_decode_matches() {
+    let tok = byte_level_tokenizer();
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..500 {
+        let text = random_string(&mut rng);
+        let mut decoder = IncrementalDecoder::new(&tok);
+        let mut streamed: String = tok.encode(&text).into_iter().map(|id| decoder.push(id)).collect();
+        streamed.push_str(&decoder.finish());
+        assert_eq!(streamed, text);
+    }
+}
+
+// spans must be ordered, inside the text, and agree between bytes and chars
+fn check_spans(text: &str, ids: &[usize], spans: &[TokenSpan]) {
+    assert_eq!(spans.iter().map(|s| s.id).collect::<Vec<_>>(), ids, "{:?}", text);
+    let mut prev = 0;
+    for span in spans {
+        assert!(prev <= span.byte_start && span.byte_start <= span.byte_end && span.byte_end <= text.len());
+        prev = span.byte_start;
+        if text.is_char_boundary(span.byte_start) && text.is_char_boundary(span.byte_end) {
+            assert_eq!(text[..span.byte_start].chars().count(), span.char_start);
+            assert_eq!(text[..span.byte_end].chars().count(), span.char_end);
+        }
+    }
+}
+
+#[test]
+fn offsets_match_encoding() {
+    let byte_level = byte_level_tokenizer();
+    let mut words = BpeTokenizer::new(
+        ["<unk>", "h", "e", "l", "o", "he", "ll"].iter().map(|s| s.to_string()).collect(),
+        vec![("h".into(), "e".into()), ("l".into(), "l".into())],
+        0,
+    );
+    words.add_special_token("<eos>");
+    let mut pieces: Vec<(String, f32)> = vec![("<unk>".into(), 0.0), ("▁he".into(), -1.0), ("llo".into(), -1.0)];
+    pieces.extend(["▁", "h", "e", "l", "o", "a", "b"].iter().map(|p| (p.to_string(), -3.0)));
+    pieces.extend((0..=255u8).map(|b| (format!("<0x{:02X}>", b), 0.0)));
+    let unigram = UnigramTokenizer::new(pieces, 0);
+    let mut normalized = byte_level_tokenizer();
+    normalized.set_pipeline(TextPipeline {
+        normalizers: vec![Normalizer::Nfkc, Normalizer::StripAccents, Normalizer::Lowercase],
+        pre_tokenizers: vec![PreTokenizer::Gpt2, PreTokenizer::Digits { individual: true }, PreTokenizer::Punctuation],
+    });
+    let tokenizers: [&dyn Tokenizer; 4] = [&byte_level, &words, &unigram, &normalized];
+
+    let mut rng = StdRng::seed_from_u64(48);
+    for _ in 0..1000 {
+        let mut text = random_string(&mut rng);
+        if rng.gen_bool(0.3) {
+            text.insert_str(0, "hello<eos> ");
+        }
+        for tok in tokenizers {
+            let spans = tok.encode_with_offsets(&text, true);
+            check_spans(&text, &tok.encode(&text), &spans);
+        }
+        // byte-level tokens tile the input exactly
+        let spans = byte_level.encode_with_offsets(&text, true);
+        let mut end = 0;
+        for span in &spans {
+            assert_eq!(span.byte_start, end);
+            end = span.byte_end;
+        }
+        assert_eq!(end, text.len());
+    }
+}
@@ -10,7 +10,7 @@ if (!extension_loaded('FFI')) {
 $header = "
     typedef unsigned long ulong;
     typedef struct ModelHandle ModelHandle;
-    ModelHandle* dragon_model_create(ulong vocab, ulong embed, ulong hidden, ulong layers);
+    ModelHandle* dragon_model_create(ulong vocab, ulong embed, ulong hidden, ulong layers, ulong heads);
     void dragon_model_free(ModelHandle* handle);
     ulong dragon_model_generate_inplace(ModelHandle* handle, ulong* tokens, ulong len, ulong steps);
 ";
@@ -24,7 +24,7 @@ if (empty($tokens)) {
     exit(1);
 }
 
-$handle = $lib->dragon_model_create(VOCAB_SIZE, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS);
+$handle = $lib->dragon_model_create(VOCAB_SIZE, EMBED_DIM, HIDDEN_DIM, NUM_LAYERS, NUM_HEADS);
 
 
 $steps = 2;
@@ -5,4 +5,5 @@ const EMBED_DIM = 4;
 const HIDDEN_DIM = 4;
 const NUM_LAYERS = 1;
 const NUM_HEADS = 1;
+const MAX_CONTEXT = 512;
 const LEARNING_RATE = 0.1;